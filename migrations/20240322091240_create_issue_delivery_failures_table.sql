@@ -0,0 +1,25 @@
+-- Add migration script here
+BEGIN;
+CREATE TABLE issue_delivery_failures (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    last_error TEXT NOT NULL,
+    http_status SMALLINT NULL,
+    n_attempts SMALLINT NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
+-- Move tasks that were already marked as failed out of the queue
+INSERT INTO issue_delivery_failures (
+    newsletter_issue_id,
+    subscriber_email,
+    last_error,
+    n_attempts,
+    failed_at
+)
+SELECT newsletter_issue_id, subscriber_email, 'Unknown error', n_retries + 1, failed_at
+FROM issue_delivery_queue
+WHERE failed_at IS NOT NULL;
+DELETE FROM issue_delivery_queue WHERE failed_at IS NOT NULL;
+ALTER TABLE issue_delivery_queue DROP COLUMN failed_at;
+COMMIT;
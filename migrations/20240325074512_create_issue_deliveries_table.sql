@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE issue_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    status TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    error_message TEXT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX issue_deliveries_newsletter_issue_id_idx ON issue_deliveries (newsletter_issue_id);
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- A scheduled issue has no `published_at` until the scheduler enqueues it
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for TIMESTAMPTZ NULL;
+ALTER TABLE newsletter_issues ADD COLUMN cancelled_at TIMESTAMPTZ NULL;
+CREATE INDEX newsletter_issues_pending_schedule_idx ON newsletter_issues (scheduled_for)
+    WHERE published_at IS NULL AND cancelled_at IS NULL;
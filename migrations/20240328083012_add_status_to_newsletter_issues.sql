@@ -0,0 +1,19 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+UPDATE newsletter_issues i
+SET status = CASE
+    WHEN cancelled_at IS NOT NULL THEN 'cancelled'
+    WHEN published_at IS NULL THEN 'scheduled'
+    WHEN EXISTS (
+        SELECT 1 FROM issue_delivery_queue q
+        WHERE q.newsletter_issue_id = i.newsletter_issue_id
+    ) THEN 'sending'
+    ELSE 'sent'
+END;
+ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'sending', 'sent', 'cancelled'));
+ALTER TABLE newsletter_issues ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+DROP INDEX newsletter_issues_pending_schedule_idx;
+CREATE INDEX newsletter_issues_scheduled_idx ON newsletter_issues (scheduled_for)
+    WHERE status = 'scheduled';
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE newsletter_issue_test_sends (
+    id BIGSERIAL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    recipient TEXT NOT NULL,
+    sent_by uuid NOT NULL REFERENCES users (user_id),
+    provider_message_id TEXT NULL,
+    error_message TEXT NULL,
+    sent_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX newsletter_issue_test_sends_newsletter_issue_id_idx
+    ON newsletter_issue_test_sends (newsletter_issue_id);
@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'sending', 'paused', 'sent', 'cancelled'));
+-- What had happened to the issue's deliveries when it was cancelled mid-send
+ALTER TABLE newsletter_issues ADD COLUMN n_sent_before_cancellation INTEGER NULL;
+ALTER TABLE newsletter_issues ADD COLUMN n_cancelled_deliveries INTEGER NULL;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at TIMESTAMPTZ NULL;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN email_format TEXT NOT NULL DEFAULT 'html';
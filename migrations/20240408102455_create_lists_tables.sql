@@ -0,0 +1,25 @@
+-- Add migration script here
+CREATE TABLE lists (
+    list_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id)
+);
+
+CREATE TABLE list_subscriptions (
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (list_id, subscriber_id)
+);
+
+-- The lists an issue is sent to: none means every confirmed subscriber
+CREATE TABLE newsletter_issue_lists (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id) ON DELETE CASCADE,
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE subscriber_tags (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+-- A saved, named segment definition: see `domain::Segment` for the syntax
+CREATE TABLE segments (
+    segment_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    definition TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (segment_id)
+);
+
+-- The segment an issue is sent to, on top of its lists: none means no further restriction
+ALTER TABLE newsletter_issues ADD COLUMN segment_id uuid
+    REFERENCES segments (segment_id);
@@ -0,0 +1,24 @@
+-- Add migration script here
+CREATE TABLE subscriber_imports (
+    import_id uuid NOT NULL,
+    mode TEXT NOT NULL,
+    n_rows INT NOT NULL,
+    n_imported INT NOT NULL,
+    n_rejected INT NOT NULL,
+    n_duplicates INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (import_id)
+);
+
+-- The rows of an import that did not make it in, for its report
+CREATE TABLE subscriber_import_rejections (
+    import_id uuid NOT NULL
+        REFERENCES subscriber_imports (import_id) ON DELETE CASCADE,
+    line_number INT NOT NULL,
+    email TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- 'invalid', 'duplicate' or 'unsent' (imported, but the confirmation email failed)
+    kind TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    PRIMARY KEY (import_id, line_number)
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Deleting a subscriber takes their confirmation tokens with them
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- What is left of erased subscribers: a hash of their email, which cannot be
+-- turned back into it, and their status when they were erased, so that
+-- suppressed addresses stay suppressed
+CREATE TABLE erased_subscribers (
+    email_hash TEXT NOT NULL,
+    status TEXT NOT NULL,
+    erased_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (email_hash)
+);
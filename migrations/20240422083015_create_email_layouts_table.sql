@@ -0,0 +1,21 @@
+-- Add migration script here
+-- A layout emails are wrapped in: see `email_layout::EmailLayout` for what it can refer to
+CREATE TABLE email_layouts (
+    layout_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    template TEXT NOT NULL,
+    postal_address TEXT NOT NULL,
+    is_default BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (layout_id)
+);
+-- Without a default one, the layout shipped in the binary is used
+CREATE UNIQUE INDEX email_layouts_default_idx ON email_layouts (is_default) WHERE is_default;
+
+-- The layout an issue is wrapped in: none means the default one
+ALTER TABLE newsletter_issues ADD COLUMN layout_id uuid
+    REFERENCES email_layouts (layout_id) ON DELETE SET NULL;
+-- Issues written before layouts existed go out the way they were written
+ALTER TABLE newsletter_issues ADD COLUMN use_layout BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ALTER COLUMN use_layout SET DEFAULT true;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Which links of an issue go through the click tracking redirects
+ALTER TABLE newsletter_issues ADD COLUMN click_tracking TEXT NOT NULL DEFAULT 'off'
+    CHECK (click_tracking IN ('off', 'html', 'html_and_text'));
+CREATE TABLE link_clicks (
+    id BIGSERIAL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    clicked_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX link_clicks_newsletter_issue_id_idx ON link_clicks (newsletter_issue_id);
+CREATE INDEX link_clicks_subscriber_id_idx ON link_clicks (subscriber_id);
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- What signing up again asks for, applied once the address is confirmed
+ALTER TABLE subscription_tokens
+    ADD COLUMN pending_name TEXT NULL,
+    ADD COLUMN pending_list_ids uuid[] NULL,
+    ADD COLUMN pending_tags TEXT[] NULL;
@@ -0,0 +1,280 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use lol_html::errors::RewritingError;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Builds and checks the redirects that stand in for the links of newsletter
+/// issues, to record who follows which link.
+///
+/// Like unsubscribe links, a redirect carries everything it needs: the issue,
+/// the subscriber and the target, along with an HMAC of the three. We only
+/// ever redirect to targets we signed, nobody can use our domain to send
+/// people elsewhere.
+#[derive(Clone)]
+pub struct ClickLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+/// What a click on one of our redirects stands for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrackedClick {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub target: String,
+}
+
+impl ClickLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn link(&self, newsletter_issue_id: Uuid, subscriber_id: Uuid, target: &str) -> String {
+        let mut payload = Vec::with_capacity(32 + target.len());
+        payload.extend_from_slice(newsletter_issue_id.as_bytes());
+        payload.extend_from_slice(subscriber_id.as_bytes());
+        payload.extend_from_slice(target.as_bytes());
+        let tag = self.mac(&payload).finalize().into_bytes();
+        format!(
+            "{}/t/c/{}.{}",
+            self.base_url,
+            URL_SAFE_NO_PAD.encode(&payload),
+            hex::encode(tag)
+        )
+    }
+
+    /// The click behind `token`, `None` unless we issued it.
+    pub fn verify(&self, token: &str) -> Option<TrackedClick> {
+        let (payload, tag) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let tag = hex::decode(tag).ok()?;
+        self.mac(&payload).verify_slice(&tag).ok()?;
+        if payload.len() < 32 {
+            return None;
+        }
+        let (ids, target) = payload.split_at(32);
+        Some(TrackedClick {
+            newsletter_issue_id: Uuid::from_slice(&ids[..16]).ok()?,
+            subscriber_id: Uuid::from_slice(&ids[16..]).ok()?,
+            target: String::from_utf8(target.to_vec()).ok()?,
+        })
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes()).unwrap();
+        // The secret also signs cookies and unsubscribe links: keep redirects apart
+        mac.update(b"click:");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// Point every web link of `html` to `track(target)`, but for the links in
+/// `untracked`.
+pub fn track_html_links(
+    html: &str,
+    untracked: &[&str],
+    track: impl Fn(&str) -> String,
+) -> Result<String, RewritingError> {
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("a[href]", |el| {
+                let href = el.get_attribute("href").unwrap_or_default();
+                // Attribute values come as they were written, references included
+                let target = unescape_html(href.trim());
+                if is_trackable(&target, untracked) {
+                    el.set_attribute("href", &track(&target))?;
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    )
+}
+
+/// Replace every web address of `text` with `track(address)`, but for the
+/// links in `untracked`.
+pub fn track_text_links(text: &str, untracked: &[&str], track: impl Fn(&str) -> String) -> String {
+    let mut tracked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = ["http://", "https://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        tracked.push_str(&rest[..start]);
+        let candidate = &rest[start..];
+        let end = candidate
+            .find(char::is_whitespace)
+            .unwrap_or(candidate.len());
+        // Punctuation right after an address most likely belongs to the sentence
+        let url = candidate[..end].trim_end_matches(|c| {
+            matches!(
+                c,
+                '.' | ',' | ';' | ':' | '!' | '?' | ')' | ']' | '>' | '"' | '\''
+            )
+        });
+        if is_trackable(url, untracked) {
+            tracked.push_str(&track(url));
+        } else {
+            tracked.push_str(url);
+        }
+        rest = &candidate[url.len()..];
+    }
+    tracked.push_str(rest);
+    tracked
+}
+
+/// Anchors, `mailto:` links and the like are left alone.
+fn is_trackable(target: &str, untracked: &[&str]) -> bool {
+    let lowercase = target.to_ascii_lowercase();
+    (lowercase.starts_with("http://") || lowercase.starts_with("https://"))
+        && target.len() > "https://".len()
+        && !untracked.contains(&target)
+}
+
+/// Decode the character references of an attribute value, the way a browser
+/// would before following the link.
+fn unescape_html(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let reference = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                name => {
+                    let code = name.strip_prefix('#')?;
+                    let code = match code.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => code.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end + 1))
+        });
+        match reference {
+            Some((c, len)) => {
+                unescaped.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{track_html_links, track_text_links, ClickLinks, TrackedClick};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn links(secret: &str) -> ClickLinks {
+        ClickLinks::new("http://localhost".into(), Secret::new(secret.into()))
+    }
+
+    fn token_of(link: &str) -> &str {
+        link.strip_prefix("http://localhost/t/c/").unwrap()
+    }
+
+    fn track(target: &str) -> String {
+        format!("[{target}]")
+    }
+
+    #[test]
+    fn links_we_issued_carry_their_click() {
+        let links = links("secret");
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+
+        let link = links.link(issue_id, subscriber_id, "https://example.com/a?b=c&d=e#f");
+
+        assert_eq!(
+            links.verify(token_of(&link)),
+            Some(TrackedClick {
+                newsletter_issue_id: issue_id,
+                subscriber_id,
+                target: "https://example.com/a?b=c&d=e#f".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn links_signed_with_another_secret_are_rejected() {
+        let link =
+            links("another secret").link(Uuid::new_v4(), Uuid::new_v4(), "https://example.com");
+
+        assert_eq!(links("secret").verify(token_of(&link)), None);
+    }
+
+    #[test]
+    fn targets_cannot_be_swapped() {
+        let links = links("secret");
+        let link = links.link(Uuid::new_v4(), Uuid::new_v4(), "https://example.com");
+        let (_, tag) = token_of(&link).split_once('.').unwrap();
+        let other_link = links.link(Uuid::new_v4(), Uuid::new_v4(), "https://evil.example");
+        let (payload, _) = token_of(&other_link).split_once('.').unwrap();
+
+        assert_eq!(links.verify(&format!("{payload}.{tag}")), None);
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let links = links("secret");
+
+        assert_eq!(links.verify(""), None);
+        assert_eq!(links.verify("not-a-token"), None);
+        assert_eq!(links.verify("!!!.abc"), None);
+    }
+
+    #[test]
+    fn web_links_of_the_html_are_tracked() {
+        let html = track_html_links(
+            r##"<a href="https://example.com/?a=1&amp;b=&#x2f;">A</a> <a href="#top">B</a> <a href="mailto:a@example.com">C</a> <a href="https://example.com/unsubscribe">D</a>"##,
+            &["https://example.com/unsubscribe"],
+            track,
+        )
+        .unwrap();
+
+        assert_eq!(
+            html,
+            r##"<a href="[https://example.com/?a=1&b=/]">A</a> <a href="#top">B</a> <a href="mailto:a@example.com">C</a> <a href="https://example.com/unsubscribe">D</a>"##
+        );
+    }
+
+    #[test]
+    fn web_addresses_of_the_text_are_tracked() {
+        let text = track_text_links(
+            "Read https://example.com/a. Or (https://example.com/b), \
+            but not https://example.com/unsubscribe nor http://",
+            &["https://example.com/unsubscribe"],
+            track,
+        );
+
+        assert_eq!(
+            text,
+            "Read [https://example.com/a]. Or ([https://example.com/b]), \
+            but not https://example.com/unsubscribe nor http://"
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use crate::click_links::ClickLinks;
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailSender, PostmarkClient, SmtpClient};
+use crate::unsubscribe_links::UnsubscribeLinks;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
@@ -7,12 +9,15 @@ use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
+use std::sync::Arc;
 #[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub worker: WorkerSettings,
+    pub subscriptions: SubscriptionSettings,
 }
 
 #[derive(Deserialize, Clone)]
@@ -22,6 +27,23 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// How long in-flight requests and deliveries get to finish once we are
+    /// asked to stop.
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+
+    pub fn unsubscribe_links(&self) -> UnsubscribeLinks {
+        UnsubscribeLinks::new(self.base_url.clone(), self.hmac_secret.clone())
+    }
+
+    pub fn click_links(&self) -> ClickLinks {
+        ClickLinks::new(self.base_url.clone(), self.hmac_secret.clone())
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -77,24 +99,74 @@ impl DatabaseSettings {
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransport,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransport {
+    #[default]
+    Postmark,
+    Smtp,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default)]
+    pub auth_mechanism: SmtpAuthMechanism,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain-text connection, only suitable for a relay on the same host.
+    None,
+    /// Upgrade a plain-text connection with `STARTTLS` (usually port 587).
+    StartTls,
+    /// TLS from the first byte (usually port 465).
+    Implicit,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthMechanism {
+    #[default]
+    Plain,
+    Login,
 }
 
 impl EmailClientSettings {
-    pub fn client(self) -> EmailClient {
+    pub fn client(self) -> Arc<dyn EmailSender> {
         let timeout = self.timeout();
 
         let sender_email = self.sender().expect("Invalid sender email address.");
 
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        match self.transport {
+            EmailTransport::Postmark => Arc::new(PostmarkClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailTransport::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The smtp transport requires an `email_client.smtp` section.");
+                Arc::new(SmtpClient::new(smtp, sender_email, timeout))
+            }
+        }
     }
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         self.sender_email.parse()
@@ -103,3 +175,68 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 }
+
+#[derive(Deserialize, Clone)]
+pub struct WorkerSettings {
+    pub max_retries: i16,
+    pub backoff_base_seconds: u64,
+    pub backoff_max_seconds: u64,
+    pub auth_failure_pause_seconds: u64,
+    /// How many queued deliveries are dequeued and sent together.
+    pub batch_size: usize,
+    /// How many delivery loops run side by side.
+    pub concurrency: usize,
+    /// The most messages handed to the email provider per second, across all loops.
+    pub messages_per_second: u32,
+    /// The most messages sent to any single recipient domain per second.
+    pub per_domain_messages_per_second: Option<u32>,
+    /// The Postgres channel enqueuers `NOTIFY` when new deliveries are queued.
+    pub notify_channel: String,
+    /// How long an idle worker waits before checking the queue anyway, in case
+    /// it missed a notification.
+    pub poll_interval_seconds: u64,
+    /// The longest the scheduler sleeps before checking for issues that are
+    /// due, in case one was scheduled while it was asleep.
+    pub scheduler_interval_seconds: u64,
+}
+
+impl WorkerSettings {
+    pub fn backoff_base(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.backoff_base_seconds)
+    }
+    pub fn backoff_max(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.backoff_max_seconds)
+    }
+    pub fn auth_failure_pause(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.auth_failure_pause_seconds)
+    }
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+    pub fn scheduler_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.scheduler_interval_seconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    /// How long a confirmation link keeps working after it was emailed.
+    pub confirmation_token_ttl_hours: u64,
+    /// Subscribers who never confirm are deleted, along with their tokens,
+    /// this many days after they were last sent a confirmation link.
+    pub pending_retention_days: u64,
+    /// How often stale pending subscribers are looked for.
+    pub cleanup_interval_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.confirmation_token_ttl_hours as i64)
+    }
+    pub fn pending_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.pending_retention_days as i64)
+    }
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+}
@@ -0,0 +1,234 @@
+use lol_html::errors::RewritingError;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{element, rewrite_str, text, ElementContentHandlers, HandlerResult};
+use lol_html::{RewriteStrSettings, Selector};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+/// Where the `style` attribute of an element is kept while the rules of the
+/// style sheets are added: its own declarations go last, to win over them.
+const OWN_STYLE: &str = "data-own-style";
+
+/// Move the rules of the `<style>` elements of a document into the `style`
+/// attributes of the elements they match, for the email clients that ignore
+/// style sheets. Rules apply in the order they are written, whatever the
+/// specificity of their selectors, and before the element's own declarations.
+/// At-rules, such as media queries, and rules that cannot be inlined, such as
+/// `a:hover`, stay in their style sheet.
+pub fn inline_css(html: &str) -> Result<String, RewritingError> {
+    let style_sheets = collect_style_sheets(html)?;
+    if style_sheets.is_empty() {
+        return Ok(html.to_owned());
+    }
+    let mut rules = Vec::new();
+    let mut kept_css: Vec<String> = style_sheets
+        .iter()
+        .map(|css| split_style_sheet(css, &mut rules))
+        .collect();
+    kept_css.reverse();
+
+    let mut element_content_handlers = vec![
+        element!("style", move |el| {
+            match kept_css.pop() {
+                Some(css) if !css.is_empty() => el.set_inner_content(&css, ContentType::Html),
+                _ => el.remove(),
+            }
+            Ok(())
+        }),
+        element!("[style]", |el| {
+            let own_style = el.get_attribute("style").unwrap_or_default();
+            el.remove_attribute("style");
+            el.set_attribute(OWN_STYLE, &own_style)?;
+            Ok(())
+        }),
+    ];
+    for (selector, declarations) in rules {
+        element_content_handlers.push((
+            Cow::Owned(selector),
+            ElementContentHandlers::default()
+                .element(move |el: &mut Element<'_, '_>| add_declarations(el, &declarations)),
+        ));
+    }
+    // Selectors match the element as it was written: these are the same ones
+    element_content_handlers.push(element!("[style]", |el| {
+        let own_style = el.get_attribute(OWN_STYLE).unwrap_or_default();
+        el.remove_attribute(OWN_STYLE);
+        add_declarations(el, &own_style)
+    }));
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers,
+            ..RewriteStrSettings::new()
+        },
+    )
+}
+
+/// The content of every `<style>` element of the document, in order.
+fn collect_style_sheets(html: &str) -> Result<Vec<String>, RewritingError> {
+    let style_sheets = RefCell::new(Vec::new());
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("style", |_| {
+                    style_sheets.borrow_mut().push(String::new());
+                    Ok(())
+                }),
+                text!("style", |chunk| {
+                    if let Some(css) = style_sheets.borrow_mut().last_mut() {
+                        css.push_str(chunk.as_str());
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+    Ok(style_sheets.into_inner())
+}
+
+/// Add the rules of a style sheet that can be inlined to `rules`, one per
+/// selector, and return the rest of it.
+fn split_style_sheet(css: &str, rules: &mut Vec<(Selector, String)>) -> String {
+    let css = strip_comments(css);
+    let mut kept = Vec::new();
+    let mut rest = css.trim();
+    while !rest.is_empty() {
+        let Some(open) = rest.find('{') else {
+            kept.push(rest);
+            break;
+        };
+        // Statements such as `@import url(...);` have no block
+        if rest.starts_with('@') {
+            if let Some(end) = rest[..open].find(';') {
+                kept.push(&rest[..=end]);
+                rest = rest[end + 1..].trim_start();
+                continue;
+            }
+        }
+        let Some(end) = block_end(rest, open) else {
+            kept.push(rest);
+            break;
+        };
+        let prelude = rest[..open].trim();
+        let selectors: Option<Vec<Selector>> = if prelude.starts_with('@') {
+            None
+        } else {
+            prelude.split(',').map(|s| s.trim().parse().ok()).collect()
+        };
+        match selectors {
+            Some(selectors) => {
+                let declarations = normalize_declarations(&rest[open + 1..end]);
+                for selector in selectors {
+                    rules.push((selector, declarations.clone()));
+                }
+            }
+            None => kept.push(&rest[..=end]),
+        }
+        rest = rest[end + 1..].trim_start();
+    }
+    kept.join("\n")
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(length) => &rest[start + 2 + length + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Where the block opening at `open` closes, nested blocks included.
+fn block_end(css: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in css[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn normalize_declarations(declarations: &str) -> String {
+    declarations
+        .split(';')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn add_declarations(el: &mut Element<'_, '_>, declarations: &str) -> HandlerResult {
+    let declarations = normalize_declarations(declarations);
+    if declarations.is_empty() {
+        return Ok(());
+    }
+    let style = match el.get_attribute("style") {
+        Some(style) if !style.trim().is_empty() => format!("{}; {declarations}", style.trim()),
+        _ => declarations,
+    };
+    el.set_attribute("style", &style)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inline_css;
+
+    #[test]
+    fn rules_are_inlined_in_order_before_the_own_declarations_of_elements() {
+        let html = inline_css(
+            r#"<style>
+                p { color: red; margin: 0 }
+                /* Notes stand out */
+                .note, h1 { color: blue; }
+            </style><h1>Title</h1><p class="note" style="font-weight: bold;">Hi</p><p>There</p>"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            html,
+            r#"<h1 style="color: blue">Title</h1><p class="note" style="color: red; margin: 0; color: blue; font-weight: bold">Hi</p><p style="color: red; margin: 0">There</p>"#
+        );
+    }
+
+    #[test]
+    fn rules_that_cannot_be_inlined_stay_in_the_style_sheet() {
+        let html = inline_css(
+            "<style>@import url(\"fonts.css\");\
+            a { color: red }\
+            a:hover { color: blue }\
+            @media (max-width: 600px) { a { color: green } }</style><a href=\"#\">Link</a>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            html,
+            "<style>@import url(\"fonts.css\");\n\
+            a:hover { color: blue }\n\
+            @media (max-width: 600px) { a { color: green } }</style>\
+            <a href=\"#\" style=\"color: red\">Link</a>"
+        );
+    }
+
+    #[test]
+    fn documents_without_style_sheets_are_left_alone() {
+        let html = r#"<p style="color: red">Hi &amp; welcome</p>"#;
+
+        assert_eq!(inline_css(html).unwrap(), html);
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which links of an issue go through our click tracking redirects.
+///
+/// Tracked links are long and opaque, which readers of the plain text version
+/// get to see: it has its own setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClickTracking {
+    #[default]
+    Off,
+    Html,
+    HtmlAndText,
+}
+
+impl ClickTracking {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClickTracking::Off => "off",
+            ClickTracking::Html => "html",
+            ClickTracking::HtmlAndText => "html_and_text",
+        }
+    }
+
+    pub fn tracks_html(&self) -> bool {
+        !matches!(self, ClickTracking::Off)
+    }
+
+    pub fn tracks_text(&self) -> bool {
+        matches!(self, ClickTracking::HtmlAndText)
+    }
+}
+
+impl FromStr for ClickTracking {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ClickTracking::Off),
+            "html" => Ok(ClickTracking::Html),
+            "html_and_text" => Ok(ClickTracking::HtmlAndText),
+            other => Err(format!("{} is not a valid click tracking setting.", other)),
+        }
+    }
+}
+
+impl fmt::Display for ClickTracking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClickTracking;
+
+    #[test]
+    fn every_setting_round_trips_through_its_database_representation() {
+        for setting in [
+            ClickTracking::Off,
+            ClickTracking::Html,
+            ClickTracking::HtmlAndText,
+        ] {
+            assert_eq!(setting.as_str().parse::<ClickTracking>(), Ok(setting));
+        }
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!("text".parse::<ClickTracking>().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which versions of our emails a subscriber wants to receive.
+///
+/// Everybody gets both the HTML and plain text versions by default, mail
+/// clients pick the one they display. `PlainText` subscribers are only sent the
+/// latter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailFormat {
+    #[default]
+    Html,
+    PlainText,
+}
+
+impl EmailFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailFormat::Html => "html",
+            EmailFormat::PlainText => "plain_text",
+        }
+    }
+}
+
+impl FromStr for EmailFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(EmailFormat::Html),
+            "plain_text" => Ok(EmailFormat::PlainText),
+            other => Err(format!("{} is not a valid email format.", other)),
+        }
+    }
+}
+
+impl fmt::Display for EmailFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailFormat;
+
+    #[test]
+    fn every_format_round_trips_through_its_database_representation() {
+        for format in [EmailFormat::Html, EmailFormat::PlainText] {
+            assert_eq!(format.as_str().parse::<EmailFormat>(), Ok(format));
+        }
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        assert!("markdown".parse::<EmailFormat>().is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How the subscribers of an import join the newsletter.
+///
+/// Addresses coming from another provider have usually gone through double
+/// opt-in already, so admins choose per import whether they are `Confirmed`
+/// straight away or sent a confirmation email like any new subscriber.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    DoubleOptIn,
+    Confirmed,
+}
+
+impl ImportMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportMode::DoubleOptIn => "double_opt_in",
+            ImportMode::Confirmed => "confirmed",
+        }
+    }
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "double_opt_in" => Ok(ImportMode::DoubleOptIn),
+            "confirmed" => Ok(ImportMode::Confirmed),
+            other => Err(format!("{} is not a valid import mode.", other)),
+        }
+    }
+}
+
+impl fmt::Display for ImportMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImportMode;
+
+    #[test]
+    fn every_mode_round_trips_through_its_database_representation() {
+        for mode in [ImportMode::DoubleOptIn, ImportMode::Confirmed] {
+            assert_eq!(mode.as_str().parse::<ImportMode>(), Ok(mode));
+        }
+    }
+
+    #[test]
+    fn unknown_modes_are_rejected() {
+        assert!("pending".parse::<ImportMode>().is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a newsletter issue is in its lifecycle.
+///
+/// A draft can be edited freely until it is published: it then goes out right
+/// away (`Sending`) or waits for its send time (`Scheduled`). Delivery can be
+/// `Paused` and resumed, and an issue is `Sent` once its delivery queue is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    Sending,
+    Paused,
+    Sent,
+    Cancelled,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Sending => "sending",
+            IssueStatus::Paused => "paused",
+            IssueStatus::Sent => "sent",
+            IssueStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for IssueStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(IssueStatus::Draft),
+            "scheduled" => Ok(IssueStatus::Scheduled),
+            "sending" => Ok(IssueStatus::Sending),
+            "paused" => Ok(IssueStatus::Paused),
+            "sent" => Ok(IssueStatus::Sent),
+            "cancelled" => Ok(IssueStatus::Cancelled),
+            other => Err(format!("{} is not a valid issue status.", other)),
+        }
+    }
+}
+
+impl fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueStatus;
+
+    #[test]
+    fn every_status_round_trips_through_its_database_representation() {
+        for status in [
+            IssueStatus::Draft,
+            IssueStatus::Scheduled,
+            IssueStatus::Sending,
+            IssueStatus::Paused,
+            IssueStatus::Sent,
+            IssueStatus::Cancelled,
+        ] {
+            assert_eq!(status.as_str().parse::<IssueStatus>(), Ok(status));
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_are_rejected() {
+        assert!("published".parse::<IssueStatus>().is_err());
+    }
+}
@@ -1,7 +1,21 @@
+mod click_tracking;
+mod email_format;
+mod import_mode;
+mod issue_status;
 mod new_subscribers;
+mod segment;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod subscription_status;
 
+pub use click_tracking::ClickTracking;
+pub use email_format::EmailFormat;
+pub use import_mode::ImportMode;
+pub use issue_status::IssueStatus;
 pub use new_subscribers::NewSubscriber;
+pub use segment::Segment;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use subscription_status::SubscriptionStatus;
@@ -0,0 +1,266 @@
+use super::SubscriberTag;
+use chrono::NaiveDate;
+use std::str::FromStr;
+
+/// Which subscribers an issue is sent to, as a boolean combination of
+/// conditions on their tags, lists and signup date:
+///
+/// ```text
+/// tag:vip and (list:"Release notes" or not signed_up_before:2024-01-01)
+/// ```
+///
+/// `not` binds tighter than `and`, which binds tighter than `or`. Dates are
+/// UTC days: `signed_up_since:2024-01-01` includes the whole of January 1st.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Tag(SubscriberTag),
+    List(String),
+    SignedUpBefore(NaiveDate),
+    SignedUpSince(NaiveDate),
+    Not(Box<Segment>),
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+}
+
+impl FromStr for Segment {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let segment = parser.or()?;
+        match parser.next() {
+            None => Ok(segment),
+            Some(token) => Err(format!("Unexpected {token} in the segment definition.")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    OpenParen,
+    CloseParen,
+    And,
+    Or,
+    Not,
+    Condition { field: String, value: String },
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::OpenParen => f.write_str("`(`"),
+            Token::CloseParen => f.write_str("`)`"),
+            Token::And => f.write_str("`and`"),
+            Token::Or => f.write_str("`or`"),
+            Token::Not => f.write_str("`not`"),
+            Token::Condition { field, value } => write!(f, "`{field}:{value}`"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::OpenParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::CloseParen);
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if chars.peek() != Some(&':') {
+                    tokens.push(match word.to_lowercase().as_str() {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "not" => Token::Not,
+                        _ => return Err(format!("Unknown keyword `{word}`.")),
+                    });
+                    continue;
+                }
+                chars.next();
+                let mut value = String::new();
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => value.push(c),
+                            None => return Err("Unterminated quoted value.".into()),
+                        }
+                    }
+                } else {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || c == '(' || c == ')' {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                }
+                tokens.push(Token::Condition { field: word, value });
+            }
+            c => return Err(format!("Unexpected character `{c}`.")),
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, one method per precedence level.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            segment = Segment::Or(Box::new(segment), Box::new(self.and()?));
+        }
+        Ok(segment)
+    }
+
+    fn and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            segment = Segment::And(Box::new(segment), Box::new(self.not()?));
+        }
+        Ok(segment)
+    }
+
+    fn not(&mut self) -> Result<Segment, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Segment::Not(Box::new(self.not()?)));
+        }
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Segment, String> {
+        match self.next() {
+            Some(Token::OpenParen) => {
+                let segment = self.or()?;
+                match self.next() {
+                    Some(Token::CloseParen) => Ok(segment),
+                    _ => Err("Missing a closing parenthesis.".into()),
+                }
+            }
+            Some(Token::Condition { field, value }) => match field.to_lowercase().as_str() {
+                "tag" => Ok(Segment::Tag(value.parse()?)),
+                "list" if !value.trim().is_empty() => Ok(Segment::List(value)),
+                "list" => Err("`list:` needs the name of a list.".into()),
+                "signed_up_before" => Ok(Segment::SignedUpBefore(parse_date(&value)?)),
+                "signed_up_since" => Ok(Segment::SignedUpSince(parse_date(&value)?)),
+                _ => Err(format!("Unknown condition `{field}:`.")),
+            },
+            Some(token) => Err(format!("Expected a condition, found {token}.")),
+            None => Err("Expected a condition, found the end of the definition.".into()),
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("{value} is not a date: use the YYYY-MM-DD format."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Segment;
+    use chrono::NaiveDate;
+
+    fn tag(t: &str) -> Segment {
+        Segment::Tag(t.parse().unwrap())
+    }
+
+    #[test]
+    fn a_single_condition_is_a_segment() {
+        assert_eq!("tag:vip".parse(), Ok(tag("vip")));
+        assert_eq!(
+            r#"list:"Release notes""#.parse(),
+            Ok(Segment::List("Release notes".into()))
+        );
+        assert_eq!(
+            "signed_up_since:2024-01-31".parse(),
+            Ok(Segment::SignedUpSince(
+                NaiveDate::from_ymd_opt(2024, 1, 31).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            "tag:a or tag:b AND not tag:c".parse(),
+            Ok(Segment::Or(
+                Box::new(tag("a")),
+                Box::new(Segment::And(
+                    Box::new(tag("b")),
+                    Box::new(Segment::Not(Box::new(tag("c"))))
+                ))
+            ))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_conditions() {
+        assert_eq!(
+            "(tag:a or tag:b) and tag:c".parse(),
+            Ok(Segment::And(
+                Box::new(Segment::Or(Box::new(tag("a")), Box::new(tag("b")))),
+                Box::new(tag("c"))
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        for definition in [
+            "",
+            "tag:",
+            "tag:a and",
+            "tag:a tag:b",
+            "(tag:a",
+            "tag:a)",
+            "color:blue",
+            "list:",
+            "signed_up_before:yesterday",
+            r#"list:"Release notes"#,
+            "tag:a & tag:b",
+        ] {
+            assert!(
+                definition.parse::<Segment>().is_err(),
+                "{definition} should be rejected"
+            );
+        }
+    }
+}
@@ -14,6 +14,13 @@ impl FromStr for SubscriberEmail {
     }
 }
 
+impl SubscriberEmail {
+    /// The part after the `@`, e.g. `gmail.com`.
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
+}
+
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
         &self.0
@@ -59,4 +66,10 @@ mod tests {
         let email = "@domain.com";
         assert!(email.parse::<SubscriberEmail>().is_err());
     }
+
+    #[test]
+    fn the_domain_is_the_part_after_the_at_symbol() {
+        let email: SubscriberEmail = "ursula@Gmail.com".parse().unwrap();
+        assert_eq!(email.domain(), "Gmail.com");
+    }
 }
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A label put on subscribers to target them: lowercase letters, digits,
+/// `-` and `_`, up to 64 characters.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    /// Parse a comma separated list of tags, ignoring blanks and duplicates.
+    pub fn parse_list(s: &str) -> Result<Vec<SubscriberTag>, String> {
+        let mut tags = s
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<SubscriberTag>, _>>()?;
+        tags.sort();
+        tags.dedup();
+        Ok(tags)
+    }
+}
+
+impl FromStr for SubscriberTag {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tag = s.trim().to_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= 64
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if is_valid {
+            Ok(SubscriberTag(tag))
+        } else {
+            Err(format!("{} is not a valid tag.", s))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SubscriberTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberTag;
+
+    #[test]
+    fn tags_are_lowercased() {
+        let tag: SubscriberTag = " Early-Adopter ".parse().unwrap();
+        assert_eq!(tag.as_ref(), "early-adopter");
+    }
+
+    #[test]
+    fn tags_with_spaces_or_punctuation_are_rejected() {
+        for tag in ["", "early adopter", "vip!", "a:b", &"a".repeat(65)] {
+            assert!(tag.parse::<SubscriberTag>().is_err(), "{tag}");
+        }
+    }
+
+    #[test]
+    fn tag_lists_are_deduplicated() {
+        let tags = SubscriberTag::parse_list("vip, podcast,,VIP").unwrap();
+        let tags: Vec<_> = tags.iter().map(AsRef::as_ref).collect();
+        assert_eq!(tags, vec!["podcast", "vip"]);
+    }
+}
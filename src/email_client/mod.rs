@@ -0,0 +1,136 @@
+mod postmark;
+mod smtp;
+
+pub use postmark::PostmarkClient;
+pub use smtp::SmtpClient;
+
+use crate::domain::SubscriberEmail;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+/// The outcome of sending one message: the id the provider assigned to it, if
+/// it reported one.
+pub type SendEmailResult = Result<Option<String>, SendEmailError>;
+
+/// One message, on its own or as part of a batch.
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    /// `None` sends the plain text version on its own.
+    pub html_body: Option<&'a str>,
+    pub text_body: &'a str,
+    /// Advertised through the `List-Unsubscribe` headers, along with support
+    /// for one-click unsubscription (RFC 8058).
+    pub list_unsubscribe: Option<&'a str>,
+}
+
+/// The headers that let mailbox providers offer a one-click unsubscribe button.
+/// See https://www.rfc-editor.org/rfc/rfc8058
+pub(crate) fn list_unsubscribe_headers(url: &str) -> [(&'static str, String); 2] {
+    [
+        ("List-Unsubscribe", format!("<{url}>")),
+        (
+            "List-Unsubscribe-Post",
+            "List-Unsubscribe=One-Click".to_string(),
+        ),
+    ]
+}
+
+/// A transport able to deliver emails on our behalf.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> SendEmailResult;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+    ) -> SendEmailResult {
+        self.send(&OutgoingEmail {
+            recipient,
+            subject,
+            html_body: Some(html_body),
+            text_body,
+            list_unsubscribe: None,
+        })
+        .await
+    }
+
+    /// The largest batch `send_batch` accepts.
+    fn max_batch_size(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Send several emails at once, returning one result per email, in order.
+    /// An outer error means that none of them were sent.
+    ///
+    /// Transports without a batch API send them one at a time.
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<SendEmailResult>, SendEmailError> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(self.send(email).await);
+        }
+        Ok(results)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendEmailError {
+    #[error("Timed out waiting for the email provider.")]
+    Timeout(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to reach the email provider.")]
+    Connection(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("The email provider is rate limiting us.")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("The email provider rejected the recipient ({error_code}): {message}")]
+    RejectedRecipient {
+        status: Option<StatusCode>,
+        error_code: i64,
+        message: String,
+    },
+    #[error("The email provider refused to send on our behalf: {message}")]
+    AuthFailure {
+        status: Option<StatusCode>,
+        message: String,
+    },
+    #[error("The email provider failed to process the request: {message}")]
+    ProviderError {
+        status: Option<StatusCode>,
+        message: String,
+    },
+    #[error("The email provider rejected the request as malformed: {message}")]
+    MalformedRequest {
+        status: Option<StatusCode>,
+        message: String,
+    },
+}
+
+impl SendEmailError {
+    /// Whether sending the same message again later might succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            SendEmailError::Timeout(_)
+                | SendEmailError::Connection(_)
+                | SendEmailError::RateLimited { .. }
+                | SendEmailError::ProviderError { .. }
+        )
+    }
+
+    /// The HTTP status returned by the provider, for transports that speak HTTP.
+    pub fn http_status(&self) -> Option<StatusCode> {
+        match self {
+            SendEmailError::Timeout(_) | SendEmailError::Connection(_) => None,
+            SendEmailError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            SendEmailError::RejectedRecipient { status, .. }
+            | SendEmailError::AuthFailure { status, .. }
+            | SendEmailError::ProviderError { status, .. }
+            | SendEmailError::MalformedRequest { status, .. } => *status,
+        }
+    }
+}
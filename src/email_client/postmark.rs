@@ -0,0 +1,646 @@
+use super::{
+    list_unsubscribe_headers, EmailSender, OutgoingEmail, SendEmailError, SendEmailResult,
+};
+use crate::domain::SubscriberEmail;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Response, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Postmark refuses batches with more messages than this.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Sends emails through Postmark's HTTP API.
+pub struct PostmarkClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendMailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html_body: Option<&'a str>,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<MessageHeader>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageHeader {
+    name: &'static str,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct SendMailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+/// The outcome of one message of a batch, in the same order as the request.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResponse {
+    error_code: i64,
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    error_code: i64,
+    message: String,
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+
+    fn request_body<'a>(&'a self, email: &OutgoingEmail<'a>) -> SendMailRequest<'a> {
+        let headers = email
+            .list_unsubscribe
+            .map(list_unsubscribe_headers)
+            .into_iter()
+            .flatten()
+            .map(|(name, value)| MessageHeader { name, value })
+            .collect();
+        SendMailRequest {
+            from: self.sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkClient {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> SendEmailResult {
+        // --snip--
+        let url = format!("{}/email", self.base_url);
+        let request_body = self.request_body(email);
+        let response = self
+            .http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(from_transport_error)?;
+        if !response.status().is_success() {
+            return Err(from_error_response(response).await);
+        }
+        let message_id = response
+            .json::<SendMailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id);
+        Ok(message_id)
+    }
+
+    fn max_batch_size(&self) -> usize {
+        MAX_BATCH_SIZE
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<SendEmailResult>, SendEmailError> {
+        if emails.len() > MAX_BATCH_SIZE {
+            return Err(SendEmailError::MalformedRequest {
+                status: None,
+                message: format!(
+                    "A batch cannot hold more than {MAX_BATCH_SIZE} messages, got {}.",
+                    emails.len()
+                ),
+            });
+        }
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = emails
+            .iter()
+            .map(|email| self.request_body(email))
+            .collect();
+        let response = self
+            .http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(from_transport_error)?;
+        if !response.status().is_success() {
+            return Err(from_error_response(response).await);
+        }
+        match response.json::<Vec<BatchMessageResponse>>().await {
+            Ok(results) if results.len() == emails.len() => Ok(results
+                .into_iter()
+                .map(|r| match r.error_code {
+                    0 => Ok(r.message_id),
+                    error_code => Err(from_error_code(None, Some(error_code), r.message)),
+                })
+                .collect()),
+            // Postmark accepted the batch, we just can't tell the messages apart
+            _ => {
+                tracing::warn!("Could not parse the per-message results of a Postmark batch.");
+                Ok(emails.iter().map(|_| Ok(None)).collect())
+            }
+        }
+    }
+}
+
+fn from_transport_error(e: reqwest::Error) -> SendEmailError {
+    if e.is_timeout() {
+        SendEmailError::Timeout(Box::new(e))
+    } else {
+        SendEmailError::Connection(Box::new(e))
+    }
+}
+
+/// Classify a non-2xx response using its status and Postmark's `ErrorCode`.
+/// See https://postmarkapp.com/developer/api/overview#error-codes
+async fn from_error_response(response: Response) -> SendEmailError {
+    let http_status = response.status();
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs);
+    let body = response.text().await.unwrap_or_default();
+    let (error_code, message) = match serde_json::from_str::<ErrorResponse>(&body) {
+        Ok(e) => (Some(e.error_code), e.message),
+        Err(_) if body.is_empty() => (None, http_status.to_string()),
+        Err(_) => (None, body),
+    };
+    if http_status == StatusCode::TOO_MANY_REQUESTS {
+        return SendEmailError::RateLimited { retry_after };
+    }
+    from_error_code(Some(http_status), error_code, message)
+}
+
+/// Classify an error using Postmark's `ErrorCode` and, when the whole request
+/// failed, its HTTP status.
+fn from_error_code(
+    status: Option<StatusCode>,
+    error_code: Option<i64>,
+    message: String,
+) -> SendEmailError {
+    match (status, error_code) {
+        (Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN), _) => {
+            SendEmailError::AuthFailure { status, message }
+        }
+        // Bad or missing server token
+        (_, Some(10)) => SendEmailError::AuthFailure { status, message },
+        // Sender signature missing/unconfirmed, or the account cannot send
+        (_, Some(400 | 401 | 405 | 412 | 413)) => SendEmailError::AuthFailure { status, message },
+        // Inactive recipient (hard bounce, spam complaint, manual suppression)
+        (_, Some(406)) => SendEmailError::RejectedRecipient {
+            status,
+            error_code: 406,
+            message,
+        },
+        (Some(s), _) if s.is_server_error() => SendEmailError::ProviderError { status, message },
+        _ => SendEmailError::MalformedRequest { status, message },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, OutgoingEmail, PostmarkClient, SendEmailError};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn recipient() -> SubscriberEmail {
+        SafeEmail().fake::<String>().parse().unwrap()
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn sender() -> SubscriberEmail {
+        SafeEmail().fake::<String>().parse().unwrap()
+    }
+
+    fn email_client(base_url: String) -> PostmarkClient {
+        PostmarkClient::new(
+            base_url,
+            sender(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url() {
+        let mock_server = MockServer::start().await;
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let _ = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await;
+    }
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let outcome = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await;
+        assert!(outcome.is_ok());
+    }
+    #[tokio::test]
+    async fn send_email_returns_the_provider_message_id() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "SubmittedAt": "2024-03-20T09:15:00.0000000-04:00",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "ErrorCode": 0,
+                "Message": "OK"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let outcome = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await;
+        assert_eq!(
+            outcome.unwrap().as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let outcome = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await;
+        assert!(outcome.is_err());
+    }
+
+    async fn send_email_with_response(response: ResponseTemplate) -> SendEmailError {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .expect_err("Expected the request to fail.")
+    }
+
+    fn postmark_error(status: u16, error_code: i64) -> ResponseTemplate {
+        ResponseTemplate::new(status).set_body_json(serde_json::json!({
+            "ErrorCode": error_code,
+            "Message": "Something went wrong"
+        }))
+    }
+
+    #[tokio::test]
+    async fn inactive_recipients_are_rejected_permanently() {
+        let e = send_email_with_response(postmark_error(422, 406)).await;
+        assert!(matches!(
+            e,
+            SendEmailError::RejectedRecipient {
+                error_code: 406,
+                ..
+            }
+        ));
+        assert!(!e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_bad_server_token_is_an_auth_failure() {
+        let e = send_email_with_response(postmark_error(401, 10)).await;
+        assert!(matches!(e, SendEmailError::AuthFailure { .. }));
+        assert!(!e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn an_unconfirmed_sender_signature_is_an_auth_failure() {
+        let e = send_email_with_response(postmark_error(422, 401)).await;
+        assert!(matches!(e, SendEmailError::AuthFailure { .. }));
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_malformed() {
+        let e = send_email_with_response(postmark_error(422, 300)).await;
+        assert!(matches!(e, SendEmailError::MalformedRequest { .. }));
+        assert!(!e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn rate_limits_carry_the_retry_after_delay() {
+        let e =
+            send_email_with_response(ResponseTemplate::new(429).insert_header("Retry-After", "42"))
+                .await;
+        assert!(matches!(
+            e,
+            SendEmailError::RateLimited {
+                retry_after: Some(d)
+            } if d == std::time::Duration::from_secs(42)
+        ));
+        assert!(e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retryable() {
+        let e = send_email_with_response(ResponseTemplate::new(503)).await;
+        assert!(matches!(e, SendEmailError::ProviderError { .. }));
+        assert!(e.is_retryable());
+        assert_eq!(e.http_status().map(|s| s.as_u16()), Some(503));
+    }
+
+    struct SendBatchBodyMatcher {
+        n_messages: usize,
+    }
+
+    impl wiremock::Match for SendBatchBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<Vec<serde_json::Value>, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.len() == self.n_messages
+                    && body.iter().all(|message| {
+                        message.get("From").is_some()
+                            && message.get("To").is_some()
+                            && message.get("Subject").is_some()
+                            && message.get("HtmlBody").is_some()
+                            && message.get("TextBody").is_some()
+                    })
+            } else {
+                false
+            }
+        }
+    }
+
+    fn batch_results(error_codes: &[i64]) -> ResponseTemplate {
+        let results: Vec<_> = error_codes
+            .iter()
+            .map(|&error_code| match error_code {
+                0 => serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": uuid::Uuid::new_v4().to_string()
+                }),
+                error_code => serde_json::json!({
+                    "ErrorCode": error_code,
+                    "Message": "Something went wrong"
+                }),
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    async fn send_batch_of(
+        email_client: &PostmarkClient,
+        n_messages: usize,
+    ) -> Result<Vec<Result<Option<String>, SendEmailError>>, SendEmailError> {
+        let recipients: Vec<_> = (0..n_messages).map(|_| recipient()).collect();
+        let (subject, html, text) = (subject(), content(), content());
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: &subject,
+                html_body: Some(&html),
+                text_body: &text,
+                list_unsubscribe: None,
+            })
+            .collect();
+        email_client.send_batch(&emails).await
+    }
+
+    #[tokio::test]
+    async fn send_batch_fires_a_single_request_to_the_batch_endpoint() {
+        let mock_server = MockServer::start().await;
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .and(SendBatchBodyMatcher { n_messages: 3 })
+            .respond_with(batch_results(&[0, 0, 0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+
+        let results = send_batch_of(&email_client, 3).await.unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| matches!(r, Ok(Some(_)))));
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_message() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .respond_with(batch_results(&[0, 406, 300, 0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+
+        let results = send_batch_of(&email_client, 4).await.unwrap();
+
+        assert!(matches!(results[0], Ok(Some(_))));
+        assert!(matches!(
+            results[1],
+            Err(SendEmailError::RejectedRecipient {
+                error_code: 406,
+                ..
+            })
+        ));
+        assert!(matches!(
+            results[2],
+            Err(SendEmailError::MalformedRequest { .. })
+        ));
+        assert!(matches!(results[3], Ok(Some(_))));
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_request_fails_the_whole_batch() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+
+        let e = send_batch_of(&email_client, 2)
+            .await
+            .expect_err("Expected the batch to fail.");
+
+        assert!(matches!(e, SendEmailError::ProviderError { .. }));
+        assert!(e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn batches_larger_than_postmark_allows_are_refused() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+
+        let outcome = send_batch_of(&email_client, email_client.max_batch_size() + 1).await;
+
+        assert!(matches!(
+            outcome,
+            Err(SendEmailError::MalformedRequest { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_unsubscribe_headers_are_passed_to_postmark() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [
+                    { "Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>" },
+                    { "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" },
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let (recipient, subject, content) = (recipient(), subject(), content());
+
+        let outcome = email_client
+            .send(&OutgoingEmail {
+                recipient: &recipient,
+                subject: &subject,
+                html_body: Some(&content),
+                text_body: &content,
+                list_unsubscribe: Some("https://example.com/unsubscribe"),
+            })
+            .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn plain_text_only_emails_have_no_html_body() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let (recipient, subject, content) = (recipient(), subject(), content());
+
+        email_client
+            .send(&OutgoingEmail {
+                recipient: &recipient,
+                subject: &subject,
+                html_body: None,
+                text_body: &content,
+                list_unsubscribe: None,
+            })
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body.get("HtmlBody").is_none());
+        assert_eq!(body["TextBody"], content);
+    }
+
+    #[tokio::test]
+    async fn send_email_timeout_if_the_server_takes_too_long_to_respond() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email_client = email_client(mock_server.uri());
+        let outcome = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await;
+        assert!(matches!(outcome, Err(SendEmailError::Timeout(_))));
+    }
+}
@@ -0,0 +1,569 @@
+use super::{
+    list_unsubscribe_headers, EmailSender, OutgoingEmail, SendEmailError, SendEmailResult,
+};
+use crate::config::{SmtpAuthMechanism, SmtpSettings, SmtpTls};
+use crate::domain::SubscriberEmail;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::ExposeSecret;
+use std::time::Duration;
+
+/// Sends emails through an SMTP relay (e.g. a local Postfix instance).
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpClient {
+    pub fn new(settings: SmtpSettings, sender: SubscriberEmail, timeout: Duration) -> Self {
+        let builder = match settings.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            }
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)
+                    .expect("Failed to configure STARTTLS for the SMTP relay.")
+            }
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)
+                .expect("Failed to configure TLS for the SMTP relay."),
+        };
+        let mut builder = builder.port(settings.port).timeout(Some(timeout));
+        if let (Some(username), Some(password)) = (settings.username, settings.password) {
+            let mechanism = match settings.auth_mechanism {
+                SmtpAuthMechanism::Plain => Mechanism::Plain,
+                SmtpAuthMechanism::Login => Mechanism::Login,
+            };
+            builder = builder
+                .credentials(Credentials::new(
+                    username,
+                    password.expose_secret().to_owned(),
+                ))
+                .authentication(vec![mechanism]);
+        }
+        Self {
+            transport: builder.build(),
+            sender,
+        }
+    }
+
+    fn build_message(&self, email: &OutgoingEmail<'_>) -> Result<Message, SendEmailError> {
+        let malformed = |e: &dyn std::error::Error| SendEmailError::MalformedRequest {
+            status: None,
+            message: e.to_string(),
+        };
+        let from: Mailbox = self.sender.as_ref().parse().map_err(|e| malformed(&e))?;
+        let to: Mailbox = email
+            .recipient
+            .as_ref()
+            .parse()
+            .map_err(|e| malformed(&e))?;
+        let mut builder = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(email.subject)
+            .message_id(None);
+        for (name, value) in email
+            .list_unsubscribe
+            .into_iter()
+            .flat_map(list_unsubscribe_headers)
+        {
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str(name),
+                value,
+            ));
+        }
+        match email.html_body {
+            Some(html_body) => builder.multipart(MultiPart::alternative_plain_html(
+                email.text_body.to_owned(),
+                html_body.to_owned(),
+            )),
+            None => builder.singlepart(SinglePart::plain(email.text_body.to_owned())),
+        }
+        .map_err(|e| malformed(&e))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    async fn send(&self, email: &OutgoingEmail<'_>) -> SendEmailResult {
+        let message = self.build_message(email)?;
+        // SMTP relays do not hand back an id of their own, so we report the
+        // `Message-ID` we generated: it is what shows up in bounces and logs.
+        let message_id = message
+            .headers()
+            .get_raw("Message-ID")
+            .map(|id| id.trim_matches(|c| c == '<' || c == '>').to_owned());
+        self.transport
+            .send(message)
+            .await
+            .map_err(from_smtp_error)?;
+        Ok(message_id)
+    }
+}
+
+/// Classify a failed SMTP exchange using the reply code, if the server sent one.
+/// See https://www.rfc-editor.org/rfc/rfc5321#section-4.2.3
+fn from_smtp_error(e: lettre::transport::smtp::Error) -> SendEmailError {
+    if let Some(code) = e.status() {
+        let message = e.to_string();
+        return match (u16::from(code), enhanced_status(&message)) {
+            // Authentication required, too weak, invalid or requires encryption
+            (530 | 534 | 535 | 538, _) => SendEmailError::AuthFailure {
+                status: None,
+                message,
+            },
+            // Mailbox unavailable, user not local, mailbox name not allowed. The
+            // same codes answer `MAIL FROM` and lettre does not tell us which
+            // command failed: only the enhanced status code pins it on the recipient.
+            (error_code @ (550 | 551 | 553), Some((_, 1, 1 | 2 | 3 | 6 | 10) | (_, 2, 1))) => {
+                SendEmailError::RejectedRecipient {
+                    status: None,
+                    error_code: error_code.into(),
+                    message,
+                }
+            }
+            // Bad sender address, relaying denied or any other policy refusal:
+            // nothing gets through until someone fixes our configuration
+            (550 | 551 | 553, Some((_, 1, 7 | 8) | (_, 7, _))) => SendEmailError::AuthFailure {
+                status: None,
+                message,
+            },
+            _ if e.is_transient() => SendEmailError::ProviderError {
+                status: None,
+                message,
+            },
+            _ => SendEmailError::MalformedRequest {
+                status: None,
+                message,
+            },
+        };
+    }
+    if e.is_timeout() {
+        SendEmailError::Timeout(Box::new(e))
+    } else if e.is_client() {
+        SendEmailError::MalformedRequest {
+            status: None,
+            message: e.to_string(),
+        }
+    } else {
+        SendEmailError::Connection(Box::new(e))
+    }
+}
+
+/// The `class.subject.detail` enhanced status code in an SMTP reply, if any.
+/// See https://www.rfc-editor.org/rfc/rfc3463
+fn enhanced_status(reply: &str) -> Option<(u16, u16, u16)> {
+    reply.split_whitespace().find_map(|word| {
+        let mut parts = word
+            .trim_matches(|c: char| !c.is_ascii_digit())
+            .split('.')
+            .map(|part| part.parse::<u16>().ok());
+        match (parts.next()?, parts.next()?, parts.next()?, parts.next()) {
+            (Some(class @ (2 | 4 | 5)), Some(subject), Some(detail), None) => {
+                Some((class, subject, detail))
+            }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{SmtpAuthMechanism, SmtpSettings, SmtpTls};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, OutgoingEmail, SendEmailError, SmtpClient};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::Fake;
+    use secrecy::Secret;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn recipient() -> SubscriberEmail {
+        SafeEmail().fake::<String>().parse().unwrap()
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn sender() -> SubscriberEmail {
+        SafeEmail().fake::<String>().parse().unwrap()
+    }
+
+    /// A bare-bones SMTP server that accepts everything unless told otherwise
+    /// and records what it was sent.
+    #[derive(Clone, Default)]
+    struct SmtpSink {
+        /// Reply to `MAIL FROM`, e.g. `553 5.7.1 Relaying denied`.
+        mail_reply: Option<&'static str>,
+        /// Reply to `RCPT TO`, e.g. `550 No such user`.
+        rcpt_reply: Option<&'static str>,
+        /// Reply to the final step of `AUTH`, e.g. `535 Bad credentials`.
+        auth_reply: Option<&'static str>,
+        received: Arc<Mutex<Received>>,
+    }
+
+    #[derive(Default)]
+    struct Received {
+        /// Decoded `(username, password)` pairs, in the order they were sent.
+        logins: Vec<(String, String)>,
+        auth_mechanisms: Vec<String>,
+        messages: Vec<String>,
+    }
+
+    impl SmtpSink {
+        async fn start(self) -> u16 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(self.clone().serve(stream));
+                }
+            });
+            port
+        }
+
+        async fn serve(self, stream: tokio::net::TcpStream) {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.to_uppercase();
+                let response = if command.starts_with("EHLO") {
+                    "250-sink\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME".to_string()
+                } else if command.starts_with("AUTH PLAIN") {
+                    let encoded = line.split_whitespace().nth(2).unwrap_or_default();
+                    let decoded = String::from_utf8(STANDARD.decode(encoded).unwrap()).unwrap();
+                    let mut parts = decoded.split('\0').skip(1);
+                    let login = (
+                        parts.next().unwrap_or_default().to_owned(),
+                        parts.next().unwrap_or_default().to_owned(),
+                    );
+                    let mut received = self.received.lock().unwrap();
+                    received.auth_mechanisms.push("PLAIN".into());
+                    received.logins.push(login);
+                    self.auth_reply.unwrap_or("235 Authenticated").to_string()
+                } else if command.starts_with("AUTH LOGIN") {
+                    writer.write_all(b"334 VXNlcm5hbWU6\r\n").await.unwrap();
+                    let username = decode_line(lines.next_line().await.unwrap().unwrap());
+                    writer.write_all(b"334 UGFzc3dvcmQ6\r\n").await.unwrap();
+                    let password = decode_line(lines.next_line().await.unwrap().unwrap());
+                    let mut received = self.received.lock().unwrap();
+                    received.auth_mechanisms.push("LOGIN".into());
+                    received.logins.push((username, password));
+                    self.auth_reply.unwrap_or("235 Authenticated").to_string()
+                } else if command.starts_with("MAIL") {
+                    self.mail_reply.unwrap_or("250 OK").to_string()
+                } else if command.starts_with("RCPT") {
+                    self.rcpt_reply.unwrap_or("250 OK").to_string()
+                } else if command.starts_with("DATA") {
+                    writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                    let mut message = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line == "." {
+                            break;
+                        }
+                        message.push_str(&line);
+                        message.push('\n');
+                    }
+                    self.received.lock().unwrap().messages.push(message);
+                    "250 Queued".to_string()
+                } else if command.starts_with("QUIT") {
+                    let _ = writer.write_all(b"221 Bye\r\n").await;
+                    return;
+                } else {
+                    "250 OK".to_string()
+                };
+                let response = format!("{response}\r\n");
+                if writer.write_all(response.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn decode_line(line: String) -> String {
+        String::from_utf8(STANDARD.decode(line.trim()).unwrap()).unwrap()
+    }
+
+    fn smtp_settings(port: u16) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            auth_mechanism: SmtpAuthMechanism::Plain,
+        }
+    }
+
+    fn email_client(settings: SmtpSettings) -> SmtpClient {
+        SmtpClient::new(settings, sender(), std::time::Duration::from_secs(5))
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_the_message_to_the_relay() {
+        let sink = SmtpSink::default();
+        let port = sink.clone().start().await;
+        let email_client = email_client(smtp_settings(port));
+        let subject = subject();
+
+        let outcome = email_client
+            .send_email(&recipient(), &subject, &content(), &content())
+            .await;
+
+        let message_id = outcome.unwrap().expect("Expected a message id.");
+        let received = sink.received.lock().unwrap();
+        assert_eq!(received.messages.len(), 1);
+        let message = &received.messages[0];
+        assert!(message.contains(&message_id));
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("text/plain"));
+        assert!(message.contains("text/html"));
+        assert!(received.logins.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_unsubscribe_headers_are_added_to_the_message() {
+        let sink = SmtpSink::default();
+        let port = sink.clone().start().await;
+        let email_client = email_client(smtp_settings(port));
+        let (recipient, subject, content) = (recipient(), subject(), content());
+
+        email_client
+            .send(&OutgoingEmail {
+                recipient: &recipient,
+                subject: &subject,
+                html_body: Some(&content),
+                text_body: &content,
+                list_unsubscribe: Some("https://example.com/unsubscribe"),
+            })
+            .await
+            .unwrap();
+
+        let received = sink.received.lock().unwrap();
+        let message = &received.messages[0];
+        assert!(message.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(message.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+
+    #[tokio::test]
+    async fn plain_text_only_emails_are_not_multipart() {
+        let sink = SmtpSink::default();
+        let port = sink.clone().start().await;
+        let email_client = email_client(smtp_settings(port));
+        let (recipient, subject, content) = (recipient(), subject(), content());
+
+        email_client
+            .send(&OutgoingEmail {
+                recipient: &recipient,
+                subject: &subject,
+                html_body: None,
+                text_body: &content,
+                list_unsubscribe: None,
+            })
+            .await
+            .unwrap();
+
+        let received = sink.received.lock().unwrap();
+        let message = &received.messages[0];
+        assert!(message.contains("text/plain"));
+        assert!(!message.contains("multipart"));
+        assert!(!message.contains("text/html"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_auth_plain() {
+        let sink = SmtpSink::default();
+        let port = sink.clone().start().await;
+        let email_client = email_client(SmtpSettings {
+            username: Some("newsletter".into()),
+            password: Some(Secret::new("hunter2".into())),
+            ..smtp_settings(port)
+        });
+
+        email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        let received = sink.received.lock().unwrap();
+        assert_eq!(received.auth_mechanisms, vec!["PLAIN"]);
+        assert_eq!(
+            received.logins,
+            vec![("newsletter".to_string(), "hunter2".to_string())]
+        );
+        assert_eq!(received.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_auth_login() {
+        let sink = SmtpSink::default();
+        let port = sink.clone().start().await;
+        let email_client = email_client(SmtpSettings {
+            username: Some("newsletter".into()),
+            password: Some(Secret::new("hunter2".into())),
+            auth_mechanism: SmtpAuthMechanism::Login,
+            ..smtp_settings(port)
+        });
+
+        email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        let received = sink.received.lock().unwrap();
+        assert_eq!(received.auth_mechanisms, vec!["LOGIN"]);
+        assert_eq!(
+            received.logins,
+            vec![("newsletter".to_string(), "hunter2".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_are_an_auth_failure() {
+        let sink = SmtpSink {
+            auth_reply: Some("535 5.7.8 Authentication credentials invalid"),
+            ..Default::default()
+        };
+        let port = sink.clone().start().await;
+        let email_client = email_client(SmtpSettings {
+            username: Some("newsletter".into()),
+            password: Some(Secret::new("wrong".into())),
+            ..smtp_settings(port)
+        });
+
+        let e = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .expect_err("Expected the request to fail.");
+
+        assert!(matches!(e, SendEmailError::AuthFailure { .. }));
+        assert!(!e.is_retryable());
+        assert!(sink.received.lock().unwrap().messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_mailboxes_are_rejected_permanently() {
+        let sink = SmtpSink {
+            rcpt_reply: Some("550 5.1.1 No such user"),
+            ..Default::default()
+        };
+        let port = sink.clone().start().await;
+        let email_client = email_client(smtp_settings(port));
+
+        let e = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .expect_err("Expected the request to fail.");
+
+        assert!(matches!(
+            e,
+            SendEmailError::RejectedRecipient {
+                error_code: 550,
+                ..
+            }
+        ));
+        assert!(!e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_refused_sender_is_not_blamed_on_the_recipient() {
+        for mail_reply in [
+            "553 5.7.1 Relaying denied",
+            "550 5.1.8 Sender address rejected",
+        ] {
+            let sink = SmtpSink {
+                mail_reply: Some(mail_reply),
+                ..Default::default()
+            };
+            let port = sink.clone().start().await;
+            let email_client = email_client(smtp_settings(port));
+
+            let e = email_client
+                .send_email(&recipient(), &subject(), &content(), &content())
+                .await
+                .expect_err("Expected the request to fail.");
+
+            assert!(matches!(e, SendEmailError::AuthFailure { .. }));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejections_that_may_not_be_about_the_recipient_do_not_reject_them() {
+        let sink = SmtpSink {
+            rcpt_reply: Some("550 Rejected"),
+            ..Default::default()
+        };
+        let port = sink.clone().start().await;
+        let email_client = email_client(smtp_settings(port));
+
+        let e = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .expect_err("Expected the request to fail.");
+
+        assert!(matches!(e, SendEmailError::MalformedRequest { .. }));
+        assert!(!e.is_retryable());
+    }
+
+    #[test]
+    fn enhanced_status_codes_are_read_from_the_reply() {
+        assert_eq!(
+            super::enhanced_status("permanent error (550): 5.1.1 No such user"),
+            Some((5, 1, 1))
+        );
+        assert_eq!(
+            super::enhanced_status("553 5.7.1 <a@b.c>: Relay access denied"),
+            Some((5, 7, 1))
+        );
+        assert_eq!(
+            super::enhanced_status("permanent error (550): Rejected"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_replies_are_retryable() {
+        let sink = SmtpSink {
+            rcpt_reply: Some("451 4.3.0 Try again later"),
+            ..Default::default()
+        };
+        let port = sink.clone().start().await;
+        let email_client = email_client(smtp_settings(port));
+
+        let e = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .expect_err("Expected the request to fail.");
+
+        assert!(matches!(e, SendEmailError::ProviderError { .. }));
+        assert!(e.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_relay_is_a_retryable_connection_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let email_client = email_client(smtp_settings(port));
+
+        let e = email_client
+            .send_email(&recipient(), &subject(), &content(), &content())
+            .await
+            .expect_err("Expected the request to fail.");
+
+        assert!(matches!(e, SendEmailError::Connection(_)));
+        assert!(e.is_retryable());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::css_inline::inline_css;
+use crate::utils::escape_html;
+use anyhow::Context;
+use minijinja::value::Value;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+use sqlx::PgExecutor;
+use std::fmt::Write;
+use std::str::FromStr;
+use uuid::Uuid;
+
+const LAYOUT_TEMPLATE: &str = "layout.html";
+
+/// The layout emails are wrapped in as long as none is saved as the default.
+pub const BUILT_IN_LAYOUT: &str = include_str!("email_layout.html");
+
+/// What a layout can refer to, under the template field of the layout forms.
+pub const LAYOUT_HINT_HTML: &str = "<p><small>A layout can use \
+    <code>{{ title }}</code>, <code>{{ content }}</code>, <code>{{ postal_address }}</code> \
+    and, in newsletter issues only, <code>{{ unsubscribe_url }}</code> and \
+    <code>{{ preferences_url }}</code>: wrap them in \
+    <code>{% if unsubscribe_url %}...{% endif %}</code>. The rules of its \
+    <code>&lt;style&gt;</code> elements are inlined in the order they are written, \
+    media queries and rules such as <code>a:hover</code> are left in place.</small></p>";
+
+const SAMPLE_CONTENT: &str = "<h1>Release notes</h1>\n\
+    <p>Version <strong>2.0</strong> is out: read <a href=\"https://example.com/blog\">the announcement</a>.</p>\n\
+    <ul>\n<li>Faster deliveries</li>\n<li>Segments</li>\n</ul>";
+
+/// The links at the bottom of every newsletter issue. Transactional messages,
+/// such as the confirmation email, have none.
+pub struct FooterLinks<'a> {
+    pub unsubscribe_url: &'a str,
+    pub preferences_url: &'a str,
+}
+
+impl FooterLinks<'static> {
+    pub fn sample() -> Self {
+        Self {
+            unsubscribe_url: "https://example.com/subscriptions/unsubscribe",
+            preferences_url: "https://example.com/subscriptions/preferences",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LayoutContext<'a> {
+    title: &'a str,
+    content: Value,
+    postal_address: &'a str,
+    /// Undefined in transactional messages, for `{% if unsubscribe_url %}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unsubscribe_url: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preferences_url: Option<Value>,
+}
+
+/// The HTML document the content of an email is wrapped in: a header, a
+/// footer with the postal address and, for newsletter issues, the unsubscribe
+/// link. Its style sheets are inlined, for the email clients that ignore them.
+pub struct EmailLayout {
+    environment: Environment<'static>,
+    postal_address: String,
+}
+
+impl EmailLayout {
+    pub fn compile(template: &str, postal_address: &str) -> Result<Self, String> {
+        let mut environment = Environment::new();
+        // Undefined values can be tested, but a typo must not go out as an empty string
+        environment.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        environment.set_trim_blocks(true);
+        environment.set_lstrip_blocks(true);
+        environment
+            .add_template_owned(LAYOUT_TEMPLATE, template.to_owned())
+            .map_err(|e| format!("The layout is not a valid template: {e}"))?;
+        Ok(Self {
+            environment,
+            postal_address: postal_address.to_owned(),
+        })
+    }
+
+    /// Compile the layout and wrap sample content in it, as a newsletter issue
+    /// and as a transactional message: anything that would fail when sending
+    /// fails here, before saving. Issues must carry the unsubscribe link.
+    pub fn validate(template: &str, postal_address: &str) -> Result<(), String> {
+        let layout = Self::compile(template, postal_address)?;
+        layout.wrap("Welcome!", SAMPLE_CONTENT, None)?;
+        let links = FooterLinks::sample();
+        let html = layout.wrap("Release notes", SAMPLE_CONTENT, Some(&links))?;
+        if !html.contains(links.unsubscribe_url) {
+            return Err(
+                "The layout must link to {{ unsubscribe_url }} in newsletter issues.".into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Sample content in this layout, as a newsletter issue.
+    pub fn preview(&self) -> Result<String, String> {
+        self.wrap(
+            "Release notes",
+            SAMPLE_CONTENT,
+            Some(&FooterLinks::sample()),
+        )
+    }
+
+    /// Wrap an HTML fragment, which is trusted: it is not escaped.
+    pub fn wrap(
+        &self,
+        title: &str,
+        content: &str,
+        links: Option<&FooterLinks<'_>>,
+    ) -> Result<String, String> {
+        // The links are escaped the way `escape_html` does everywhere else
+        let link = |url: &str| Value::from_safe_string(escape_html(url));
+        let html = self
+            .environment
+            .get_template(LAYOUT_TEMPLATE)
+            .and_then(|template| {
+                template.render(LayoutContext {
+                    title,
+                    content: Value::from_safe_string(content.to_owned()),
+                    postal_address: &self.postal_address,
+                    unsubscribe_url: links.map(|l| link(l.unsubscribe_url)),
+                    preferences_url: links.map(|l| link(l.preferences_url)),
+                })
+            })
+            .map_err(|e| format!("Failed to render the layout: {e}"))?;
+        inline_css(&html).map_err(|e| format!("Failed to inline the CSS of the layout: {e}"))
+    }
+}
+
+impl Default for EmailLayout {
+    fn default() -> Self {
+        Self::compile(BUILT_IN_LAYOUT, "").expect("The built-in layout is not a valid template")
+    }
+}
+
+/// A layout as admins edit it.
+pub struct SavedLayout {
+    pub layout_id: Uuid,
+    pub name: String,
+    pub template: String,
+    pub postal_address: String,
+    pub is_default: bool,
+}
+
+impl SavedLayout {
+    pub fn compile(&self) -> Result<EmailLayout, anyhow::Error> {
+        EmailLayout::compile(&self.template, &self.postal_address)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("The layout {} cannot be used", self.name))
+    }
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_layouts(executor: impl PgExecutor<'_>) -> Result<Vec<SavedLayout>, sqlx::Error> {
+    sqlx::query_as!(
+        SavedLayout,
+        r#"
+        SELECT layout_id, name, template, postal_address, is_default
+        FROM email_layouts
+        ORDER BY name
+        "#
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_layout(
+    executor: impl PgExecutor<'_>,
+    layout_id: Uuid,
+) -> Result<Option<SavedLayout>, sqlx::Error> {
+    sqlx::query_as!(
+        SavedLayout,
+        r#"
+        SELECT layout_id, name, template, postal_address, is_default
+        FROM email_layouts
+        WHERE layout_id = $1
+        "#,
+        layout_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// The layout of transactional messages, and of the issues that do not pick
+/// one: the one saved as the default, or the built-in one.
+#[tracing::instrument(skip(executor))]
+pub async fn get_default_layout(
+    executor: impl PgExecutor<'_>,
+) -> Result<EmailLayout, anyhow::Error> {
+    let layout = sqlx::query_as!(
+        SavedLayout,
+        r#"
+        SELECT layout_id, name, template, postal_address, is_default
+        FROM email_layouts
+        WHERE is_default
+        "#
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch the default layout.")?;
+    match layout {
+        Some(layout) => layout.compile(),
+        None => Ok(EmailLayout::default()),
+    }
+}
+
+/// The layout an issue goes out in, if any: see `IssueLayout`.
+#[tracing::instrument(skip(executor))]
+pub async fn get_issue_layout(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<EmailLayout>, anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT i.use_layout, l.name AS "name?", l.template AS "template?",
+            l.postal_address AS "postal_address?"
+        FROM newsletter_issues i
+        LEFT JOIN email_layouts l ON l.layout_id = COALESCE(
+            i.layout_id,
+            (SELECT layout_id FROM email_layouts WHERE is_default)
+        )
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to fetch the layout of a newsletter issue.")?;
+    if !issue.use_layout {
+        return Ok(None);
+    }
+    let layout = match (issue.name, issue.template, issue.postal_address) {
+        (Some(name), Some(template), Some(postal_address)) => {
+            EmailLayout::compile(&template, &postal_address)
+                .map_err(|e| anyhow::anyhow!("The layout {name} cannot be used: {e}"))?
+        }
+        _ => EmailLayout::default(),
+    };
+    Ok(Some(layout))
+}
+
+/// Which layout an issue is wrapped in, as picked in the issue forms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueLayout {
+    /// The default one at the time the issue goes out.
+    Default,
+    Saved(Uuid),
+    /// None: the HTML content is a whole document of its own.
+    Without,
+}
+
+impl IssueLayout {
+    /// As stored in the `use_layout` and `layout_id` columns of `newsletter_issues`.
+    pub fn from_columns(use_layout: bool, layout_id: Option<Uuid>) -> Self {
+        match (use_layout, layout_id) {
+            (false, _) => Self::Without,
+            (true, None) => Self::Default,
+            (true, Some(layout_id)) => Self::Saved(layout_id),
+        }
+    }
+
+    pub fn use_layout(&self) -> bool {
+        *self != Self::Without
+    }
+
+    pub fn layout_id(&self) -> Option<Uuid> {
+        match self {
+            Self::Saved(layout_id) => Some(*layout_id),
+            Self::Default | Self::Without => None,
+        }
+    }
+}
+
+impl FromStr for IssueLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Ok(Self::Default),
+            "none" => Ok(Self::Without),
+            layout_id => layout_id
+                .parse()
+                .map(Self::Saved)
+                .map_err(|_| format!("{layout_id} is not a valid layout id.")),
+        }
+    }
+}
+
+/// The layout picker of the issue forms.
+pub fn layout_select_html(layouts: &[SavedLayout], selected: IssueLayout) -> String {
+    let option = |value: &str, label: &str, is_selected: bool| {
+        format!(
+            r#"<option value="{value}"{}>{label}</option>"#,
+            if is_selected { " selected" } else { "" }
+        )
+    };
+    let mut options_html = String::new();
+    writeln!(
+        options_html,
+        "{}",
+        option("", "The default layout", selected == IssueLayout::Default)
+    )
+    .unwrap();
+    for layout in layouts {
+        writeln!(
+            options_html,
+            "{}",
+            option(
+                &layout.layout_id.to_string(),
+                &escape_html(&layout.name),
+                selected == IssueLayout::Saved(layout.layout_id)
+            )
+        )
+        .unwrap();
+    }
+    write!(
+        options_html,
+        "{}",
+        option(
+            "none",
+            "None: the HTML content is a whole email",
+            selected == IssueLayout::Without
+        )
+    )
+    .unwrap();
+    format!(
+        r#"<label>Layout:<br>
+            <select name="layout">
+                {options_html}
+            </select>
+        </label>
+        <br>"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailLayout, FooterLinks};
+
+    #[test]
+    fn the_title_is_escaped_but_not_the_content() {
+        let layout = EmailLayout::compile("<h1>{{ title }}</h1>{{ content }}", "").unwrap();
+
+        let html = layout.wrap("Q&A", "<p>Answers</p>", None).unwrap();
+
+        assert_eq!(html, "<h1>Q&amp;A</h1><p>Answers</p>");
+    }
+
+    #[test]
+    fn the_built_in_layout_is_valid() {
+        let template = super::BUILT_IN_LAYOUT;
+
+        assert!(EmailLayout::validate(template, "1 Main Street\nSpringfield").is_ok());
+    }
+
+    #[test]
+    fn the_footer_links_are_left_out_of_transactional_messages() {
+        let layout = EmailLayout::default();
+        let links = FooterLinks::sample();
+
+        let issue = layout.wrap("Title", "<p>Hi</p>", Some(&links)).unwrap();
+        let message = layout.wrap("Title", "<p>Hi</p>", None).unwrap();
+
+        assert!(issue.contains(links.unsubscribe_url));
+        assert!(!message.contains("href"));
+    }
+
+    #[test]
+    fn layouts_without_the_unsubscribe_link_are_rejected() {
+        let e = EmailLayout::validate("<div>{{ content }}</div>", "").unwrap_err();
+
+        assert!(e.contains("{{ unsubscribe_url }}"), "{e}");
+    }
+
+    #[test]
+    fn footer_links_must_be_optional() {
+        let e = EmailLayout::validate(
+            r#"{{ content }}<a href="{{ unsubscribe_url }}">Leave</a>"#,
+            "",
+        )
+        .unwrap_err();
+
+        assert!(e.starts_with("Failed to render the layout: "), "{e}");
+    }
+
+    #[test]
+    fn layouts_with_typos_are_rejected() {
+        let e = EmailLayout::validate(
+            r#"{{ contnet }}{% if unsubscribe_url %}<a href="{{ unsubscribe_url }}">Leave</a>{% endif %}"#,
+            "",
+        )
+        .unwrap_err();
+
+        assert!(e.starts_with("Failed to render the layout: "), "{e}");
+    }
+}
@@ -1,122 +1,850 @@
-use crate::config::Settings;
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::click_links::{track_html_links, track_text_links, ClickLinks};
+use crate::config::{Settings, WorkerSettings};
+use crate::domain::{ClickTracking, EmailFormat, IssueStatus, SubscriberEmail};
+use crate::email_client::{EmailSender, OutgoingEmail, SendEmailError};
+use crate::email_layout::{get_issue_layout, EmailLayout, FooterLinks};
+use crate::issue_scheduler::{run_scheduler, SystemClock};
+use crate::issue_template::{IssueContext, IssueTemplate, SubscriberContext, TemplateContext};
+use crate::rate_limiter::RateLimiter;
+use crate::segments::{get_issue_segment, push_segment_condition};
 use crate::startup::get_conn_pool;
-use sqlx::{Executor, PgPool, Postgres, Transaction};
+use crate::subscription_cleanup::run_subscription_cleanup;
+use crate::unsubscribe_links::{
+    html_with_unsubscribe_footer, text_with_unsubscribe_footer, UnsubscribeLinks,
+};
+use chrono::Utc;
+use rand::Rng;
+use sqlx::postgres::PgListener;
+use sqlx::{Executor, PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-pub async fn run_worker_until_stopped(config: Settings) -> Result<(), anyhow::Error> {
+pub async fn run_worker_until_stopped(
+    config: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
     let conn_pool = get_conn_pool(&config.database);
     let email_client = config.email_client.client();
-    worker_loop(conn_pool, email_client).await
+    let unsubscribe_links = config.application.unsubscribe_links();
+    let click_links = config.application.click_links();
+    let scheduler = run_scheduler(
+        conn_pool.clone(),
+        Arc::new(SystemClock),
+        config.worker.clone(),
+        shutdown.clone(),
+    );
+    let cleanup = run_subscription_cleanup(
+        conn_pool.clone(),
+        config.subscriptions.clone(),
+        shutdown.clone(),
+    );
+    let workers = run_delivery_workers(
+        conn_pool,
+        email_client,
+        unsubscribe_links,
+        click_links,
+        config.worker,
+        shutdown,
+    );
+    tokio::try_join!(scheduler, cleanup, workers)?;
+    Ok(())
+}
+
+/// Run `config.concurrency` delivery loops, woken up whenever an enqueuer
+/// notifies `config.notify_channel`.
+///
+/// Once `shutdown` is cancelled every loop finishes the batch it is working
+/// on, if any, and stops dequeuing.
+pub async fn run_delivery_workers(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    unsubscribe_links: UnsubscribeLinks,
+    click_links: ClickLinks,
+    config: WorkerSettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let unsubscribe_links = Arc::new(unsubscribe_links);
+    let click_links = Arc::new(click_links);
+    let rate_limiter = Arc::new(RateLimiter::new(&config));
+    let pause = Arc::new(QueuePause::default());
+    let config = Arc::new(config);
+    let (wake_up, new_tasks) = watch::channel(());
+    let mut workers = JoinSet::new();
+    let notifications = forward_notifications(pool.clone(), config.notify_channel.clone(), wake_up);
+    let stopped = shutdown.clone();
+    workers.spawn(async move {
+        tokio::select! {
+            result = notifications => result,
+            _ = stopped.cancelled() => Ok(()),
+        }
+    });
+    for _ in 0..config.concurrency.max(1) {
+        workers.spawn(worker_loop(
+            pool.clone(),
+            email_client.clone(),
+            unsubscribe_links.clone(),
+            click_links.clone(),
+            rate_limiter.clone(),
+            pause.clone(),
+            config.clone(),
+            new_tasks.clone(),
+            shutdown.clone(),
+        ));
+    }
+    while let Some(result) = workers.join_next().await {
+        result??;
+    }
+    Ok(())
 }
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+
+/// Wake every worker loop up when new deliveries are queued.
+async fn forward_notifications(
+    pool: PgPool,
+    channel: String,
+    wake_up: watch::Sender<()>,
+) -> Result<(), anyhow::Error> {
+    let mut listener = loop {
+        match listen(&pool, &channel).await {
+            Ok(listener) => break listener,
+            Err(e) => {
+                // The workers still poll the queue in the meantime
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to listen for delivery queue notifications."
+                );
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    };
     loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::TaskCompleted) => {}
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+        match listener.try_recv().await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                // The connection was re-established: we may have missed notifications
+                tracing::warn!("Lost the connection to the delivery queue notifications.");
             }
-            Err(_) => {
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to listen for delivery queue notifications."
+                );
                 tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        }
+        wake_up.send_replace(());
+    }
+}
+
+async fn listen(pool: &PgPool, channel: &str) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(channel).await?;
+    Ok(listener)
+}
+
+/// Shared by every delivery loop: once the email provider refuses our
+/// credentials to one of them, none of them dequeues anything until it is over.
+#[derive(Default)]
+struct QueuePause {
+    until: Mutex<Option<Instant>>,
+}
+
+impl QueuePause {
+    fn pause_for(&self, duration: Duration) {
+        *self.until.lock().unwrap() = Some(Instant::now() + duration);
+    }
+
+    /// How long until the queue resumes, if it is paused.
+    fn remaining(&self) -> Option<Duration> {
+        let until = (*self.until.lock().unwrap())?;
+        Some(until.saturating_duration_since(Instant::now())).filter(|d| !d.is_zero())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    unsubscribe_links: Arc<UnsubscribeLinks>,
+    click_links: Arc<ClickLinks>,
+    rate_limiter: Arc<RateLimiter>,
+    pause: Arc<QueuePause>,
+    config: Arc<WorkerSettings>,
+    mut new_tasks: watch::Receiver<()>,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        if let Some(remaining) = pause.remaining() {
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = tokio::time::sleep(remaining) => {}
             }
+            continue;
+        }
+        // Anything enqueued from here on wakes us up, even while we are busy sending
+        new_tasks.borrow_and_update();
+        let (wait, wake_on_new_tasks) = match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &unsubscribe_links,
+            &click_links,
+            &rate_limiter,
+            &config,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                let poll_interval = config.poll_interval();
+                let wait = match next_task_due_in(&pool).await {
+                    Ok(Some(due_in)) => due_in.min(poll_interval),
+                    _ => poll_interval,
+                };
+                (wait, true)
+            }
+            Ok(ExecutionOutcome::QueuePaused) => {
+                pause.pause_for(config.auth_failure_pause());
+                continue;
+            }
+            Err(_) => (Duration::from_secs(1), false),
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            Ok(()) = new_tasks.changed(), if wake_on_new_tasks => {}
+            _ = tokio::time::sleep(wait) => {}
         }
     }
+    Ok(())
 }
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
+    /// The email provider refused our credentials: the task was put back untouched
+    /// and nothing else should be sent until someone fixes the configuration.
+    QueuePaused,
 }
 pub async fn try_execute_task(
     pool: &PgPool,
-    email_client: &EmailClient,
+    email_client: &dyn EmailSender,
+    unsubscribe_links: &UnsubscribeLinks,
+    click_links: &ClickLinks,
+    rate_limiter: &RateLimiter,
+    config: &WorkerSettings,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    if let Some((tx, issue_id, email)) = dequeue_task(pool).await? {
-        match email.clone().parse::<SubscriberEmail>() {
+    // A single batch never holds more than a second worth of messages
+    let batch_size = config
+        .batch_size
+        .min(email_client.max_batch_size())
+        .min(config.messages_per_second as usize)
+        .max(1);
+    let (mut tx, tasks) = dequeue_tasks(pool, batch_size).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let mut issue_ids: Vec<_> = tasks.iter().map(|t| t.newsletter_issue_id).collect();
+    issue_ids.sort_unstable();
+    issue_ids.dedup();
+    let mut issues = HashMap::new();
+    let mut deliverable = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let Some(subscriber_id) = task.subscriber_id.filter(|_| task.is_confirmed) else {
+            // They left after the issue was published
+            delete_task(&mut tx, &task).await?;
+            continue;
+        };
+        match task.subscriber_email.parse::<SubscriberEmail>() {
             Ok(email) => {
-                let issue = get_issue(pool, issue_id).await?;
-                if let Err(e) = email_client
-                    .send_email(
-                        &email,
-                        &issue.title,
-                        &issue.text_content,
-                        &issue.html_content,
-                    )
-                    .await
-                {
-                    tracing::error!(
-                        error.cause_chain =?e,
-                        error.message = %e,
-                        "Failed to send email to a confirmed subscriber. Skipping."
-                    );
+                let wait = rate_limiter.try_reserve_for_domain(email.domain());
+                if !wait.is_zero() {
+                    // Not a failure: the task keeps its retries and tries again once
+                    // the domain has room for it
+                    reschedule_task(&mut tx, &task, task.n_retries, wait).await?;
+                    continue;
                 }
+                if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+                    entry.insert(get_issue(pool, task.newsletter_issue_id).await?);
+                }
+                let links = (
+                    unsubscribe_links.link(subscriber_id),
+                    unsubscribe_links.preferences_link(subscriber_id),
+                );
+                let track = |target: &str| {
+                    click_links.link(task.newsletter_issue_id, subscriber_id, target)
+                };
+                let issue = &issues[&task.newsletter_issue_id];
+                let content = match render_issue(issue, &task, &links, track) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        // Templates are validated when issues are published: this is a bug
+                        tracing::error!(
+                            error.message = %e,
+                            "Skipping a confirmed subscriber. The issue could not be rendered for them.");
+                        log_delivery(&mut tx, &task, DeliveryStatus::Failed, None, Some(&e))
+                            .await?;
+                        move_task_to_failures(&mut tx, &task, &e, None).await?;
+                        continue;
+                    }
+                };
+                deliverable.push((task, email, links, content));
             }
             Err(e) => {
                 tracing::error!(
                     error.cause_chain =?e,
                     error.message = %e,
                     "Skipping a confirmed subscriber. Their stored contact details are invalid.");
+                log_delivery(&mut tx, &task, DeliveryStatus::Failed, None, Some(&e)).await?;
+                move_task_to_failures(&mut tx, &task, &e, None).await?;
+            }
+        }
+    }
+    if deliverable.is_empty() {
+        tx.commit().await?;
+        mark_delivered_issues_as_sent(pool, &issue_ids).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let emails: Vec<_> = deliverable
+        .iter()
+        .map(
+            |(task, email, (unsubscribe_link, _), (html_body, text_body))| OutgoingEmail {
+                recipient: email,
+                subject: &issues[&task.newsletter_issue_id].title,
+                html_body: html_body.as_deref(),
+                text_body,
+                list_unsubscribe: Some(unsubscribe_link),
+            },
+        )
+        .collect();
+    tokio::time::sleep(rate_limiter.reserve(emails.len())).await;
+    let mut outcome = ExecutionOutcome::TaskCompleted;
+    match email_client.send_batch(&emails).await {
+        Ok(results) => {
+            for ((task, _, _, _), result) in deliverable.iter().zip(results) {
+                match result {
+                    Ok(message_id) => {
+                        log_delivery(&mut tx, task, DeliveryStatus::Sent, message_id, None).await?;
+                        delete_task(&mut tx, task).await?;
+                    }
+                    Err(e) => {
+                        if let ExecutionOutcome::QueuePaused =
+                            handle_send_failure(&mut tx, task, &e, false, config).await?
+                        {
+                            outcome = ExecutionOutcome::QueuePaused;
+                        }
+                    }
+                }
             }
         }
-        delete_task(tx, issue_id, &email).await?;
-        Ok(ExecutionOutcome::TaskCompleted)
+        Err(e @ SendEmailError::AuthFailure { .. }) => {
+            // Every task is left untouched, to be picked up once the queue resumes
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "The email provider refused to send a batch of deliveries."
+            );
+            outcome = ExecutionOutcome::QueuePaused;
+        }
+        Err(e) => {
+            for (task, _, _, _) in &deliverable {
+                handle_send_failure(&mut tx, task, &e, true, config).await?;
+            }
+        }
+    }
+    if let ExecutionOutcome::QueuePaused = outcome {
+        tracing::error!("The email provider refused our credentials. Pausing the delivery queue.");
+    }
+    tx.commit().await?;
+    // Only once committed: a worker finishing the last tasks of an issue at the
+    // same time as us would otherwise still see ours, and we theirs
+    mark_delivered_issues_as_sent(pool, &issue_ids).await?;
+    Ok(outcome)
+}
+
+/// The HTML body, if they want one, and the plain text body of the issue for
+/// the subscriber behind `task`, footer included. Links go through `track`
+/// if the issue tracks clicks, but for the footer's own.
+fn render_issue(
+    issue: &NewsletterIssue,
+    task: &DeliveryTask,
+    (unsubscribe_link, preferences_link): &(String, String),
+    track: impl Fn(&str) -> String,
+) -> Result<(Option<String>, String), String> {
+    let template = issue.template.as_ref().map_err(Clone::clone)?;
+    let (html_content, text_content) = template.render(&TemplateContext {
+        subscriber: SubscriberContext {
+            name: task.subscriber_name.as_deref().unwrap_or_default(),
+            email: &task.subscriber_email,
+        },
+        issue: IssueContext {
+            title: &issue.title,
+        },
+        unsubscribe_url: unsubscribe_link,
+        preferences_url: preferences_link,
+    })?;
+    let html_body = match (task.email_format(), &issue.layout) {
+        (EmailFormat::Html, Some(layout)) => Some(layout.wrap(
+            &issue.title,
+            &html_content,
+            Some(&FooterLinks {
+                unsubscribe_url: unsubscribe_link,
+                preferences_url: preferences_link,
+            }),
+        )?),
+        (EmailFormat::Html, None) => Some(html_with_unsubscribe_footer(
+            &html_content,
+            unsubscribe_link,
+            preferences_link,
+        )),
+        (EmailFormat::PlainText, _) => None,
+    };
+    let untracked = [unsubscribe_link.as_str(), preferences_link.as_str()];
+    let html_body = match html_body {
+        Some(html) if issue.click_tracking.tracks_html() => Some(
+            track_html_links(&html, &untracked, &track)
+                .map_err(|e| format!("Failed to track the links of the issue: {e}"))?,
+        ),
+        html_body => html_body,
+    };
+    let text_content = if issue.click_tracking.tracks_text() {
+        track_text_links(&text_content, &untracked, &track)
     } else {
-        Ok(ExecutionOutcome::EmptyQueue)
+        text_content
+    };
+    let text_body = text_with_unsubscribe_footer(&text_content, unsubscribe_link, preferences_link);
+    Ok((html_body, text_body))
+}
+
+/// Flag the issues among `issue_ids` that have nothing left in the queue as `sent`.
+#[tracing::instrument(skip(pool))]
+async fn mark_delivered_issues_as_sent(
+    pool: &PgPool,
+    issue_ids: &[Uuid],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = 'sent', updated_at = now()
+        WHERE newsletter_issue_id = ANY($1)
+            AND status = 'sending'
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            )
+        "#,
+        issue_ids
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Decide whether a failed delivery is retried, dead-lettered (suppressing the
+/// recipient if the provider rejected them) or whether the whole queue must stop.
+///
+/// `whole_batch` errors failed the request the task was sent in rather than the
+/// task's own message: they say nothing about its recipient, so nobody is
+/// suppressed over them and they are retried.
+async fn handle_send_failure(
+    tx: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
+    e: &SendEmailError,
+    whole_batch: bool,
+    config: &WorkerSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let http_status = e.http_status().map(|s| s.as_u16() as i16);
+    let error_message = e.to_string();
+    match e {
+        SendEmailError::AuthFailure { .. } => {
+            // The task is left untouched, to be picked up once the queue resumes
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "The email provider refused to send a newsletter issue."
+            );
+            return Ok(ExecutionOutcome::QueuePaused);
+        }
+        SendEmailError::RateLimited { retry_after } => {
+            // Being throttled is not the recipient's fault: it does not use up a retry
+            let delay = retry_after.unwrap_or_else(|| backoff_delay(task.n_retries, config));
+            tracing::warn!(
+                error.message = %e,
+                retry_in_seconds = delay.as_secs(),
+                "The email provider is rate limiting us. Retrying later."
+            );
+            log_delivery(
+                tx,
+                task,
+                DeliveryStatus::Retrying,
+                None,
+                Some(&error_message),
+            )
+            .await?;
+            reschedule_task(tx, task, task.n_retries, delay).await?;
+        }
+        SendEmailError::RejectedRecipient { .. } if !whole_batch => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "The email provider rejected a confirmed subscriber. Suppressing them."
+            );
+            suppress_subscriber(tx, &task.subscriber_email).await?;
+            log_delivery(tx, task, DeliveryStatus::Failed, None, Some(&error_message)).await?;
+            move_task_to_failures(tx, task, &error_message, http_status).await?;
+        }
+        e if !(e.is_retryable()
+            || (whole_batch && matches!(e, SendEmailError::RejectedRecipient { .. })))
+            || task.n_retries >= config.max_retries =>
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                n_retries = task.n_retries,
+                "Failed to send email to a confirmed subscriber. Giving up."
+            );
+            log_delivery(tx, task, DeliveryStatus::Failed, None, Some(&error_message)).await?;
+            move_task_to_failures(tx, task, &error_message, http_status).await?;
+        }
+        e => {
+            let delay = backoff_delay(task.n_retries, config);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                n_retries = task.n_retries,
+                retry_in_seconds = delay.as_secs(),
+                "Failed to send email to a confirmed subscriber. Retrying later."
+            );
+            log_delivery(
+                tx,
+                task,
+                DeliveryStatus::Retrying,
+                None,
+                Some(&error_message),
+            )
+            .await?;
+            reschedule_task(tx, task, task.n_retries + 1, delay).await?;
+        }
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Exponential backoff capped at `backoff_max`, with the actual delay drawn
+/// uniformly from the upper half of the window to spread out retries.
+fn backoff_delay(n_retries: i16, config: &WorkerSettings) -> Duration {
+    let exponential = config
+        .backoff_base()
+        .saturating_mul(2u32.saturating_pow(n_retries.max(0) as u32));
+    let capped = exponential.min(config.backoff_max());
+    rand::thread_rng().gen_range(capped / 2..=capped)
+}
+
+/// The outcome of a single delivery attempt, as recorded in `issue_deliveries`.
+#[derive(Clone, Copy, Debug)]
+pub enum DeliveryStatus {
+    Sent,
+    Retrying,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Retrying => "retrying",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    subscriber_id: Option<Uuid>,
+    /// Whether they are still subscribed, now that it is time to send.
+    is_confirmed: bool,
+    email_format: Option<String>,
+    subscriber_name: Option<String>,
+}
+
+impl DeliveryTask {
+    fn email_format(&self) -> EmailFormat {
+        self.email_format
+            .as_deref()
+            .and_then(|f| f.parse().ok())
+            .unwrap_or_default()
     }
 }
 
-async fn dequeue_task(
+/// Queue one delivery of the issue for every confirmed subscriber and mark it
+/// as published: `sending`, or straight away `sent` if there is nobody to send it to.
+///
+/// Issues sent to specific lists only reach their confirmed members, once
+/// however many of those lists they belong to. Issues sent to a segment only
+/// reach the subscribers in it: it is compiled into the `WHERE` clause, so
+/// targeting still takes a single query.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    tx: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let segment = get_issue_segment(&mut **tx, newsletter_issue_id).await?;
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) SELECT ",
+    );
+    // The first bind parameter, `$1` below
+    query.push_bind(newsletter_issue_id).push(
+        r#", s.email
+        FROM subscriptions s
+        WHERE s.status = 'confirmed'
+            AND (
+                NOT EXISTS (
+                    SELECT 1 FROM newsletter_issue_lists
+                    WHERE newsletter_issue_id = $1
+                )
+                OR EXISTS (
+                    SELECT 1
+                    FROM newsletter_issue_lists il
+                    JOIN list_subscriptions ls ON ls.list_id = il.list_id
+                    WHERE il.newsletter_issue_id = $1 AND ls.subscriber_id = s.id
+                )
+            )"#,
+    );
+    if let Some(segment) = segment {
+        query.push(" AND (");
+        push_segment_condition(&mut query, &segment);
+        query.push(")");
+    }
+    let n_enqueued = query.build().execute(&mut **tx).await?.rows_affected();
+    let status = if n_enqueued > 0 {
+        IssueStatus::Sending
+    } else {
+        IssueStatus::Sent
+    };
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, published_at = now()::text, updated_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        status.as_str()
+    );
+    tx.execute(query).await?;
+    Ok(())
+}
+
+/// Wake the delivery workers up: call it in the transaction that enqueued new
+/// tasks, Postgres only delivers the notification once it commits.
+pub async fn notify_delivery_workers(
+    tx: &mut Transaction<'_, Postgres>,
+    channel: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!("SELECT pg_notify($1, '')", channel);
+    tx.execute(query).await?;
+    Ok(())
+}
+
+/// How long until the earliest task scheduled in the future becomes due.
+async fn next_task_due_in(pool: &PgPool) -> Result<Option<Duration>, anyhow::Error> {
+    let next_due = sqlx::query!(
+        r#"
+        SELECT MIN(q.execute_after) AS next_due
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.execute_after > now() AND i.status = 'sending'
+        "#
+    )
+    .fetch_one(pool)
+    .await?
+    .next_due;
+    Ok(next_due.and_then(|next_due| (next_due - Utc::now()).to_std().ok()))
+}
+
+/// Lock up to `limit` due tasks of issues that are being sent (not paused): they
+/// stay locked, and out of reach of other workers, until the returned
+/// transaction is committed or dropped.
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(Transaction<'static, Postgres>, Uuid, String)>, anyhow::Error> {
+    limit: usize,
+) -> Result<(Transaction<'static, Postgres>, Vec<DeliveryTask>), anyhow::Error> {
     let mut tx = pool.begin().await?;
-    if let Some(row) = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
-        FROM issue_delivery_queue
-        FOR UPDATE
+        SELECT
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            s.id AS "subscriber_id?",
+            COALESCE(s.status = 'confirmed', false) AS "is_confirmed!",
+            s.email_format AS "email_format?",
+            s.name AS "subscriber_name?"
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        LEFT JOIN subscriptions s ON s.email = q.subscriber_email
+        WHERE q.execute_after <= now() AND i.status = 'sending'
+        FOR UPDATE OF q
         SKIP LOCKED
-        LIMIT 1
+        LIMIT $1
         "#,
+        i64::try_from(limit).unwrap_or(i64::MAX)
     )
-    .fetch_optional(&mut *tx)
-    .await?
-    {
-        Ok(Some((tx, row.newsletter_issue_id, row.subscriber_email)))
-    } else {
-        Ok(None)
-    }
+    .fetch_all(&mut *tx)
+    .await?;
+    Ok((tx, tasks))
 }
 
 async fn delete_task(
-    mut tx: Transaction<'static, Postgres>,
-    issue_id: Uuid,
-    email: &str,
+    tx: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
 ) -> Result<(), anyhow::Error> {
     let query = sqlx::query!(
         r#"
         DELETE FROM issue_delivery_queue
         WHERE newsletter_issue_id = $1 AND subscriber_email = $2
         "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    tx.execute(query).await?;
+    Ok(())
+}
+
+async fn reschedule_task(
+    tx: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
+    n_retries: i16,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = $3, execute_after = $4
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_retries,
+        execute_after
+    );
+    tx.execute(query).await?;
+    Ok(())
+}
+
+/// Stop sending to an address the provider refuses to deliver to.
+#[tracing::instrument(skip_all)]
+async fn suppress_subscriber(
+    tx: &mut Transaction<'static, Postgres>,
+    subscriber_email: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'suppressed'
+        WHERE email = $1
+        "#,
+        subscriber_email
+    );
+    tx.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn log_delivery(
+    tx: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
+    status: DeliveryStatus,
+    provider_message_id: Option<String>,
+    error_message: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            provider_message_id,
+            error_message,
+            attempted_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status.as_str(),
+        provider_message_id,
+        error_message
+    );
+    tx.execute(query).await?;
+    Ok(())
+}
+
+/// Dead-letter a task: it leaves the queue and is kept in `issue_delivery_failures`
+/// until an admin requeues or discards it.
+async fn move_task_to_failures(
+    tx: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
+    last_error: &str,
+    http_status: Option<i16>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id,
+            subscriber_email,
+            last_error,
+            http_status,
+            n_attempts,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET last_error = EXCLUDED.last_error,
+            http_status = EXCLUDED.http_status,
+            n_attempts = EXCLUDED.n_attempts,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        last_error,
+        http_status,
+        task.n_retries + 1
+    );
+    tx.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
     );
     tx.execute(query).await?;
-    tx.commit().await?;
     Ok(())
 }
 
 struct NewsletterIssue {
     title: String,
-    text_content: String,
-    html_content: String,
+    /// Compiled once per batch, rendered for every recipient.
+    template: Result<IssueTemplate, String>,
+    /// `None` if the HTML content goes out as it is, footer aside.
+    layout: Option<EmailLayout>,
+    click_tracking: ClickTracking,
 }
 
 #[tracing::instrument(skip_all)]
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
-    let issue = sqlx::query_as!(
-        NewsletterIssue,
+    let issue = sqlx::query!(
         r#"
-        SELECT title, text_content, html_content
+        SELECT title, text_content, html_content, click_tracking
         FROM newsletter_issues
         WHERE newsletter_issue_id = $1
         "#,
@@ -124,5 +852,54 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     )
     .fetch_one(pool)
     .await?;
-    Ok(issue)
+    Ok(NewsletterIssue {
+        template: IssueTemplate::compile(&issue.html_content, &issue.text_content),
+        layout: get_issue_layout(pool, issue_id).await?,
+        click_tracking: issue.click_tracking.parse().map_err(anyhow::Error::msg)?,
+        title: issue.title,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backoff_delay;
+    use crate::config::WorkerSettings;
+    use std::time::Duration;
+
+    fn worker_settings() -> WorkerSettings {
+        WorkerSettings {
+            max_retries: 5,
+            backoff_base_seconds: 10,
+            backoff_max_seconds: 100,
+            auth_failure_pause_seconds: 300,
+            batch_size: 500,
+            concurrency: 1,
+            messages_per_second: 50,
+            per_domain_messages_per_second: None,
+            notify_channel: "issue_delivery_queue".into(),
+            poll_interval_seconds: 300,
+            scheduler_interval_seconds: 30,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_with_each_retry() {
+        let config = worker_settings();
+        for (n_retries, upper_bound) in [(0, 10), (1, 20), (2, 40), (3, 80)] {
+            let delay = backoff_delay(n_retries, &config);
+            let upper_bound = Duration::from_secs(upper_bound);
+            assert!(delay <= upper_bound);
+            assert!(delay >= upper_bound / 2);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped_at_the_configured_maximum() {
+        let config = worker_settings();
+        for n_retries in [4, 10, i16::MAX] {
+            let delay = backoff_delay(n_retries, &config);
+            assert!(delay <= Duration::from_secs(100));
+            assert!(delay >= Duration::from_secs(50));
+        }
+    }
 }
@@ -0,0 +1,111 @@
+use crate::config::WorkerSettings;
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, notify_delivery_workers};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Where the scheduler gets the current time from, so tests can move it around.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Publish scheduled issues as they become due, until `shutdown` is cancelled.
+///
+/// The loop sleeps until the next scheduled issue is due, but never longer
+/// than `config.scheduler_interval()`: issues scheduled while it sleeps are
+/// picked up on the next tick.
+pub async fn run_scheduler(
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+    config: WorkerSettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        let wait = match publish_due_issues(&pool, clock.now(), &config.notify_channel).await {
+            Ok(_) => match next_issue_due_at(&pool).await {
+                Ok(Some(due_at)) => (due_at - clock.now())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO)
+                    .min(config.scheduler_interval()),
+                _ => config.scheduler_interval(),
+            },
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to publish scheduled newsletter issues."
+                );
+                Duration::from_secs(1)
+            }
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Enqueue the deliveries of every scheduled issue due at `now`, for the
+/// subscribers confirmed at that point, and return their ids.
+#[tracing::instrument(skip(pool, notify_channel))]
+pub async fn publish_due_issues(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+    notify_channel: &str,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    // Locking the issues keeps concurrent schedulers, and admins rescheduling
+    // or cancelling them, out of the way until they are enqueued
+    let issue_ids = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_for <= $1
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        now
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|r| r.newsletter_issue_id)
+    .collect::<Vec<_>>();
+    if issue_ids.is_empty() {
+        return Ok(issue_ids);
+    }
+    for &issue_id in &issue_ids {
+        enqueue_delivery_tasks(&mut tx, issue_id).await?;
+        tracing::info!(%issue_id, "Published a scheduled newsletter issue.");
+    }
+    notify_delivery_workers(&mut tx, notify_channel).await?;
+    tx.commit().await?;
+    Ok(issue_ids)
+}
+
+/// When the earliest pending scheduled issue is due.
+async fn next_issue_due_at(pool: &PgPool) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let next_due = sqlx::query!(
+        r#"
+        SELECT MIN(scheduled_for) AS next_due
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        "#
+    )
+    .fetch_one(pool)
+    .await?
+    .next_due;
+    Ok(next_due)
+}
@@ -0,0 +1,140 @@
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+
+const HTML_TEMPLATE: &str = "html_content.html";
+const TEXT_TEMPLATE: &str = "text_content.txt";
+
+/// What the content of an issue can refer to, e.g. `{{ subscriber.name }}`.
+#[derive(Serialize)]
+pub struct TemplateContext<'a> {
+    pub subscriber: SubscriberContext<'a>,
+    pub issue: IssueContext<'a>,
+    pub unsubscribe_url: &'a str,
+    pub preferences_url: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberContext<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct IssueContext<'a> {
+    pub title: &'a str,
+}
+
+impl<'a> TemplateContext<'a> {
+    /// Made-up recipient details, for previews, test sends and validation.
+    pub fn sample(title: &'a str) -> Self {
+        Self {
+            subscriber: SubscriberContext {
+                name: "Ursula Le Guin",
+                email: "ursula@example.com",
+            },
+            issue: IssueContext { title },
+            unsubscribe_url: "https://example.com/subscriptions/unsubscribe",
+            preferences_url: "https://example.com/subscriptions/preferences",
+        }
+    }
+}
+
+/// The HTML and plain text content of an issue, compiled once and rendered for
+/// every recipient. Values are escaped in the HTML content, not in the plain
+/// text one.
+pub struct IssueTemplate {
+    environment: Environment<'static>,
+}
+
+impl IssueTemplate {
+    pub fn compile(html_content: &str, text_content: &str) -> Result<Self, String> {
+        let mut environment = Environment::new();
+        // A typo must not go out as an empty string
+        environment.set_undefined_behavior(UndefinedBehavior::Strict);
+        environment
+            .add_template_owned(HTML_TEMPLATE, html_content.to_owned())
+            .map_err(|e| format!("The HTML content is not a valid template: {e}"))?;
+        environment
+            .add_template_owned(TEXT_TEMPLATE, text_content.to_owned())
+            .map_err(|e| format!("The plain text content is not a valid template: {e}"))?;
+        Ok(Self { environment })
+    }
+
+    /// Compile the templates and render them for a made-up recipient: anything
+    /// that would fail for real recipients fails here, before publishing.
+    pub fn validate(title: &str, html_content: &str, text_content: &str) -> Result<(), String> {
+        let template = Self::compile(html_content, text_content)?;
+        template.render(&TemplateContext::sample(title))?;
+        Ok(())
+    }
+
+    /// The HTML and plain text content for this recipient.
+    pub fn render(&self, context: &TemplateContext<'_>) -> Result<(String, String), String> {
+        let html = self
+            .render_one(HTML_TEMPLATE, context)
+            .map_err(|e| format!("Failed to render the HTML content: {e}"))?;
+        let text = self
+            .render_one(TEXT_TEMPLATE, context)
+            .map_err(|e| format!("Failed to render the plain text content: {e}"))?;
+        Ok((html, text))
+    }
+
+    fn render_one(
+        &self,
+        name: &str,
+        context: &TemplateContext<'_>,
+    ) -> Result<String, minijinja::Error> {
+        self.environment.get_template(name)?.render(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IssueTemplate, TemplateContext};
+
+    fn render(html_content: &str, text_content: &str) -> Result<(String, String), String> {
+        IssueTemplate::compile(html_content, text_content)?
+            .render(&TemplateContext::sample("Release <notes>"))
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_only() {
+        let (html, text) = render("<h1>{{ issue.title }}</h1>", "{{ issue.title }}").unwrap();
+
+        assert_eq!(html, "<h1>Release &lt;notes&gt;</h1>");
+        assert_eq!(text, "Release <notes>");
+    }
+
+    #[test]
+    fn conditionals_and_default_values_are_supported() {
+        let (html, text) = render(
+            "Hi {{ subscriber.nickname | default(subscriber.name) }}",
+            "{% if subscriber.name %}Hi {{ subscriber.name }}{% else %}Hi{% endif %}",
+        )
+        .unwrap();
+
+        assert_eq!(html, "Hi Ursula Le Guin");
+        assert_eq!(text, "Hi Ursula Le Guin");
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        let e = IssueTemplate::validate("Title", "<p>{{ subscriber.name </p>", "Hi").unwrap_err();
+
+        assert!(
+            e.starts_with("The HTML content is not a valid template: "),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        let e =
+            IssueTemplate::validate("Title", "<p>Hi</p>", "Hi {{ subscriber.nmae }}").unwrap_err();
+
+        assert!(
+            e.starts_with("Failed to render the plain text content: "),
+            "{e}"
+        );
+    }
+}
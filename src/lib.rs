@@ -1,11 +1,25 @@
 pub mod authentication;
+pub mod click_links;
 pub mod config;
+pub mod css_inline;
 pub mod domain;
 pub mod email_client;
+pub mod email_layout;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod issue_template;
+pub mod lists;
+pub mod markdown;
+pub mod rate_limiter;
 pub mod routes;
+pub mod segments;
 pub mod session_state;
 pub mod startup;
+pub mod subscriber_data;
+pub mod subscriber_import;
+pub mod subscription_cleanup;
+pub mod tags;
 pub mod telemetry;
+pub mod unsubscribe_links;
 pub mod utils;
@@ -0,0 +1,160 @@
+use crate::utils::escape_html;
+use sqlx::{Executor, PgExecutor, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// A newsletter of its own: subscribers pick the lists they want to hear
+/// from, and issues are sent to the members of one or more of them.
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub name: String,
+    pub description: String,
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_lists(executor: impl PgExecutor<'_>) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, name, description FROM lists ORDER BY name
+        "#
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_list_ids_of_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let list_ids = sqlx::query!(
+        r#"
+        SELECT list_id FROM list_subscriptions WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(|r| r.list_id)
+    .collect();
+    Ok(list_ids)
+}
+
+/// Make the subscriber a member of exactly these lists. Lists that do not
+/// exist (anymore) are ignored.
+#[tracing::instrument(skip(transaction))]
+pub async fn replace_list_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM list_subscriptions WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id)
+        SELECT list_id, $2 FROM lists WHERE list_id = ANY($1)
+        "#,
+        list_ids,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Send the issue to the members of these lists only. Lists that do not
+/// exist (anymore) are ignored: returns how many were kept.
+#[tracing::instrument(skip(transaction))]
+pub async fn set_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id FROM lists WHERE list_id = ANY($2)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        list_ids
+    );
+    Ok(transaction.execute(query).await?.rows_affected())
+}
+
+/// Parse the values of the `list_id` checkboxes rendered by `list_checkboxes_html`.
+pub fn parse_list_ids(values: &[String]) -> Result<Vec<Uuid>, String> {
+    values
+        .iter()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("{} is not a valid list id.", v))
+        })
+        .collect()
+}
+
+/// One `list_id` checkbox per list, ticked for the `selected` ones.
+pub fn list_checkboxes_html(lists: &[MailingList], selected: &[Uuid]) -> String {
+    let mut html = String::new();
+    for list in lists {
+        let checked = if selected.contains(&list.list_id) {
+            " checked"
+        } else {
+            ""
+        };
+        writeln!(
+            html,
+            r#"<label><input type="checkbox" name="list_id" value="{}"{checked}> {}</label> {}<br>"#,
+            list.list_id,
+            escape_html(&list.name),
+            escape_html(&list.description)
+        )
+        .unwrap();
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_checkboxes_html, parse_list_ids, MailingList};
+    use uuid::Uuid;
+
+    #[test]
+    fn list_ids_must_be_uuids() {
+        let list_id = Uuid::new_v4();
+
+        assert_eq!(parse_list_ids(&[list_id.to_string()]), Ok(vec![list_id]));
+        assert!(parse_list_ids(&["all".to_string()]).is_err());
+    }
+
+    #[test]
+    fn selected_lists_are_ticked_and_names_escaped() {
+        let lists = vec![
+            MailingList {
+                list_id: Uuid::new_v4(),
+                name: "Tips & tricks".into(),
+                description: String::new(),
+            },
+            MailingList {
+                list_id: Uuid::new_v4(),
+                name: "Events".into(),
+                description: String::new(),
+            },
+        ];
+
+        let html = list_checkboxes_html(&lists, &[lists[0].list_id]);
+
+        assert!(html.contains(&format!(
+            r#"value="{}" checked> Tips &amp; tricks</label>"#,
+            lists[0].list_id
+        )));
+        assert!(html.contains(&format!(r#"value="{}"> Events</label>"#, lists[1].list_id)));
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt::{Debug, Display};
-use tokio::task::JoinError;
+use std::time::Duration;
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
 use zero2prod::config::get_config;
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 use zero2prod::startup::Application;
@@ -10,19 +12,77 @@ async fn main() -> Result<(), anyhow::Error> {
     init_subscriber(subscriber);
 
     let config = get_config().expect("Failed to read configuration.");
+    let shutdown_timeout = config.application.shutdown_timeout();
+    let shutdown = CancellationToken::new();
 
     let application = Application::build(config.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
+    let mut application_task = tokio::spawn(application.run_until_stopped(shutdown.clone()));
 
-    let worker_task = tokio::spawn(run_worker_until_stopped(config));
+    let mut worker_task = tokio::spawn(run_worker_until_stopped(config, shutdown.clone()));
 
+    // Whatever happens first, the other task gets a chance to wind down
     tokio::select! {
-        result = application_task => report_exit("API", result),
-        result = worker_task => report_exit("Background worker",result)
+        result = &mut application_task => {
+            report_exit("API", result);
+            shutdown.cancel();
+            wait_for_exit("Background worker", worker_task, shutdown_timeout).await;
+        }
+        result = &mut worker_task => {
+            report_exit("Background worker", result);
+            shutdown.cancel();
+            wait_for_exit("API", application_task, shutdown_timeout).await;
+        }
+        _ = shutdown_signal() => {
+            tracing::info!("Received a shutdown signal, stopping");
+            shutdown.cancel();
+            tokio::join!(
+                wait_for_exit("API", application_task, shutdown_timeout),
+                wait_for_exit("Background worker", worker_task, shutdown_timeout)
+            );
+        }
     }
     Ok(())
 }
 
+/// Resolves on Ctrl+C or, on Unix, on SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+async fn wait_for_exit<E: Debug + Display>(
+    task_name: &str,
+    task: JoinHandle<Result<(), E>>,
+    deadline: Duration,
+) {
+    match tokio::time::timeout(deadline, task).await {
+        Ok(result) => report_exit(task_name, result),
+        Err(_) => {
+            tracing::warn!(
+                "{} did not shut down within {} seconds",
+                task_name,
+                deadline.as_secs()
+            )
+        }
+    }
+}
+
 fn report_exit(task_name: &str, result: Result<Result<(), impl Debug + Display>, JoinError>) {
     match result {
         Ok(Ok(())) => {
@@ -0,0 +1,312 @@
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+
+/// Markdown rendered into an HTML fragment, sanitised, and into plain text.
+/// Template tags, e.g. `{{ subscriber.name }}`, are left for `IssueTemplate`
+/// to render for every recipient.
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+pub fn render_markdown(markdown: &str) -> RenderedMarkdown {
+    let (markdown, tags) = protect_template_tags(markdown);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(&markdown));
+    let html = ammonia::clean(&html);
+    let text = TextWriter::render(&markdown);
+    RenderedMarkdown {
+        html: restore_template_tags(html, &tags),
+        text: restore_template_tags(text, &tags),
+    }
+}
+
+/// Swap template tags for placeholders Markdown leaves alone: it would escape
+/// their quotes in text and percent-encode them in link targets.
+fn protect_template_tags(markdown: &str) -> (String, Vec<&str>) {
+    let mut protected = String::with_capacity(markdown.len());
+    let mut tags = Vec::new();
+    let mut rest = markdown;
+    while let Some(start) = rest.find('{') {
+        let close = match rest[start..].chars().nth(1) {
+            Some('{') => "}}",
+            Some('%') => "%}",
+            Some('#') => "#}",
+            _ => {
+                protected.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        let Some(length) = rest[start + 2..].find(close) else {
+            break;
+        };
+        let end = start + 2 + length + close.len();
+        protected.push_str(&rest[..start]);
+        protected.push_str(&placeholder(tags.len()));
+        tags.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    protected.push_str(rest);
+    (protected, tags)
+}
+
+fn restore_template_tags(mut rendered: String, tags: &[&str]) -> String {
+    for (i, tag) in tags.iter().enumerate() {
+        rendered = rendered.replace(&placeholder(i), tag);
+    }
+    rendered
+}
+
+fn placeholder(i: usize) -> String {
+    format!("zz0template{i}zz")
+}
+
+/// Writes Markdown as plain text meant to be read as is: markup is dropped,
+/// structure is kept with indentation, and link targets are listed as
+/// footnotes at the end.
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    /// Written at the start of every line: quote markers and list indentation.
+    prefixes: Vec<String>,
+    at_line_start: bool,
+    /// Nothing was written since the marker of the current list item.
+    after_item_marker: bool,
+    /// The next number of every open list, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    /// Where the text of every open link or image starts, and its target.
+    links: Vec<(usize, String)>,
+    footnotes: Vec<String>,
+    /// Inside an inline `<script>` or `<style>` element, whose content is not
+    /// text and is dropped from the HTML as well.
+    in_raw_element: bool,
+}
+
+impl TextWriter {
+    fn render(markdown: &str) -> String {
+        let mut writer = TextWriter::default();
+        for event in Parser::new(markdown) {
+            writer.event(event);
+        }
+        writer.finish()
+    }
+
+    fn event(&mut self, event: Event<'_>) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(_) if self.in_raw_element => {}
+            Event::Text(text) | Event::Code(text) => self.write(&text),
+            Event::InlineHtml(html) => {
+                let tag = html.trim_start_matches('<').to_ascii_lowercase();
+                if tag.starts_with("script") || tag.starts_with("style") {
+                    self.in_raw_element = true;
+                } else if tag.starts_with("/script") || tag.starts_with("/style") {
+                    self.in_raw_element = false;
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => self.write("\n"),
+            Event::Rule => {
+                self.start_block();
+                self.write("----\n");
+            }
+            // Raw HTML has no plain text rendition
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph | Tag::Heading { .. } => self.start_block(),
+            Tag::BlockQuote(_) => {
+                self.start_block();
+                self.prefixes.push("> ".into());
+            }
+            Tag::CodeBlock(_) => {
+                self.start_block();
+                self.prefixes.push("    ".into());
+            }
+            Tag::List(first_number) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else {
+                    self.end_line();
+                }
+                self.lists.push(first_number);
+            }
+            Tag::Item => {
+                self.end_line();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.write(&marker);
+                // Continuation lines line up with the text of the item
+                self.prefixes.push(" ".repeat(marker.len()));
+                self.after_item_marker = true;
+            }
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.links.push((self.out.len(), dest_url.into_string()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => self.end_line(),
+            TagEnd::Heading(level) => {
+                let underline = match level {
+                    HeadingLevel::H1 => '=',
+                    HeadingLevel::H2 => '-',
+                    _ => {
+                        self.end_line();
+                        return;
+                    }
+                };
+                let heading = self.out.rsplit('\n').next().unwrap_or_default();
+                let underline = underline.to_string().repeat(heading.chars().count());
+                self.write("\n");
+                self.write(&underline);
+                self.end_line();
+            }
+            TagEnd::BlockQuote(_) | TagEnd::CodeBlock => {
+                self.prefixes.pop();
+                self.end_line();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                self.end_line();
+            }
+            TagEnd::Item => {
+                self.prefixes.pop();
+                self.end_line();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                let Some((start, target)) = self.links.pop() else {
+                    return;
+                };
+                let text = self.out[start..].trim();
+                // Bare URLs and email addresses are their own footnote
+                if text == target || format!("mailto:{text}") == target {
+                    return;
+                }
+                let n = match self.footnotes.iter().position(|f| *f == target) {
+                    Some(i) => i + 1,
+                    None => {
+                        self.footnotes.push(target);
+                        self.footnotes.len()
+                    }
+                };
+                self.write(&format!(" [{n}]"));
+            }
+            _ => {}
+        }
+    }
+
+    fn write(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.at_line_start && c != '\n' {
+                self.out.push_str(&self.prefixes.concat());
+            }
+            self.out.push(c);
+            self.at_line_start = c == '\n';
+        }
+        if !s.is_empty() {
+            self.after_item_marker = false;
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.out.is_empty() && !self.at_line_start {
+            self.write("\n");
+        }
+    }
+
+    /// Blocks are separated by a blank line, except right after the marker of
+    /// the list item they are in.
+    fn start_block(&mut self) {
+        if self.out.is_empty() || self.after_item_marker {
+            return;
+        }
+        self.end_line();
+        if !self.out.ends_with("\n\n") {
+            self.write("\n");
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = self.out.trim_end().to_string();
+        if !self.footnotes.is_empty() {
+            text.push_str("\n\n");
+            for (i, target) in self.footnotes.iter().enumerate() {
+                text.push_str(&format!("[{}] {target}\n", i + 1));
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_markdown;
+
+    const ISSUE: &str = r#"# Release notes
+
+Hi {{ subscriber.name | default("there") }}, version **2.0** is _out_.
+Read [the announcement](https://example.com/blog/2-0) or
+[the changelog](https://example.com/changelog "Changelog").
+
+## What changed
+
+1. Faster deliveries
+2. Segments, see [the docs](https://example.com/docs/segments)
+   and [the changelog](https://example.com/changelog)
+3. Imports:
+   - CSV files
+   - up to 32 MiB
+
+> Upgrading is *free*.
+> Questions? Write to <support@example.com>
+
+    cargo install zero2prod
+
+---
+
+Not interested anymore? [Unsubscribe]({{ unsubscribe_url }}) or visit <https://example.com>.
+"#;
+
+    #[test]
+    fn issues_are_rendered_to_html() {
+        insta::assert_snapshot!(render_markdown(ISSUE).html);
+    }
+
+    #[test]
+    fn issues_are_rendered_to_plain_text_with_links_as_footnotes() {
+        insta::assert_snapshot!(render_markdown(ISSUE).text);
+    }
+
+    #[test]
+    fn html_is_sanitised() {
+        let rendered = render_markdown(
+            "Hello <script>alert(1)</script><b onclick=\"steal()\">there</b>\n\n\
+            [click](javascript:alert(1))",
+        );
+
+        insta::assert_snapshot!(rendered.html);
+        assert_eq!(
+            rendered.text,
+            "Hello there\n\nclick [1]\n\n[1] javascript:alert(1)"
+        );
+    }
+
+    #[test]
+    fn braces_that_are_not_template_tags_are_left_alone() {
+        let rendered = render_markdown("Sets look like {a, b}, and {{ unclosed");
+
+        assert_eq!(rendered.text, "Sets look like {a, b}, and {{ unclosed");
+    }
+}
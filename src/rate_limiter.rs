@@ -0,0 +1,164 @@
+use crate::config::WorkerSettings;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Per-domain buckets are dropped once we track this many domains, as long as
+/// they have fully refilled (i.e. forgetting them changes nothing).
+const MAX_IDLE_DOMAINS: usize = 10_000;
+
+/// Caps how fast the delivery workers hand messages to the email provider,
+/// both overall and per recipient domain. Shared by every worker task.
+pub struct RateLimiter {
+    global: Mutex<TokenBucket>,
+    per_domain: Option<(u32, Mutex<HashMap<String, TokenBucket>>)>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &WorkerSettings) -> Self {
+        let now = Instant::now();
+        Self {
+            global: Mutex::new(TokenBucket::new(config.messages_per_second, now)),
+            per_domain: config
+                .per_domain_messages_per_second
+                .map(|rate| (rate, Mutex::new(HashMap::new()))),
+        }
+    }
+
+    /// Reserve room for `n_messages` and return how long to wait before sending them.
+    pub fn reserve(&self, n_messages: usize) -> Duration {
+        self.global
+            .lock()
+            .unwrap()
+            .reserve(n_messages as f64, Instant::now())
+    }
+
+    /// Take room for one message to `domain` if there is some right now.
+    /// Otherwise nothing is taken and we get back how long until there is:
+    /// deferred messages must not eat into the room of those sent in the meantime.
+    pub fn try_reserve_for_domain(&self, domain: &str) -> Duration {
+        let Some((rate, buckets)) = &self.per_domain else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let mut buckets = buckets.lock().unwrap();
+        if buckets.len() >= MAX_IDLE_DOMAINS {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        buckets
+            .entry(domain.to_lowercase())
+            .or_insert_with(|| TokenBucket::new(*rate, now))
+            .try_reserve(1.0, now)
+    }
+}
+
+/// A token bucket that refills at `rate` tokens per second, holds at most one
+/// second worth of tokens and can go into debt: a reservation always succeeds,
+/// but the caller has to wait until the bucket is out of the red.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        let rate = f64::from(rate.max(1));
+        Self {
+            rate,
+            tokens: rate,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        self.updated_at = now;
+    }
+
+    fn reserve(&mut self, n_tokens: f64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= n_tokens;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Like `reserve`, but the bucket never goes into debt: if there are not
+    /// enough tokens, none are taken and we get how long until there are.
+    fn try_reserve(&mut self, n_tokens: f64, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= n_tokens {
+            self.tokens -= n_tokens;
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((n_tokens - self.tokens) / self.rate)
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenBucket;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_of_one_second_worth_of_messages() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        for _ in 0..10 {
+            assert_eq!(bucket.reserve(1.0, now), Duration::ZERO);
+        }
+        assert_eq!(bucket.reserve(1.0, now), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn reservations_beyond_the_burst_are_spread_at_the_configured_rate() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        assert_eq!(bucket.reserve(10.0, now), Duration::ZERO);
+        // Every extra message is pushed 100ms further into the future
+        assert_eq!(bucket.reserve(5.0, now), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(5.0, now), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn the_bucket_refills_over_time_up_to_its_capacity() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        bucket.reserve(10.0, now);
+        assert!(!bucket.is_full(now));
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(bucket.reserve(5.0, later), Duration::ZERO);
+        assert!(bucket.reserve(1.0, later) > Duration::ZERO);
+
+        let much_later = later + Duration::from_secs(60);
+        assert!(bucket.is_full(much_later));
+        assert_eq!(bucket.reserve(10.0, much_later), Duration::ZERO);
+        assert!(bucket.reserve(1.0, much_later) > Duration::ZERO);
+    }
+
+    #[test]
+    fn a_failed_try_reserve_takes_nothing_from_the_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        assert_eq!(bucket.try_reserve(10.0, now), Duration::ZERO);
+        // However many times we ask, the wait does not grow
+        for _ in 0..5 {
+            assert_eq!(bucket.try_reserve(1.0, now), Duration::from_millis(100));
+        }
+
+        let later = now + Duration::from_millis(100);
+        assert_eq!(bucket.try_reserve(1.0, later), Duration::ZERO);
+        assert_eq!(bucket.try_reserve(1.0, later), Duration::from_millis(100));
+    }
+}
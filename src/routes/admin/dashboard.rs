@@ -29,6 +29,13 @@ pub async fn admin_dashboard(
                 <li>
                     <a href="/admin/newsletters">Send a newsletter issue></a>
                 </li>
+                <li><a href="/admin/lists">Lists</a></li>
+                <li><a href="/admin/tags">Tags</a></li>
+                <li><a href="/admin/segments">Segments</a></li>
+                <li><a href="/admin/layouts">Email layouts</a></li>
+                <li><a href="/admin/imports">Import subscribers</a></li>
+                <li><a href="/admin/subscribers">Subscribers</a></li>
+                <li><a href="/admin/failures">Failed deliveries</a></li>
                 <li>
                     <form name="logoutForm" action="/admin/logout" method="post">
                         <input type="submit" value="Logout">
@@ -0,0 +1,138 @@
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct DeliveryFailure {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    last_error: String,
+    http_status: Option<i16>,
+    n_attempts: i16,
+    failed_at: DateTime<Utc>,
+}
+
+pub async fn delivery_failures(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let failures = get_delivery_failures(&pool).await.map_err(e500)?;
+
+    let mut failures_html = String::new();
+    if failures.is_empty() {
+        failures_html.push_str("<p>There are no failed deliveries.</p>");
+    }
+    for (i, failure) in failures.iter().enumerate() {
+        let issue_id = failure.newsletter_issue_id;
+        // Rows are sorted by issue, so a new issue starts a new section
+        if i == 0 || failures[i - 1].newsletter_issue_id != issue_id {
+            if i != 0 {
+                failures_html.push_str("</table>");
+            }
+            write!(
+                failures_html,
+                r#"<h2>{title}</h2>
+    <form action="/admin/failures/requeue" method="post" style="display: inline;">
+        <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+        <button type="submit">Requeue all</button>
+    </form>
+    <form action="/admin/failures/discard" method="post" style="display: inline;">
+        <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+        <button type="submit">Discard all</button>
+    </form>
+    <table>
+        <tr>
+            <th>Recipient</th>
+            <th>Last error</th>
+            <th>HTTP status</th>
+            <th>Attempts</th>
+            <th>Failed at</th>
+            <th></th>
+        </tr>"#,
+                title = escape_html(&failure.title),
+            )
+            .unwrap();
+        }
+        write!(
+            failures_html,
+            r#"
+        <tr>
+            <td>{email}</td>
+            <td>{last_error}</td>
+            <td>{http_status}</td>
+            <td>{n_attempts}</td>
+            <td>{failed_at}</td>
+            <td>
+                <form action="/admin/failures/requeue" method="post" style="display: inline;">
+                    <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                    <input hidden type="text" name="subscriber_email" value="{email}">
+                    <button type="submit">Requeue</button>
+                </form>
+                <form action="/admin/failures/discard" method="post" style="display: inline;">
+                    <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                    <input hidden type="text" name="subscriber_email" value="{email}">
+                    <button type="submit">Discard</button>
+                </form>
+            </td>
+        </tr>"#,
+            email = escape_html(&failure.subscriber_email),
+            last_error = escape_html(&failure.last_error),
+            http_status = failure
+                .http_status
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "-".into()),
+            n_attempts = failure.n_attempts,
+            failed_at = failure.failed_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        )
+        .unwrap();
+    }
+    if !failures.is_empty() {
+        failures_html.push_str("</table>");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Failed Deliveries</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Failed deliveries</h1>
+    {failures_html}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_delivery_failures(pool: &PgPool) -> Result<Vec<DeliveryFailure>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT f.newsletter_issue_id, i.title, f.subscriber_email, f.last_error,
+            f.http_status, f.n_attempts, f.failed_at
+        FROM issue_delivery_failures f
+        JOIN newsletter_issues i ON i.newsletter_issue_id = f.newsletter_issue_id
+        ORDER BY f.newsletter_issue_id, f.failed_at, f.subscriber_email
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch failed deliveries.")?;
+    Ok(failures)
+}
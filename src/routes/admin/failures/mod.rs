@@ -0,0 +1,4 @@
+mod get;
+mod post;
+pub use get::delivery_failures;
+pub use post::{discard_failures, requeue_failures};
@@ -0,0 +1,126 @@
+use crate::issue_delivery_worker::notify_delivery_workers;
+use crate::startup::DeliveryQueueChannel;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Targets every failure of an issue, or a single recipient when
+/// `subscriber_email` is provided.
+#[derive(Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Requeueing failed deliveries.",
+    skip(pool, form, queue_channel)
+)]
+pub async fn requeue_failures(
+    pool: web::Data<PgPool>,
+    form: web::Form<FormData>,
+    queue_channel: web::Data<DeliveryQueueChannel>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_requeued = requeue(
+        &pool,
+        &queue_channel.0,
+        form.newsletter_issue_id,
+        form.subscriber_email.as_deref(),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info(format!("{n_requeued} deliveries have been requeued.")).send();
+    Ok(see_other("/admin/failures"))
+}
+
+#[tracing::instrument(name = "Discarding failed deliveries.", skip(pool, form))]
+pub async fn discard_failures(
+    pool: web::Data<PgPool>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let n_discarded = discard(
+        &pool,
+        form.newsletter_issue_id,
+        form.subscriber_email.as_deref(),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info(format!("{n_discarded} deliveries have been discarded.")).send();
+    Ok(see_other("/admin/failures"))
+}
+
+async fn requeue(
+    pool: &PgPool,
+    queue_channel: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_email: Option<&str>,
+) -> Result<u64, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    // Cancelled issues are never delivered again: their failures stay put
+    let n_requeued = sqlx::query!(
+        r#"
+        WITH requeued AS (
+            DELETE FROM issue_delivery_failures
+            WHERE newsletter_issue_id = $1
+                AND ($2::TEXT IS NULL OR subscriber_email = $2)
+                AND EXISTS (
+                    SELECT 1 FROM newsletter_issues
+                    WHERE newsletter_issue_id = $1 AND status <> 'cancelled'
+                )
+            RETURNING newsletter_issue_id, subscriber_email
+        )
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email FROM requeued
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to move failed deliveries back to the delivery queue.")?
+    .rows_affected();
+    if n_requeued > 0 {
+        sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = 'sending', updated_at = now()
+            WHERE newsletter_issue_id = $1 AND status = 'sent'
+            "#,
+            newsletter_issue_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to mark the newsletter issue as sending again.")?;
+    }
+    notify_delivery_workers(&mut tx, queue_channel)
+        .await
+        .context("Failed to notify the delivery workers.")?;
+    tx.commit().await?;
+    Ok(n_requeued)
+}
+
+async fn discard(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: Option<&str>,
+) -> Result<u64, anyhow::Error> {
+    let n_discarded = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_failures
+        WHERE newsletter_issue_id = $1
+            AND ($2::TEXT IS NULL OR subscriber_email = $2)
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(pool)
+    .await
+    .context("Failed to discard failed deliveries.")?
+    .rows_affected();
+    Ok(n_discarded)
+}
@@ -0,0 +1,110 @@
+use crate::subscriber_import::{get_import_rejections, rejections_csv};
+use crate::utils::{attachment, e404, e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct ImportSummary {
+    import_id: Uuid,
+    mode: String,
+    n_rows: i32,
+    n_imported: i32,
+    n_rejected: i32,
+    n_duplicates: i32,
+    created_at: DateTime<Utc>,
+}
+
+pub async fn imports_form(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let mut imports_html = String::new();
+    for import in get_recent_imports(&pool).await.map_err(e500)? {
+        writeln!(
+            imports_html,
+            r#"<li>{} ({}): {} of {} rows imported, {} rejected, {} duplicates -
+            <a href="/admin/imports/{}/report">report</a></li>"#,
+            import.created_at.format("%Y-%m-%d %H:%M UTC"),
+            import.mode,
+            import.n_imported,
+            import.n_rows,
+            import.n_rejected,
+            import.n_duplicates,
+            import.import_id
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/imports" method="post" enctype="multipart/form-data">
+        <p>
+            A CSV file with a header row: <code>email</code> and <code>name</code>
+            columns, optionally <code>tags</code> (comma separated) and <code>list</code>
+            (the name of a list to join).
+        </p>
+        <input type="file" name="file" accept=".csv,text/csv">
+        <br>
+        <label><input type="radio" name="mode" value="double_opt_in" checked> Send them a confirmation email</label>
+        <br>
+        <label><input type="radio" name="mode" value="confirmed"> They already confirmed their subscription</label>
+        <br>
+        <button type="submit">Import</button>
+    </form>
+    <h2>Recent imports</h2>
+    <ul>
+        {imports_html}
+    </ul>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+/// The rows an import rejected or skipped as duplicates, as a CSV download.
+pub async fn import_report(
+    pool: web::Data<PgPool>,
+    import_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    let rejections = get_import_rejections(&pool, import_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no such import."))?;
+    let csv = rejections_csv(&rejections).map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(attachment(format!("import-{import_id}-report.csv")))
+        .body(csv))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_recent_imports(pool: &PgPool) -> Result<Vec<ImportSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        ImportSummary,
+        r#"
+        SELECT import_id, mode, n_rows, n_imported, n_rejected, n_duplicates, created_at
+        FROM subscriber_imports
+        ORDER BY created_at DESC
+        LIMIT 20
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,4 @@
+mod get;
+pub use get::{import_report, imports_form};
+mod post;
+pub use post::{import_form_config, upload_import};
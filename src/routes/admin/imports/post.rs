@@ -0,0 +1,63 @@
+use crate::domain::ImportMode;
+use crate::email_client::EmailSender;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::subscriber_import::{import_subscribers, ImportError};
+use crate::utils::{e400, e500, see_other};
+use actix_multipart::form::bytes::Bytes;
+use actix_multipart::form::text::Text;
+use actix_multipart::form::{MultipartForm, MultipartFormConfig};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+
+/// CSV files are read in memory: a 40k rows export weighs a few megabytes.
+const MAX_IMPORT_SIZE: usize = 32 * 1024 * 1024;
+
+pub fn import_form_config() -> MultipartFormConfig {
+    MultipartFormConfig::default()
+        .total_limit(MAX_IMPORT_SIZE)
+        .memory_limit(MAX_IMPORT_SIZE)
+}
+
+#[derive(MultipartForm)]
+pub struct ImportForm {
+    file: Bytes,
+    mode: Text<String>,
+}
+
+#[tracing::instrument(
+    name = "Importing subscribers.",
+    skip(form, pool, email_client, base_url, hmac_secret),
+    fields(mode = %form.mode.0, size = form.file.data.len())
+)]
+pub async fn upload_import(
+    MultipartForm(form): MultipartForm<ImportForm>,
+    pool: web::Data<sqlx::PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mode: ImportMode = form.mode.parse().map_err(e400)?;
+    let summary = match import_subscribers(
+        &pool,
+        email_client.into_inner(),
+        &base_url.0,
+        &hmac_secret,
+        mode,
+        &form.file.data,
+    )
+    .await
+    {
+        Ok(summary) => summary,
+        Err(ImportError::InvalidFile(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/imports"));
+        }
+        Err(e @ ImportError::UnexpectedError(_)) => return Err(e500(e)),
+    };
+    FlashMessage::info(format!(
+        "Imported {} of {} rows: {} rejected, {} duplicates.",
+        summary.n_imported, summary.n_rows, summary.n_rejected, summary.n_duplicates
+    ))
+    .send();
+    Ok(see_other("/admin/imports"))
+}
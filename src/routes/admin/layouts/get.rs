@@ -0,0 +1,202 @@
+use crate::email_layout::{
+    get_layout, get_layouts, EmailLayout, BUILT_IN_LAYOUT, LAYOUT_HINT_HTML,
+};
+use crate::utils::{e404, e500, escape_html, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub async fn layouts_page(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let layouts = get_layouts(pool.get_ref()).await.map_err(e500)?;
+    let mut layouts_html = String::new();
+    for layout in &layouts {
+        writeln!(
+            layouts_html,
+            r#"<li><a href="/admin/layouts/{id}/edit">{}</a>{} - <a href="/admin/layouts/{id}/preview">Preview</a></li>"#,
+            escape_html(&layout.name),
+            if layout.is_default { " (default)" } else { "" },
+            id = layout.layout_id,
+        )
+        .unwrap();
+    }
+    // The built-in layout is the default one until another is picked
+    let built_in_is_default = !layouts.iter().any(|l| l.is_default);
+    writeln!(
+        layouts_html,
+        r#"<li>Built-in{} - <a href="/admin/layouts/built-in/preview">Preview</a></li>"#,
+        if built_in_is_default {
+            " (default)"
+        } else {
+            ""
+        },
+    )
+    .unwrap();
+    let fields_html = layout_fields_html("", "", BUILT_IN_LAYOUT, false);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Email layouts</title>
+</head>
+<body>
+    {msg_html}
+    <p>Newsletter issues and transactional emails, such as the confirmation email, are wrapped in a layout.</p>
+    <ul>
+        {layouts_html}
+    </ul>
+    <h2>New layout</h2>
+    <form action="/admin/layouts" method="post">
+        {fields_html}
+        <button type="submit">Save layout</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+pub async fn edit_layout_form(
+    pool: web::Data<PgPool>,
+    layout_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let Some(layout) = get_layout(pool.get_ref(), layout_id).await.map_err(e500)? else {
+        FlashMessage::error("There is no layout with the provided id.").send();
+        return Ok(see_other("/admin/layouts"));
+    };
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let fields_html = layout_fields_html(
+        &layout.name,
+        &layout.postal_address,
+        &layout.template,
+        layout.is_default,
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit layout</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/layouts/{layout_id}/edit" method="post">
+        {fields_html}
+        <button type="submit">Save layout</button>
+    </form>
+    <p><a href="/admin/layouts/{layout_id}/preview">Preview</a></p>
+    <form action="/admin/layouts/{layout_id}/delete" method="post">
+        <button type="submit">Delete layout</button>
+    </form>
+    <p><a href="/admin/layouts">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+pub async fn preview_layout(
+    pool: web::Data<PgPool>,
+    layout_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let layout = get_layout(pool.get_ref(), layout_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no layout with the provided id."))?;
+    let preview = layout.compile().map_err(e500)?.preview();
+    Ok(preview_page(
+        &layout.name,
+        preview,
+        &format!("/admin/layouts/{layout_id}/edit"),
+    ))
+}
+
+pub async fn preview_built_in_layout() -> HttpResponse {
+    preview_page(
+        "Built-in",
+        EmailLayout::default().preview(),
+        "/admin/layouts",
+    )
+}
+
+/// Sample content in a layout, the way a newsletter issue would look.
+fn preview_page(name: &str, preview: Result<String, String>, back_link: &str) -> HttpResponse {
+    let (error_html, html) = match preview {
+        Ok(html) => (String::new(), html),
+        Err(e) => (
+            format!(r#"<p><i>{}</i></p>"#, escape_html(&e)),
+            String::new(),
+        ),
+    };
+    let name = escape_html(name);
+    // Rendered in a sandboxed frame, away from the admin pages
+    let html = escape_html(&html);
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview: {name}</title>
+</head>
+<body>
+    <h1>{name}</h1>
+    {error_html}
+    <iframe sandbox srcdoc="{html}" width="800" height="600"></iframe>
+    <p><a href="{back_link}">&lt;- Back</a></p>
+</body>
+</html>"#
+        ))
+}
+
+fn layout_fields_html(
+    name: &str,
+    postal_address: &str,
+    template: &str,
+    is_default: bool,
+) -> String {
+    format!(
+        r#"<label>Name:<br>
+            <input type="text" name="name" value="{}">
+        </label>
+        <br>
+        <label>Postal address:<br>
+            <textarea name="postal_address" rows="4" cols="50">{}</textarea>
+        </label>
+        <br>
+        <label>Template:<br>
+            <textarea name="template" rows="30" cols="100">{}</textarea>
+        </label>
+        <br>
+        {LAYOUT_HINT_HTML}
+        <label>
+            <input type="checkbox" name="is_default" value="on"{}>
+            Use it for every email that does not pick another layout
+        </label>
+        <br>"#,
+        escape_html(name),
+        escape_html(postal_address),
+        escape_html(template),
+        if is_default { " checked" } else { "" }
+    )
+}
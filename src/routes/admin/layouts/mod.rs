@@ -0,0 +1,4 @@
+mod get;
+pub use get::{edit_layout_form, layouts_page, preview_built_in_layout, preview_layout};
+mod post;
+pub use post::{create_layout, delete_layout, update_layout};
@@ -0,0 +1,155 @@
+use crate::email_layout::EmailLayout;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    #[serde(default)]
+    postal_address: String,
+    template: String,
+    /// Only sent when the checkbox is ticked.
+    is_default: Option<String>,
+}
+
+impl FormData {
+    /// Whatever would stop the layout from being used, before saving it.
+    fn check(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("A layout needs a name.".into());
+        }
+        EmailLayout::validate(&self.template, self.postal_address.trim())
+    }
+}
+
+#[tracing::instrument(name = "Saving a layout.", skip(pool, form), fields(name = %form.name))]
+pub async fn create_layout(
+    pool: web::Data<PgPool>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(e) = form.check() {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/layouts"));
+    }
+    let layout_id = Uuid::new_v4();
+    let mut tx = pool.begin().await.map_err(e500)?;
+    if form.is_default.is_some() {
+        unset_default_layout(&mut tx).await.map_err(e500)?;
+    }
+    let name = form.name.trim();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_layouts (layout_id, name, template, postal_address, is_default)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        layout_id,
+        name,
+        form.template,
+        form.postal_address.trim(),
+        form.is_default.is_some()
+    );
+    let n_inserted = tx
+        .execute(query)
+        .await
+        .context("Failed to store the layout.")
+        .map_err(e500)?
+        .rows_affected();
+    if n_inserted == 0 {
+        FlashMessage::error(format!("There already is a layout named {name}.")).send();
+        return Ok(see_other("/admin/layouts"));
+    }
+    tx.commit().await.map_err(e500)?;
+    FlashMessage::info(format!("The layout {name} has been saved.")).send();
+    Ok(see_other("/admin/layouts"))
+}
+
+#[tracing::instrument(name = "Updating a layout.", skip(pool, form), fields(name = %form.name))]
+pub async fn update_layout(
+    pool: web::Data<PgPool>,
+    layout_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let edit_page = format!("/admin/layouts/{layout_id}/edit");
+    if let Err(e) = form.check() {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&edit_page));
+    }
+    let mut tx = pool.begin().await.map_err(e500)?;
+    if form.is_default.is_some() {
+        unset_default_layout(&mut tx).await.map_err(e500)?;
+    }
+    let name = form.name.trim();
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_layouts
+        SET name = $2, template = $3, postal_address = $4, is_default = $5, updated_at = now()
+        WHERE layout_id = $1
+        "#,
+        layout_id,
+        name,
+        form.template,
+        form.postal_address.trim(),
+        form.is_default.is_some()
+    );
+    let n_updated = match tx.execute(query).await {
+        Ok(outcome) => outcome.rows_affected(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            FlashMessage::error(format!("There already is a layout named {name}.")).send();
+            return Ok(see_other(&edit_page));
+        }
+        Err(e) => return Err(e500(e)),
+    };
+    if n_updated == 0 {
+        FlashMessage::error("There is no layout with the provided id.").send();
+        return Ok(see_other("/admin/layouts"));
+    }
+    tx.commit().await.map_err(e500)?;
+    FlashMessage::info(format!("The layout {name} has been saved.")).send();
+    Ok(see_other(&edit_page))
+}
+
+/// Issues that were to go out in the layout get the default one instead.
+#[tracing::instrument(name = "Deleting a layout.", skip(pool))]
+pub async fn delete_layout(
+    pool: web::Data<PgPool>,
+    layout_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM email_layouts
+        WHERE layout_id = $1
+        RETURNING name
+        "#,
+        layout_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to delete the layout.")
+    .map_err(e500)?;
+    match deleted {
+        Some(layout) => {
+            FlashMessage::info(format!("The layout {} has been deleted.", layout.name)).send()
+        }
+        None => FlashMessage::error("There is no layout with the provided id.").send(),
+    }
+    Ok(see_other("/admin/layouts"))
+}
+
+/// There is at most one default layout: make room for another one.
+async fn unset_default_layout(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_layouts SET is_default = false WHERE is_default
+        "#
+    );
+    tx.execute(query).await?;
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+struct ListSummary {
+    name: String,
+    description: String,
+    n_confirmed_members: i64,
+}
+
+pub async fn lists_form(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let mut lists_html = String::new();
+    for list in get_list_summaries(&pool).await.map_err(e500)? {
+        writeln!(
+            lists_html,
+            r#"<li>{} ({} confirmed members) {}</li>"#,
+            escape_html(&list.name),
+            list.n_confirmed_members,
+            escape_html(&list.description)
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Lists</title>
+</head>
+<body>
+    {msg_html}
+    <ul>
+        {lists_html}
+    </ul>
+    <form action="/admin/lists" method="post">
+        <label>Name
+            <input type="text" name="name">
+        </label>
+        <br>
+        <label>Description
+            <input type="text" name="description">
+        </label>
+        <br>
+        <button type="submit">Create list</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_list_summaries(pool: &PgPool) -> Result<Vec<ListSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.name,
+            l.description,
+            COUNT(s.id) AS "n_confirmed_members!"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls ON ls.list_id = l.list_id
+        LEFT JOIN subscriptions s ON s.id = ls.subscriber_id AND s.status = 'confirmed'
+        GROUP BY l.list_id
+        ORDER BY l.name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
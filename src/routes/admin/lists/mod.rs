@@ -0,0 +1,4 @@
+mod get;
+pub use get::lists_form;
+mod post;
+pub use post::create_list;
@@ -0,0 +1,44 @@
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    description: String,
+}
+
+#[tracing::instrument(name = "Creating a list.", skip(pool, form), fields(name = %form.name))]
+pub async fn create_list(
+    pool: web::Data<PgPool>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("A list needs a name.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, name, description)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        form.description.trim()
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted == 0 {
+        FlashMessage::error(format!("There already is a list named {name}.")).send();
+    } else {
+        FlashMessage::info(format!("The list {name} has been created.")).send();
+    }
+    Ok(see_other("/admin/lists"))
+}
@@ -1,8 +1,22 @@
 mod dashboard;
+mod failures;
+mod imports;
+mod layouts;
+mod lists;
 mod logout;
 mod newsletter;
 mod password;
+mod segments;
+mod subscribers;
+mod tags;
 pub use dashboard::*;
+pub use failures::*;
+pub use imports::*;
+pub use layouts::*;
+pub use lists::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use segments::*;
+pub use subscribers::*;
+pub use tags::*;
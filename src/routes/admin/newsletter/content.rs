@@ -0,0 +1,57 @@
+use crate::markdown::render_markdown;
+
+/// The content of an issue, as it is stored.
+pub(super) struct IssueBody {
+    pub markdown_content: Option<String>,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+impl IssueBody {
+    /// Fill in whichever of the HTML and plain text contents the editor left
+    /// empty from the Markdown body, if there is one. Explicit contents win.
+    pub fn compose(markdown_content: &str, html_content: String, text_content: String) -> Self {
+        if markdown_content.trim().is_empty() {
+            return Self {
+                markdown_content: None,
+                html_content,
+                text_content,
+            };
+        }
+        let rendered = render_markdown(markdown_content);
+        Self {
+            markdown_content: Some(markdown_content.to_owned()),
+            html_content: if html_content.trim().is_empty() {
+                rendered.html
+            } else {
+                html_content
+            },
+            text_content: if text_content.trim().is_empty() {
+                rendered.text
+            } else {
+                text_content
+            },
+        }
+    }
+
+    /// The HTML and plain text contents to fill the edit form with: the ones
+    /// generated from the Markdown body are left empty, to keep following it.
+    pub fn overrides(&self) -> (&str, &str) {
+        let Some(markdown_content) = &self.markdown_content else {
+            return (&self.html_content, &self.text_content);
+        };
+        let rendered = render_markdown(markdown_content);
+        (
+            if rendered.html == self.html_content {
+                ""
+            } else {
+                &self.html_content
+            },
+            if rendered.text == self.text_content {
+                ""
+            } else {
+                &self.text_content
+            },
+        )
+    }
+}
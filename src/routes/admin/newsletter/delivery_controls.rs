@@ -0,0 +1,225 @@
+use crate::domain::IssueStatus;
+use crate::issue_delivery_worker::notify_delivery_workers;
+use crate::startup::DeliveryQueueChannel;
+use crate::utils::{e404, e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Stop the workers from picking up the issue's remaining deliveries. A batch
+/// already handed to the email provider still goes out.
+#[tracing::instrument(skip(pool))]
+pub async fn pause_newsletter_issue(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'paused', updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'sending'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to pause a newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("Only issues being sent can be paused.").send();
+    } else {
+        FlashMessage::info("Delivery of the newsletter issue has been paused.").send();
+    }
+    Ok(see_other(&format!(
+        "/admin/newsletters/{newsletter_issue_id}"
+    )))
+}
+
+#[tracing::instrument(skip(pool, queue_channel))]
+pub async fn resume_newsletter_issue(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+    queue_channel: web::Data<DeliveryQueueChannel>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut tx = pool.begin().await.map_err(e500)?;
+    // The last batch in flight when the issue was paused may have emptied its queue
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = CASE
+                WHEN EXISTS (
+                    SELECT 1 FROM issue_delivery_queue q
+                    WHERE q.newsletter_issue_id = i.newsletter_issue_id
+                ) THEN 'sending'
+                ELSE 'sent'
+            END,
+            updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'paused'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to resume a newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("Only paused issues can be resumed.").send();
+    } else {
+        notify_delivery_workers(&mut tx, &queue_channel.0)
+            .await
+            .context("Failed to notify the delivery workers.")
+            .map_err(e500)?;
+        FlashMessage::info("Delivery of the newsletter issue has been resumed.").send();
+    }
+    tx.commit().await.map_err(e500)?;
+    Ok(see_other(&format!(
+        "/admin/newsletters/{newsletter_issue_id}"
+    )))
+}
+
+/// Cancel an issue that has not gone out to everyone yet, whether it is
+/// scheduled, being sent or paused. Deliveries still in the queue are dropped.
+#[tracing::instrument(skip(pool))]
+pub async fn cancel_newsletter_issue(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue_page = format!("/admin/newsletters/{newsletter_issue_id}");
+    let mut tx = pool.begin().await.map_err(e500)?;
+    let status = lock_issue(&mut tx, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no newsletter issue with the provided id."))?;
+    match status {
+        IssueStatus::Scheduled => {
+            mark_issue_as_cancelled(&mut tx, newsletter_issue_id, None)
+                .await
+                .map_err(e500)?;
+            FlashMessage::info("The scheduled newsletter issue has been cancelled.").send();
+        }
+        IssueStatus::Sending | IssueStatus::Paused => {
+            let counts = drop_remaining_deliveries(&mut tx, newsletter_issue_id)
+                .await
+                .map_err(e500)?;
+            mark_issue_as_cancelled(&mut tx, newsletter_issue_id, Some(counts))
+                .await
+                .map_err(e500)?;
+            FlashMessage::info(format!(
+                "The newsletter issue has been cancelled: {} emails had already been sent, \
+                {} will not be.",
+                counts.n_sent, counts.n_cancelled
+            ))
+            .send();
+        }
+        IssueStatus::Draft | IssueStatus::Sent | IssueStatus::Cancelled => {
+            FlashMessage::error("Only issues that are not fully sent yet can be cancelled.").send();
+        }
+    }
+    tx.commit().await.map_err(e500)?;
+    Ok(see_other(&issue_page))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CancellationCounts {
+    n_sent: i32,
+    n_cancelled: i32,
+}
+
+/// Lock the issue until the end of the transaction and return its status.
+///
+/// `NO KEY UPDATE` still serialises status changes, but lets a worker finishing
+/// a batch log its deliveries meanwhile: the foreign keys of `issue_deliveries`
+/// and `issue_delivery_failures` take a `KEY SHARE` lock on the issue, which
+/// `FOR UPDATE` would block while we wait for that very batch.
+#[tracing::instrument(skip(tx))]
+async fn lock_issue(
+    tx: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueStatus>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR NO KEY UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .context("Failed to fetch the status of a newsletter issue.")?;
+    match record {
+        Some(r) => Ok(Some(r.status.parse().map_err(anyhow::Error::msg)?)),
+        None => Ok(None),
+    }
+}
+
+/// Empty the issue's delivery queue. Tasks a worker is sending right now are
+/// locked: we wait for that batch to be done before removing what is left.
+#[tracing::instrument(skip(tx))]
+async fn drop_remaining_deliveries(
+    tx: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<CancellationCounts, anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    );
+    let n_cancelled = tx
+        .execute(query)
+        .await
+        .context("Failed to remove the remaining deliveries of a newsletter issue.")?
+        .rows_affected();
+    let n_sent = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT subscriber_email) AS "n_sent!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 AND status = 'sent'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .context("Failed to count the deliveries of a newsletter issue.")?
+    .n_sent;
+    Ok(CancellationCounts {
+        n_sent: i32::try_from(n_sent)?,
+        n_cancelled: i32::try_from(n_cancelled)?,
+    })
+}
+
+#[tracing::instrument(skip(tx))]
+async fn mark_issue_as_cancelled(
+    tx: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    counts: Option<CancellationCounts>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled',
+            cancelled_at = now(),
+            updated_at = now(),
+            n_sent_before_cancellation = $2,
+            n_cancelled_deliveries = $3
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        counts.map(|c| c.n_sent),
+        counts.map(|c| c.n_cancelled)
+    );
+    tx.execute(query)
+        .await
+        .context("Failed to mark a newsletter issue as cancelled.")?;
+    Ok(())
+}
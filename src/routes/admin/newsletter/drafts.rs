@@ -0,0 +1,292 @@
+use super::content::IssueBody;
+use super::get::{click_tracking_html, target_lists_html, target_segment_html, TEMPLATE_HINT_HTML};
+use super::test_send::{get_test_sends, test_sends_html};
+use crate::domain::ClickTracking;
+use crate::email_layout::{get_layouts, layout_select_html, IssueLayout};
+use crate::lists::get_lists;
+use crate::segments::get_segments;
+use crate::utils::{e400, e500, escape_html, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub(super) struct Draft {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub markdown_content: Option<String>,
+    pub use_layout: bool,
+    pub layout_id: Option<Uuid>,
+    pub click_tracking: String,
+}
+
+/// Drafts are saved as they are: nothing is validated until they get published.
+#[derive(Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    #[serde(default)]
+    markdown_content: String,
+    #[serde(default)]
+    html_content: String,
+    #[serde(default)]
+    text_content: String,
+    /// Left empty for the default layout.
+    #[serde(default)]
+    layout: String,
+    /// Clicks are not tracked unless asked for.
+    click_tracking: Option<String>,
+}
+
+impl DraftFormData {
+    fn into_parts(self) -> Result<(String, IssueBody, IssueLayout, ClickTracking), String> {
+        let layout = self.layout.parse()?;
+        let click_tracking = parse_click_tracking(self.click_tracking.as_deref())?;
+        let body = IssueBody::compose(&self.markdown_content, self.html_content, self.text_content);
+        Ok((self.title, body, layout, click_tracking))
+    }
+}
+
+pub(super) fn parse_click_tracking(click_tracking: Option<&str>) -> Result<ClickTracking, String> {
+    click_tracking.map_or(Ok(ClickTracking::Off), str::parse)
+}
+
+#[tracing::instrument(name = "Saving a newsletter draft.", skip(pool, form))]
+pub async fn create_draft(
+    pool: web::Data<PgPool>,
+    form: web::Form<DraftFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (title, body, layout, click_tracking) = form.into_inner().into_parts().map_err(e400)?;
+    let mut tx = pool.begin().await.map_err(e500)?;
+    let newsletter_issue_id =
+        insert_newsletter_issue(&mut tx, &title, &body, layout, click_tracking)
+            .await
+            .context("Failed to store the newsletter draft.")
+            .map_err(e500)?;
+    tx.commit().await.map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/{newsletter_issue_id}/edit"
+    )))
+}
+
+pub async fn edit_draft_form(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let Some(draft) = get_draft(pool.get_ref(), newsletter_issue_id)
+        .await
+        .context("Failed to fetch the newsletter draft.")
+        .map_err(e500)?
+    else {
+        FlashMessage::error("Only drafts can be edited.").send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{newsletter_issue_id}"
+        )));
+    };
+    let test_sends = get_test_sends(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let test_sends_html = test_sends_html(newsletter_issue_id, &test_sends);
+    let lists_html = target_lists_html(&get_lists(pool.get_ref()).await.map_err(e500)?);
+    let segment_html = target_segment_html(&get_segments(pool.get_ref()).await.map_err(e500)?);
+    let layout_html = layout_select_html(
+        &get_layouts(pool.get_ref()).await.map_err(e500)?,
+        IssueLayout::from_columns(draft.use_layout, draft.layout_id),
+    );
+    let click_tracking_html = click_tracking_html(draft.click_tracking.parse().map_err(e500)?);
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let body = IssueBody {
+        markdown_content: draft.markdown_content,
+        html_content: draft.html_content,
+        text_content: draft.text_content,
+    };
+    let (html_content, text_content) = body.overrides();
+    let title = escape_html(&draft.title);
+    let markdown_content = escape_html(body.markdown_content.as_deref().unwrap_or_default());
+    let text_content = escape_html(text_content);
+    let html_content = escape_html(html_content);
+    let idempotency_key = Uuid::new_v4();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit draft</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/newsletters/{newsletter_issue_id}/edit" method="post">
+        <label>Newsletter Title:<br>
+            <input type="text" name="title" value="{title}">
+        </label>
+        <br>
+        <label>Markdown Content:<br>
+            <textarea name="markdown_content" rows="20" cols="50">{markdown_content}</textarea>
+        </label>
+        <br>
+        <label>Plain Text Content (leave empty to generate it from the Markdown):<br>
+            <textarea name="text_content" rows="20" cols="50">{text_content}</textarea>
+        </label>
+        <br>
+        <label>HTML Content (leave empty to generate it from the Markdown):<br>
+            <textarea name="html_content" rows="20" cols="50">{html_content}</textarea>
+        </label>
+        <br>
+        {TEMPLATE_HINT_HTML}
+        {layout_html}
+        {click_tracking_html}
+        <button type="submit">Save draft</button>
+    </form>
+    <p><a href="/admin/newsletters/{newsletter_issue_id}/preview">Preview</a></p>
+    <form action="/admin/newsletters/{newsletter_issue_id}/publish" method="post">
+        <label>Send at (UTC, leave empty to send now):<br>
+            <input type="datetime-local" name="scheduled_for">
+        </label>
+        {lists_html}
+        {segment_html}
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Publish</button>
+    </form>
+    <form action="/admin/newsletters/{newsletter_issue_id}/delete" method="post">
+        <button type="submit">Delete draft</button>
+    </form>
+    {test_sends_html}
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[tracing::instrument(name = "Updating a newsletter draft.", skip(pool, form))]
+pub async fn update_draft(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let (title, body, layout, click_tracking) = form.into_inner().into_parts().map_err(e400)?;
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, markdown_content = $5,
+            use_layout = $6, layout_id = $7, click_tracking = $8, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id,
+        title,
+        body.text_content,
+        body.html_content,
+        body.markdown_content,
+        layout.use_layout(),
+        layout.layout_id(),
+        click_tracking.as_str()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("Only drafts can be edited.").send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{newsletter_issue_id}"
+        )));
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/{newsletter_issue_id}/edit"
+    )))
+}
+
+#[tracing::instrument(name = "Deleting a newsletter draft.", skip(pool))]
+pub async fn delete_draft(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete the newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_deleted == 0 {
+        FlashMessage::error("Only drafts can be deleted.").send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{newsletter_issue_id}"
+        )));
+    }
+    FlashMessage::info("The draft has been deleted.").send();
+    Ok(see_other("/admin/newsletters"))
+}
+
+#[tracing::instrument(skip_all)]
+pub(super) async fn insert_newsletter_issue(
+    tx: &mut Transaction<'_, Postgres>,
+    title: &str,
+    body: &IssueBody,
+    layout: IssueLayout,
+    click_tracking: ClickTracking,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, markdown_content,
+            use_layout, layout_id, click_tracking, status
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'draft')
+        "#,
+        newsletter_issue_id,
+        title,
+        body.text_content,
+        body.html_content,
+        body.markdown_content,
+        layout.use_layout(),
+        layout.layout_id(),
+        click_tracking.as_str()
+    );
+    tx.execute(query).await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Fetch an issue as long as it is still a draft. Within a transaction it stays
+/// locked, and a draft, until the transaction ends.
+#[tracing::instrument(skip(executor))]
+pub(super) async fn get_draft<'c>(
+    executor: impl PgExecutor<'c>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Draft>, sqlx::Error> {
+    sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT
+            title, text_content, html_content, markdown_content, use_layout, layout_id,
+            click_tracking
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(executor)
+    .await
+}
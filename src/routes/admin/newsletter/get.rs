@@ -1,14 +1,145 @@
+use super::schedule::format_send_time;
+use crate::domain::{ClickTracking, IssueStatus};
+use crate::email_layout::{get_layouts, layout_select_html, IssueLayout};
+use crate::lists::{get_lists, list_checkboxes_html, MailingList};
+use crate::segments::{get_segments, SavedSegment};
+use crate::utils::{e500, escape_html};
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    published_at: Option<String>,
+    scheduled_for: Option<DateTime<Utc>>,
+}
+
+impl IssueSummary {
+    fn state(&self, status: IssueStatus) -> String {
+        match (status, &self.published_at, self.scheduled_for) {
+            (IssueStatus::Scheduled, _, Some(scheduled_for)) => {
+                format!("scheduled for {}", format_send_time(scheduled_for))
+            }
+            (_, Some(published_at), _) => format!("{status}, published at {published_at}"),
+            _ => status.to_string(),
+        }
+    }
+}
+
+/// Where to send an issue, for the publish forms. Nothing to choose from
+/// until lists are created.
+pub(super) fn target_lists_html(lists: &[MailingList]) -> String {
+    if lists.is_empty() {
+        return String::new();
+    }
+    format!(
+        r#"<fieldset>
+            <legend>Send to (leave unticked to send to every subscriber)</legend>
+            {}
+        </fieldset>"#,
+        list_checkboxes_html(lists, &[])
+    )
+}
+
+/// What the content of an issue can refer to, under the content fields of the
+/// publish forms.
+pub(super) const TEMPLATE_HINT_HTML: &str = "<p><small>Both contents can use \
+    <code>{{ subscriber.name }}</code>, <code>{{ subscriber.email }}</code>, \
+    <code>{{ issue.title }}</code>, <code>{{ unsubscribe_url }}</code> and \
+    <code>{{ preferences_url }}</code>, conditionals such as \
+    <code>{% if ... %}...{% endif %}</code> and default values such as \
+    <code>{{ subscriber.name | default(&quot;friend&quot;) }}</code>.</small></p>";
+
+/// Which links of an issue to track, for the publish forms.
+pub(super) fn click_tracking_html(selected: ClickTracking) -> String {
+    let mut options_html = String::new();
+    for (setting, label) in [
+        (ClickTracking::Html, "Links of the HTML version"),
+        (ClickTracking::HtmlAndText, "Links of both versions"),
+        (ClickTracking::Off, "None"),
+    ] {
+        writeln!(
+            options_html,
+            r#"<option value="{setting}"{}>{label}</option>"#,
+            if setting == selected { " selected" } else { "" }
+        )
+        .unwrap();
+    }
+    format!(
+        r#"<label>Track clicks on:<br>
+            <select name="click_tracking">
+                {options_html}
+            </select>
+        </label>
+        <br>"#
+    )
+}
+
+/// The segment to send an issue to, for the publish forms. Nothing to choose
+/// from until segments are saved.
+pub(super) fn target_segment_html(segments: &[SavedSegment]) -> String {
+    if segments.is_empty() {
+        return String::new();
+    }
+    let mut options_html = String::new();
+    for segment in segments {
+        writeln!(
+            options_html,
+            r#"<option value="{}">{} ({})</option>"#,
+            segment.segment_id,
+            escape_html(&segment.name),
+            escape_html(&segment.definition)
+        )
+        .unwrap();
+    }
+    format!(
+        r#"<label>Only send to the segment:<br>
+            <select name="segment_id">
+                <option value="">Everybody</option>
+                {options_html}
+            </select>
+        </label>
+        <br>"#
+    )
+}
 
 pub async fn publish_newsletter_form(
+    pool: web::Data<PgPool>,
     flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut error_html = String::new();
     for m in flash_messages.iter() {
         error_html.push_str(&format!(r#"<p style="color: red;">{}</p>"#, m.content()));
     }
+    let mut issues_html = String::new();
+    for issue in get_recent_issues(&pool).await.map_err(e500)? {
+        let status: IssueStatus = issue.status.parse().map_err(e500)?;
+        // Drafts open in the editor, everything else on its delivery progress page
+        let link = match status {
+            IssueStatus::Draft => format!("/admin/newsletters/{}/edit", issue.newsletter_issue_id),
+            _ => format!("/admin/newsletters/{}", issue.newsletter_issue_id),
+        };
+        issues_html.push_str(&format!(
+            r#"<li><a href="{}">{}</a> ({})</li>"#,
+            link,
+            escape_html(&issue.title),
+            issue.state(status)
+        ));
+    }
+    let lists_html = target_lists_html(&get_lists(pool.get_ref()).await.map_err(e500)?);
+    let segment_html = target_segment_html(&get_segments(pool.get_ref()).await.map_err(e500)?);
+    let layout_html = layout_select_html(
+        &get_layouts(pool.get_ref()).await.map_err(e500)?,
+        IssueLayout::Default,
+    );
+    let click_tracking_html = click_tracking_html(ClickTracking::Html);
     let idempotency_key = uuid::Uuid::new_v4();
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -30,7 +161,16 @@ pub async fn publish_newsletter_form(
             >
         </label>
         <br>
-        <label>Plain Text Content:<br>
+        <label>Markdown Content:<br>
+            <textarea
+                placeholder="Enter the content in Markdown"
+                name="markdown_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <label>Plain Text Content (leave empty to generate it from the Markdown):<br>
             <textarea
                 placeholder="Enter the content in plain text content"
                 name="text_content"
@@ -39,7 +179,7 @@ pub async fn publish_newsletter_form(
             ></textarea>
         </label>
         <br>
-        <label>HTML Content:<br>
+        <label>HTML Content (leave empty to generate it from the Markdown):<br>
             <textarea
                 placeholder="Enter the content in HTML format"
                 name="html_content"
@@ -47,11 +187,43 @@ pub async fn publish_newsletter_form(
                 cols="50"
             ></textarea>
         </label>
+        <br>
+        {TEMPLATE_HINT_HTML}
+        {layout_html}
+        {click_tracking_html}
+        <label>Send at (UTC, leave empty to send now):<br>
+            <input type="datetime-local" name="scheduled_for">
+        </label>
+        <br>
+        {lists_html}
+        {segment_html}
         <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
         <button type="submit">Send</button>
+        <button type="submit" formaction="/admin/newsletters/drafts">Save as draft</button>
     </form>
+    <h2>Recent issues</h2>
+    <ul>
+        {issues_html}
+    </ul>
     <p><a href="/admin/dashboard">&lt;- Back</a></p>
 </body>
 </html>"#
         )))
 }
+
+#[tracing::instrument(skip_all)]
+async fn get_recent_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, status, published_at, scheduled_for
+        FROM newsletter_issues
+        ORDER BY updated_at DESC
+        LIMIT 20
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch recent newsletter issues.")?;
+    Ok(issues)
+}
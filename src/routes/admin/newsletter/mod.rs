@@ -1,4 +1,19 @@
+mod content;
+mod delivery_controls;
+mod drafts;
 mod get;
 mod post;
+mod preview;
+mod progress;
+mod schedule;
+mod test_send;
+pub use delivery_controls::{
+    cancel_newsletter_issue, pause_newsletter_issue, resume_newsletter_issue,
+};
+pub use drafts::{create_draft, delete_draft, edit_draft_form, update_draft};
 pub use get::publish_newsletter_form;
-pub use post::publish_newsletter;
+pub use post::{publish_draft, publish_newsletter};
+pub use preview::preview_newsletter_issue;
+pub use progress::newsletter_issue_progress;
+pub use schedule::reschedule_newsletter_issue;
+pub use test_send::send_test_newsletter;
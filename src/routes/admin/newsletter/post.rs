@@ -1,9 +1,19 @@
+use super::content::IssueBody;
+use super::drafts::{get_draft, insert_newsletter_issue, parse_click_tracking};
+use super::schedule::{format_send_time, parse_send_time};
 use crate::authentication::UserId;
+use crate::email_layout::IssueLayout;
 use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
-use crate::utils::{e400, e500, see_other};
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, notify_delivery_workers};
+use crate::issue_template::IssueTemplate;
+use crate::lists::{parse_list_ids, set_issue_lists};
+use crate::segments::set_issue_segment;
+use crate::startup::DeliveryQueueChannel;
+use crate::utils::{e400, e500, see_other, split_repeated_field};
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
@@ -11,23 +21,68 @@ use uuid::Uuid;
 #[derive(Deserialize)]
 pub struct FormData {
     title: String,
+    /// Fills in whichever of the HTML and plain text contents are left empty.
+    #[serde(default)]
+    markdown_content: String,
+    #[serde(default)]
     html_content: String,
+    #[serde(default)]
     text_content: String,
+    /// Left empty for the default layout.
+    #[serde(default)]
+    layout: String,
+    /// Clicks are not tracked unless asked for.
+    click_tracking: Option<String>,
     idempotency_key: String,
+    /// Left empty to send the issue right away.
+    scheduled_for: Option<String>,
+    /// Left empty to send the issue to everybody on its lists.
+    segment_id: Option<String>,
 }
 
-#[tracing::instrument(name = "Publishing a newsletter.", skip(pool, form, user_id), fields(user_id = %*user_id))]
+#[tracing::instrument(name = "Publishing a newsletter.", skip(pool, form, user_id, queue_channel), fields(user_id = %*user_id))]
 pub async fn publish_newsletter(
     pool: web::Data<PgPool>,
-    form: web::Form<FormData>,
+    form: web::Form<Vec<(String, String)>>,
     user_id: web::ReqData<UserId>,
+    queue_channel: web::Data<DeliveryQueueChannel>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let FormData {
-        title,
-        html_content,
-        text_content,
-        idempotency_key,
-    } = form.into_inner();
+    let (
+        FormData {
+            title,
+            markdown_content,
+            html_content,
+            text_content,
+            layout,
+            click_tracking,
+            idempotency_key,
+            scheduled_for,
+            segment_id,
+        },
+        list_ids,
+    ) = split_repeated_field(form.into_inner(), "list_id").map_err(e400)?;
+    let list_ids = parse_list_ids(&list_ids).map_err(e400)?;
+    let segment_id = parse_optional_segment_id(segment_id).map_err(e400)?;
+    let layout: IssueLayout = layout.parse().map_err(e400)?;
+    let click_tracking = parse_click_tracking(click_tracking.as_deref()).map_err(e400)?;
+    let scheduled_for = match parse_optional_send_time(scheduled_for) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+    let body = IssueBody::compose(&markdown_content, html_content, text_content);
+    if body.html_content.trim().is_empty() || body.text_content.trim().is_empty() {
+        return Err(e400(
+            "A newsletter issue needs HTML content and plain text content, or Markdown content.",
+        ));
+    }
+    // Rather than discovering a broken template halfway through the deliveries
+    if let Err(e) = IssueTemplate::validate(&title, &body.html_content, &body.text_content) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/newsletters"));
+    }
 
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
     let mut tx = match try_processing(&pool, &idempotency_key, **user_id)
@@ -44,59 +99,239 @@ pub async fn publish_newsletter(
         }
     };
 
-    let issue_id = insert_newsletter_issue(&mut tx, &title, &text_content, &html_content)
+    let issue_id = insert_newsletter_issue(&mut tx, &title, &body, layout, click_tracking)
         .await
         .context("Failed to store newsletter issue details")
         .map_err(e500)?;
+    match release_issue(
+        &mut tx,
+        issue_id,
+        &list_ids,
+        segment_id,
+        scheduled_for,
+        &queue_channel.0,
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(ReleaseError::ValidationError(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+        Err(ReleaseError::UnexpectedError(e)) => return Err(e500(e)),
+    }
 
-    enqueue_delivery_tasks(&mut tx, issue_id)
+    let response = see_other("/admin/newsletters");
+    let response = save_response(tx, &idempotency_key, **user_id, response)
         .await
-        .context("Failed to enqueue delivery tasks")
         .map_err(e500)?;
+    released_message(scheduled_for).send();
+    Ok(response)
+}
 
-    let response = see_other("/admin/newsletters");
+#[derive(Deserialize)]
+pub struct PublishFormData {
+    idempotency_key: String,
+    /// Left empty to send the issue right away.
+    scheduled_for: Option<String>,
+    /// Left empty to send the issue to everybody on its lists.
+    segment_id: Option<String>,
+}
+
+/// Publish a draft, right away or at the requested send time.
+#[tracing::instrument(
+    name = "Publishing a newsletter draft.",
+    skip(pool, form, user_id, queue_channel),
+    fields(user_id = %*user_id)
+)]
+pub async fn publish_draft(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<Vec<(String, String)>>,
+    user_id: web::ReqData<UserId>,
+    queue_channel: web::Data<DeliveryQueueChannel>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let (
+        PublishFormData {
+            idempotency_key,
+            scheduled_for,
+            segment_id,
+        },
+        list_ids,
+    ) = split_repeated_field(form.into_inner(), "list_id").map_err(e400)?;
+    let list_ids = parse_list_ids(&list_ids).map_err(e400)?;
+    let segment_id = parse_optional_segment_id(segment_id).map_err(e400)?;
+    let edit_page = format!("/admin/newsletters/{newsletter_issue_id}/edit");
+    let scheduled_for = match parse_optional_send_time(scheduled_for) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&edit_page));
+        }
+    };
+
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let mut tx = match try_processing(&pool, &idempotency_key, **user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            return Ok(saved_response);
+        }
+    };
+
+    let Some(draft) = get_draft(&mut *tx, newsletter_issue_id)
+        .await
+        .context("Failed to fetch the newsletter draft")
+        .map_err(e500)?
+    else {
+        FlashMessage::error("Only drafts can be published.").send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{newsletter_issue_id}"
+        )));
+    };
+    if draft.title.trim().is_empty()
+        || draft.text_content.trim().is_empty()
+        || draft.html_content.trim().is_empty()
+    {
+        // Dropping the transaction frees the idempotency key for another attempt
+        FlashMessage::error(
+            "A newsletter issue needs a title, HTML content and plain text content \
+            before it can be published.",
+        )
+        .send();
+        return Ok(see_other(&edit_page));
+    }
+    if let Err(e) = IssueTemplate::validate(&draft.title, &draft.html_content, &draft.text_content)
+    {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&edit_page));
+    }
+    match release_issue(
+        &mut tx,
+        newsletter_issue_id,
+        &list_ids,
+        segment_id,
+        scheduled_for,
+        &queue_channel.0,
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(ReleaseError::ValidationError(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&edit_page));
+        }
+        Err(ReleaseError::UnexpectedError(e)) => return Err(e500(e)),
+    }
+
+    let response = see_other(&format!("/admin/newsletters/{newsletter_issue_id}"));
     let response = save_response(tx, &idempotency_key, **user_id, response)
         .await
         .map_err(e500)?;
-    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
-        .send();
+    released_message(scheduled_for).send();
     Ok(response)
 }
 
-#[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
-    tx: &mut Transaction<'_, Postgres>,
-    title: &str,
-    text_content: &str,
-    html_content: &str,
-) -> Result<Uuid, sqlx::Error> {
-    let newsletter_issue_id = Uuid::new_v4();
-    let query = sqlx::query!(
-        r#"
-        INSERT INTO newsletter_issues (newsletter_issue_id, title, text_content, html_content, published_at)
-        VALUES ($1, $2, $3, $4, now())
-        "#,
-        newsletter_issue_id,
-        title,
-        text_content,
-        html_content,
-    );
-    tx.execute(query).await?;
-    Ok(newsletter_issue_id)
+fn parse_optional_send_time(
+    scheduled_for: Option<String>,
+) -> Result<Option<DateTime<Utc>>, String> {
+    match scheduled_for.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(send_time) => parse_send_time(send_time).map(Some),
+    }
+}
+
+fn parse_optional_segment_id(segment_id: Option<String>) -> Result<Option<Uuid>, String> {
+    match segment_id.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(segment_id) => segment_id
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} is not a valid segment id.", segment_id)),
+    }
 }
 
-#[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
-    tx: &mut Transaction<'_, Postgres>,
+fn released_message(scheduled_for: Option<DateTime<Utc>>) -> FlashMessage {
+    match scheduled_for {
+        None => FlashMessage::info(
+            "The newsletter issue has been accepted - emails will go out shortly.",
+        ),
+        Some(send_time) => FlashMessage::info(format!(
+            "The newsletter issue has been scheduled for {}.",
+            format_send_time(send_time)
+        )),
+    }
+}
+
+/// The lists or segment picked in the form may have been deleted since: that
+/// is the admin's to fix, not a server error. Dropping the transaction frees
+/// the idempotency key for another attempt.
+#[derive(Debug, thiserror::Error)]
+enum ReleaseError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Move a draft to `scheduled`, or enqueue it straight away if it has no send time.
+/// It goes to the members of `list_ids`, or to everybody if there are none,
+/// narrowed down to the subscribers in the segment if there is one.
+#[tracing::instrument(skip(tx, queue_channel))]
+async fn release_issue(
+    tx: &mut Transaction<'static, Postgres>,
     newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    let query = sqlx::query!(
-        r#"
-            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
-            SELECT $1, email FROM subscriptions WHERE status = 'confirmed'
-            "#,
-        newsletter_issue_id,
-    );
-    tx.execute(query).await?;
+    list_ids: &[Uuid],
+    segment_id: Option<Uuid>,
+    scheduled_for: Option<DateTime<Utc>>,
+    queue_channel: &str,
+) -> Result<(), ReleaseError> {
+    let n_lists = set_issue_lists(tx, newsletter_issue_id, list_ids)
+        .await
+        .context("Failed to store the lists the newsletter issue is sent to")?;
+    // Sending to everybody is not an acceptable fallback
+    if !list_ids.is_empty() && n_lists == 0 {
+        return Err(ReleaseError::ValidationError(
+            "None of the lists the newsletter issue is sent to exist anymore.".into(),
+        ));
+    }
+    if let Some(segment_id) = segment_id {
+        let exists = set_issue_segment(tx, newsletter_issue_id, segment_id)
+            .await
+            .context("Failed to store the segment the newsletter issue is sent to")?;
+        if !exists {
+            return Err(ReleaseError::ValidationError(
+                "The segment the newsletter issue is sent to does not exist anymore.".into(),
+            ));
+        }
+    }
+    match scheduled_for {
+        // Scheduled issues are enqueued by the scheduler once they are due
+        Some(scheduled_for) => {
+            let query = sqlx::query!(
+                r#"
+                UPDATE newsletter_issues
+                SET status = 'scheduled', scheduled_for = $2, updated_at = now()
+                WHERE newsletter_issue_id = $1
+                "#,
+                newsletter_issue_id,
+                scheduled_for
+            );
+            tx.execute(query)
+                .await
+                .context("Failed to schedule the newsletter issue")?;
+        }
+        None => {
+            enqueue_delivery_tasks(tx, newsletter_issue_id)
+                .await
+                .context("Failed to enqueue delivery tasks")?;
+            notify_delivery_workers(tx, queue_channel)
+                .await
+                .context("Failed to notify the delivery workers")?;
+        }
+    }
     Ok(())
 }
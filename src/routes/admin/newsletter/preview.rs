@@ -0,0 +1,114 @@
+use crate::domain::IssueStatus;
+use crate::email_layout::{get_issue_layout, EmailLayout, FooterLinks};
+use crate::issue_template::{IssueTemplate, TemplateContext};
+use crate::utils::{e404, e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub(super) struct IssueContent {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub status: String,
+}
+
+/// Show an issue the way subscribers will get it, in both its HTML and plain
+/// text versions, rendered for a made-up subscriber.
+pub async fn preview_newsletter_issue(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = get_issue_content(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no newsletter issue with the provided id."))?;
+    let layout = get_issue_layout(pool.get_ref(), newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let rendered = render_sample(&issue, layout.as_ref());
+    let (error_html, (html_content, text_content)) = match rendered {
+        Ok(content) => (String::new(), content),
+        // Show the raw content, for the editor to find the mistake
+        Err(e) => (
+            format!(r#"<p><i>{}</i></p>"#, escape_html(&e)),
+            (issue.html_content, issue.text_content),
+        ),
+    };
+    let title = escape_html(&issue.title);
+    // The HTML body is rendered in a sandboxed frame, away from the admin pages
+    let html_content = escape_html(&html_content);
+    let text_content = escape_html(&text_content);
+    let status: IssueStatus = issue.status.parse().map_err(e500)?;
+    let back_link = if status == IssueStatus::Draft {
+        format!("/admin/newsletters/{newsletter_issue_id}/edit")
+    } else {
+        format!("/admin/newsletters/{newsletter_issue_id}")
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview: {title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    {error_html}
+    <h2>HTML</h2>
+    <iframe sandbox srcdoc="{html_content}" width="800" height="600"></iframe>
+    <h2>Plain text</h2>
+    <pre>{text_content}</pre>
+    <p><a href="{back_link}">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+/// The HTML and plain text contents of an issue for a made-up subscriber,
+/// wrapped in the layout of the issue if it has one.
+pub(super) fn render_sample(
+    issue: &IssueContent,
+    layout: Option<&EmailLayout>,
+) -> Result<(String, String), String> {
+    let context = TemplateContext::sample(&issue.title);
+    let (html_content, text_content) =
+        IssueTemplate::compile(&issue.html_content, &issue.text_content)?.render(&context)?;
+    let html_content = match layout {
+        Some(layout) => layout.wrap(
+            &issue.title,
+            &html_content,
+            Some(&FooterLinks {
+                unsubscribe_url: context.unsubscribe_url,
+                preferences_url: context.preferences_url,
+            }),
+        )?,
+        None => html_content,
+    };
+    Ok((html_content, text_content))
+}
+
+#[tracing::instrument(skip(pool))]
+pub(super) async fn get_issue_content(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueContent>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueContent,
+        r#"
+        SELECT title, text_content, html_content, status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the content of a newsletter issue.")?;
+    Ok(issue)
+}
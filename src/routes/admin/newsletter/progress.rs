@@ -0,0 +1,305 @@
+use super::schedule::{format_send_time, send_time_input_value};
+use super::test_send::{get_test_sends, test_sends_html};
+use crate::domain::IssueStatus;
+use crate::utils::{e404, e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct IssueProgress {
+    title: String,
+    status: String,
+    published_at: Option<String>,
+    scheduled_for: Option<DateTime<Utc>>,
+    cancelled_at: Option<DateTime<Utc>>,
+    n_sent_before_cancellation: Option<i32>,
+    n_cancelled_deliveries: Option<i32>,
+    n_queued: i64,
+    n_sent: i64,
+    n_failed: i64,
+}
+
+struct DeliveryLogEntry {
+    subscriber_email: String,
+    status: String,
+    provider_message_id: Option<String>,
+    error_message: Option<String>,
+    attempted_at: DateTime<Utc>,
+}
+
+struct LinkClicks {
+    url: String,
+    n_clicks: i64,
+    n_readers: i64,
+}
+
+pub async fn newsletter_issue_progress(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let progress = get_issue_progress(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no newsletter issue with the provided id."))?;
+    let log = get_recent_deliveries(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let test_sends = get_test_sends(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let test_sends_html = test_sends_html(newsletter_issue_id, &test_sends);
+    let clicks = get_link_clicks(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+
+    let IssueProgress {
+        title,
+        status,
+        published_at,
+        scheduled_for,
+        cancelled_at,
+        n_sent_before_cancellation,
+        n_cancelled_deliveries,
+        n_queued,
+        n_sent,
+        n_failed,
+    } = progress;
+    let title = escape_html(&title);
+    let status: IssueStatus = status.parse().map_err(e500)?;
+    let state_html = match (status, published_at, cancelled_at, scheduled_for) {
+        (IssueStatus::Draft, _, _, _) => format!(
+            r#"<p>Draft - <a href="/admin/newsletters/{newsletter_issue_id}/edit">edit</a></p>"#
+        ),
+        (IssueStatus::Cancelled, _, Some(cancelled_at), _) => {
+            let mut html = format!("<p>Cancelled at {}</p>", format_send_time(cancelled_at));
+            if let (Some(n_sent), Some(n_cancelled)) =
+                (n_sent_before_cancellation, n_cancelled_deliveries)
+            {
+                write!(
+                    html,
+                    "\n    <p>{n_sent} emails had already been sent, {n_cancelled} were not.</p>"
+                )
+                .unwrap();
+            }
+            html
+        }
+        (IssueStatus::Sending, Some(published_at), _, _) => format!(
+            r#"<p>Status: sending. Published at {published_at}</p>
+    <form action="/admin/newsletters/{newsletter_issue_id}/pause" method="post">
+        <button type="submit">Pause</button>
+    </form>
+    <form action="/admin/newsletters/{newsletter_issue_id}/cancel" method="post">
+        <button type="submit">Cancel</button>
+    </form>"#
+        ),
+        (IssueStatus::Paused, Some(published_at), _, _) => format!(
+            r#"<p>Status: paused. Published at {published_at}</p>
+    <form action="/admin/newsletters/{newsletter_issue_id}/resume" method="post">
+        <button type="submit">Resume</button>
+    </form>
+    <form action="/admin/newsletters/{newsletter_issue_id}/cancel" method="post">
+        <button type="submit">Cancel</button>
+    </form>"#
+        ),
+        (IssueStatus::Scheduled, _, _, Some(scheduled_for)) => format!(
+            r#"<p>Scheduled for {}</p>
+    <form action="/admin/newsletters/{newsletter_issue_id}/reschedule" method="post">
+        <label>Send at (UTC):
+            <input type="datetime-local" name="scheduled_for" value="{}">
+        </label>
+        <button type="submit">Reschedule</button>
+    </form>
+    <form action="/admin/newsletters/{newsletter_issue_id}/cancel" method="post">
+        <button type="submit">Cancel</button>
+    </form>"#,
+            format_send_time(scheduled_for),
+            send_time_input_value(scheduled_for)
+        ),
+        (_, Some(published_at), _, _) => {
+            format!("<p>Status: {status}. Published at {published_at}</p>")
+        }
+        _ => format!("<p>Status: {status}</p>"),
+    };
+    let n_total = n_queued + n_sent + n_failed;
+    let n_done = n_sent + n_failed;
+    let percentage = if n_total == 0 {
+        100
+    } else {
+        n_done * 100 / n_total
+    };
+
+    let mut log_html = String::new();
+    for entry in log {
+        write!(
+            log_html,
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            entry.attempted_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            escape_html(&entry.subscriber_email),
+            entry.status,
+            escape_html(&entry.provider_message_id.unwrap_or_default()),
+            escape_html(&entry.error_message.unwrap_or_default()),
+        )
+        .unwrap();
+    }
+
+    let mut clicks_html = String::new();
+    for link in clicks {
+        write!(
+            clicks_html,
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            escape_html(&link.url),
+            link.n_clicks,
+            link.n_readers,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {msg_html}
+    <h1>{title}</h1>
+    {state_html}
+    <p><a href="/admin/newsletters/{newsletter_issue_id}/preview">Preview</a></p>
+    <progress value="{n_done}" max="{n_total}">{percentage}%</progress> {percentage}%
+    <ul>
+        <li>Queued: {n_queued}</li>
+        <li>Sent: {n_sent}</li>
+        <li>Failed: {n_failed}</li>
+    </ul>
+    <h2>Latest delivery attempts</h2>
+    <table>
+        <tr>
+            <th>Attempted at</th>
+            <th>Recipient</th>
+            <th>Status</th>
+            <th>Provider message id</th>
+            <th>Error</th>
+        </tr>{log_html}
+    </table>
+    <h2>Clicks</h2>
+    <table>
+        <tr>
+            <th>Link</th>
+            <th>Clicks</th>
+            <th>Readers</th>
+        </tr>{clicks_html}
+    </table>
+    {test_sends_html}
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_progress(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueProgress>, anyhow::Error> {
+    let progress = sqlx::query_as!(
+        IssueProgress,
+        r#"
+        SELECT
+            title,
+            status,
+            published_at,
+            scheduled_for,
+            cancelled_at,
+            n_sent_before_cancellation,
+            n_cancelled_deliveries,
+            (SELECT COUNT(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id) AS "n_queued!",
+            (SELECT COUNT(DISTINCT subscriber_email) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND d.status = 'sent') AS "n_sent!",
+            (SELECT COUNT(*) FROM issue_delivery_failures f
+                WHERE f.newsletter_issue_id = i.newsletter_issue_id) AS "n_failed!"
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the delivery progress of a newsletter issue.")?;
+    Ok(progress)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_recent_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<DeliveryLogEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        DeliveryLogEntry,
+        r#"
+        SELECT subscriber_email, status, provider_message_id, error_message, attempted_at
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        ORDER BY attempted_at DESC, id DESC
+        LIMIT 50
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the delivery log of a newsletter issue.")?;
+    Ok(entries)
+}
+
+/// The links of the issue that were clicked, most clicked first.
+#[tracing::instrument(skip(pool))]
+async fn get_link_clicks(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<LinkClicks>, anyhow::Error> {
+    let clicks = sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT
+            url,
+            COUNT(*) AS "n_clicks!",
+            COUNT(DISTINCT subscriber_id) AS "n_readers!"
+        FROM link_clicks
+        WHERE newsletter_issue_id = $1
+        GROUP BY url
+        ORDER BY COUNT(*) DESC, url
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the clicks on the links of a newsletter issue.")?;
+    Ok(clicks)
+}
@@ -0,0 +1,106 @@
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The format of `<input type="datetime-local">`, interpreted as UTC.
+const SEND_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+/// Parse a send time submitted by an admin: it has to be in the future.
+pub(super) fn parse_send_time(s: &str) -> Result<DateTime<Utc>, String> {
+    let send_time = NaiveDateTime::parse_from_str(s, SEND_TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| format!("{s} is not a valid send time."))?
+        .and_utc();
+    if send_time <= Utc::now() {
+        return Err("The send time must be in the future.".into());
+    }
+    Ok(send_time)
+}
+
+pub(super) fn format_send_time(send_time: DateTime<Utc>) -> String {
+    send_time.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// The value to pre-fill a `<input type="datetime-local">` with.
+pub(super) fn send_time_input_value(send_time: DateTime<Utc>) -> String {
+    send_time.format(SEND_TIME_FORMAT).to_string()
+}
+
+#[derive(Deserialize)]
+pub struct RescheduleFormData {
+    scheduled_for: String,
+}
+
+#[tracing::instrument(skip(pool, form))]
+pub async fn reschedule_newsletter_issue(
+    pool: web::Data<PgPool>,
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<RescheduleFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue_page = format!("/admin/newsletters/{newsletter_issue_id}");
+    let scheduled_for = match parse_send_time(form.scheduled_for.trim()) {
+        Ok(send_time) => send_time,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&issue_page));
+        }
+    };
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id,
+        scheduled_for
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule a newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("Only issues waiting to be sent can be rescheduled.").send();
+    } else {
+        FlashMessage::info(format!(
+            "The newsletter issue has been rescheduled for {}.",
+            format_send_time(scheduled_for)
+        ))
+        .send();
+    }
+    Ok(see_other(&issue_page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_send_time;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn a_future_datetime_local_value_is_accepted() {
+        let send_time = Utc::now() + Duration::days(1);
+        let input = send_time.format("%Y-%m-%dT%H:%M").to_string();
+        let parsed = parse_send_time(&input).unwrap();
+        assert_eq!(parsed.format("%Y-%m-%dT%H:%M").to_string(), input);
+    }
+
+    #[test]
+    fn send_times_in_the_past_are_rejected() {
+        let send_time = Utc::now() - Duration::minutes(5);
+        let input = send_time.format("%Y-%m-%dT%H:%M").to_string();
+        assert!(parse_send_time(&input).is_err());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        for input in ["", "tomorrow", "2030-13-01T10:00", "2030-01-01"] {
+            assert!(parse_send_time(input).is_err(), "{input} was accepted");
+        }
+    }
+}
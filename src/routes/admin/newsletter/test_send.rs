@@ -0,0 +1,270 @@
+use super::preview::{get_issue_content, render_sample};
+use crate::authentication::UserId;
+use crate::domain::{IssueStatus, SubscriberEmail};
+use crate::email_client::{EmailSender, OutgoingEmail};
+use crate::email_layout::get_issue_layout;
+use crate::utils::{e404, e500, escape_html, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Test sends are meant for a handful of editors, not for mailing a list.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    /// Comma, semicolon or whitespace separated addresses.
+    recipients: String,
+}
+
+pub(super) struct TestSend {
+    recipient: String,
+    provider_message_id: Option<String>,
+    error_message: Option<String>,
+    sent_at: DateTime<Utc>,
+}
+
+/// Send a copy of an issue to the given addresses, through the same email
+/// client as the delivery workers. It never goes through the delivery queue.
+#[tracing::instrument(
+    name = "Sending a test copy of a newsletter issue.",
+    skip(pool, email_client, form, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn send_test_newsletter(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = get_issue_content(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no newsletter issue with the provided id."))?;
+    let status: IssueStatus = issue.status.parse().map_err(e500)?;
+    let return_to = match status {
+        IssueStatus::Draft => format!("/admin/newsletters/{newsletter_issue_id}/edit"),
+        _ => format!("/admin/newsletters/{newsletter_issue_id}"),
+    };
+
+    let recipients = match parse_recipients(&form.recipients) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&return_to));
+        }
+    };
+
+    // The recipients are not subscribers: they get the made-up subscriber's copy
+    let layout = get_issue_layout(pool.get_ref(), newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let (html_content, text_content) = match render_sample(&issue, layout.as_ref()) {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&return_to));
+        }
+    };
+    let subject = format!("[Test] {}", issue.title);
+    let emails: Vec<_> = recipients
+        .iter()
+        .map(|recipient| OutgoingEmail {
+            recipient,
+            subject: &subject,
+            html_body: Some(&html_content),
+            text_body: &text_content,
+            // The recipients are not subscribers: there is nothing to leave
+            list_unsubscribe: None,
+        })
+        .collect();
+    // An outer error means none of them went out
+    let results: Vec<Result<Option<String>, String>> = match email_client.send_batch(&emails).await
+    {
+        Ok(results) => results
+            .into_iter()
+            .map(|r| r.map_err(|e| e.to_string()))
+            .collect(),
+        Err(e) => recipients.iter().map(|_| Err(e.to_string())).collect(),
+    };
+
+    record_test_sends(&pool, newsletter_issue_id, **user_id, &recipients, &results)
+        .await
+        .context("Failed to record the test sends of a newsletter issue.")
+        .map_err(e500)?;
+    for (recipient, result) in recipients.iter().zip(&results) {
+        match result {
+            Ok(_) => FlashMessage::info(format!("A test copy was sent to {}.", recipient.as_ref())),
+            Err(e) => FlashMessage::error(format!(
+                "Failed to send a test copy to {}: {e}",
+                recipient.as_ref()
+            )),
+        }
+        .send();
+    }
+    Ok(see_other(&return_to))
+}
+
+fn parse_recipients(recipients: &str) -> Result<Vec<SubscriberEmail>, String> {
+    let mut parsed: Vec<SubscriberEmail> = Vec::new();
+    for recipient in recipients
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|r| !r.is_empty())
+    {
+        let recipient: SubscriberEmail = recipient.parse()?;
+        if !parsed.iter().any(|r| r.as_ref() == recipient.as_ref()) {
+            parsed.push(recipient);
+        }
+    }
+    if parsed.is_empty() {
+        return Err("Enter at least one address to send a test copy to.".into());
+    }
+    if parsed.len() > MAX_TEST_RECIPIENTS {
+        return Err(format!(
+            "A test copy can be sent to at most {MAX_TEST_RECIPIENTS} addresses."
+        ));
+    }
+    Ok(parsed)
+}
+
+/// The test send form, followed by the test sends made so far.
+pub(super) fn test_sends_html(newsletter_issue_id: Uuid, test_sends: &[TestSend]) -> String {
+    let mut rows = String::new();
+    for test_send in test_sends {
+        let outcome = match (&test_send.provider_message_id, &test_send.error_message) {
+            (_, Some(e)) => format!("failed: {}", escape_html(e)),
+            (Some(message_id), None) => format!("sent ({message_id})"),
+            (None, None) => "sent".into(),
+        };
+        write!(
+            rows,
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            test_send.sent_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            escape_html(&test_send.recipient),
+            outcome
+        )
+        .unwrap();
+    }
+    format!(
+        r#"<h2>Test sends</h2>
+    <form action="/admin/newsletters/{newsletter_issue_id}/test" method="post">
+        <label>Send a test copy to:
+            <input type="text" name="recipients" placeholder="editor@example.com, ...">
+        </label>
+        <button type="submit">Send test</button>
+    </form>
+    <table>
+        <tr>
+            <th>Sent at</th>
+            <th>Recipient</th>
+            <th>Outcome</th>
+        </tr>{rows}
+    </table>"#
+    )
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_test_sends(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    sent_by: Uuid,
+    recipients: &[SubscriberEmail],
+    results: &[Result<Option<String>, String>],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for (recipient, result) in recipients.iter().zip(results) {
+        let (provider_message_id, error_message) = match result {
+            Ok(message_id) => (message_id.as_deref(), None),
+            Err(e) => (None, Some(e.as_str())),
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issue_test_sends (
+                newsletter_issue_id,
+                recipient,
+                sent_by,
+                provider_message_id,
+                error_message,
+                sent_at
+            )
+            VALUES ($1, $2, $3, $4, $5, now())
+            "#,
+            newsletter_issue_id,
+            recipient.as_ref(),
+            sent_by,
+            provider_message_id,
+            error_message
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+#[tracing::instrument(skip(pool))]
+pub(super) async fn get_test_sends(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<TestSend>, anyhow::Error> {
+    let test_sends = sqlx::query_as!(
+        TestSend,
+        r#"
+        SELECT recipient, provider_message_id, error_message, sent_at
+        FROM newsletter_issue_test_sends
+        WHERE newsletter_issue_id = $1
+        ORDER BY sent_at DESC, id DESC
+        LIMIT 50
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the test sends of a newsletter issue.")?;
+    Ok(test_sends)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_recipients;
+
+    #[test]
+    fn recipients_can_be_separated_by_commas_semicolons_or_whitespace() {
+        let recipients =
+            parse_recipients("a@example.com, b@example.com;c@example.com\nd@example.com").unwrap();
+        assert_eq!(recipients.len(), 4);
+    }
+
+    #[test]
+    fn duplicate_recipients_only_get_one_copy() {
+        let recipients = parse_recipients("a@example.com a@example.com").unwrap();
+        assert_eq!(recipients.len(), 1);
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        assert!(parse_recipients("a@example.com, not-an-email").is_err());
+    }
+
+    #[test]
+    fn at_least_one_recipient_is_required() {
+        assert!(parse_recipients(" , ").is_err());
+    }
+
+    #[test]
+    fn there_is_a_cap_on_the_number_of_recipients() {
+        let recipients: Vec<_> = (0..11).map(|i| format!("editor{i}@example.com")).collect();
+        assert!(parse_recipients(&recipients.join(",")).is_err());
+    }
+}
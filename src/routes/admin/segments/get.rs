@@ -0,0 +1,66 @@
+use crate::domain::Segment;
+use crate::segments::{count_confirmed_members, get_segments};
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn segments_form(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let mut segments_html = String::new();
+    for saved in get_segments(pool.get_ref()).await.map_err(e500)? {
+        let segment: Segment = saved.definition.parse().map_err(e500)?;
+        let n_confirmed_members = count_confirmed_members(&pool, &segment)
+            .await
+            .map_err(e500)?;
+        writeln!(
+            segments_html,
+            r#"<li>{} ({} confirmed members): <code>{}</code></li>"#,
+            escape_html(&saved.name),
+            n_confirmed_members,
+            escape_html(&saved.definition)
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Segments</title>
+</head>
+<body>
+    {msg_html}
+    <ul>
+        {segments_html}
+    </ul>
+    <form action="/admin/segments" method="post">
+        <label>Name
+            <input type="text" name="name">
+        </label>
+        <br>
+        <label>Definition
+            <input type="text" name="definition" size="80">
+        </label>
+        <p>
+            Combine <code>tag:vip</code>, <code>list:"Release notes"</code>,
+            <code>signed_up_before:2024-01-01</code> and <code>signed_up_since:2024-01-01</code>
+            with <code>and</code>, <code>or</code>, <code>not</code> and parentheses.
+        </p>
+        <button type="submit">Save segment</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
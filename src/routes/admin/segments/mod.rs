@@ -0,0 +1,4 @@
+mod get;
+pub use get::segments_form;
+mod post;
+pub use post::create_segment;
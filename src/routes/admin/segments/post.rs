@@ -0,0 +1,54 @@
+use crate::domain::Segment;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    definition: String,
+}
+
+#[tracing::instrument(
+    name = "Saving a segment.",
+    skip(pool, form),
+    fields(name = %form.name, definition = %form.definition)
+)]
+pub async fn create_segment(
+    pool: web::Data<PgPool>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("A segment needs a name.").send();
+        return Ok(see_other("/admin/segments"));
+    }
+    let definition = form.definition.trim();
+    if let Err(e) = definition.parse::<Segment>() {
+        FlashMessage::error(format!("Invalid segment definition: {e}")).send();
+        return Ok(see_other("/admin/segments"));
+    }
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO segments (segment_id, name, definition)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        definition
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted == 0 {
+        FlashMessage::error(format!("There already is a segment named {name}.")).send();
+    } else {
+        FlashMessage::info(format!("The segment {name} has been saved.")).send();
+    }
+    Ok(see_other("/admin/segments"))
+}
@@ -0,0 +1,180 @@
+use crate::domain::SubscriptionStatus;
+use crate::lists::get_lists;
+use crate::subscriber_data::{
+    collect_subscriber_data, get_subscriber_id, get_subscribers_for_export, subscribers_csv,
+};
+use crate::utils::{attachment, e400, e500, escape_html, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn subscribers_page(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let mut status_options = String::new();
+    for status in [
+        SubscriptionStatus::PendingConfirmation,
+        SubscriptionStatus::Confirmed,
+        SubscriptionStatus::Unsubscribed,
+        SubscriptionStatus::Suppressed,
+    ] {
+        writeln!(
+            status_options,
+            r#"<option value="{status}">{status}</option>"#
+        )
+        .unwrap();
+    }
+    let mut list_options = String::new();
+    for list in get_lists(pool.get_ref()).await.map_err(e500)? {
+        writeln!(
+            list_options,
+            r#"<option value="{}">{}</option>"#,
+            list.list_id,
+            escape_html(&list.name)
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <h2>Export subscribers</h2>
+    <form action="/admin/subscribers/export" method="get">
+        <label>Format
+            <select name="format">
+                <option value="csv">CSV</option>
+                <option value="json">JSON</option>
+            </select>
+        </label>
+        <label>Status
+            <select name="status">
+                <option value="">Any</option>
+                {status_options}
+            </select>
+        </label>
+        <label>List
+            <select name="list_id">
+                <option value="">Any</option>
+                {list_options}
+            </select>
+        </label>
+        <button type="submit">Export</button>
+    </form>
+    <h2>Data subject requests</h2>
+    <form action="/admin/subscribers/data" method="get">
+        <label>Email
+            <input type="text" name="email">
+        </label>
+        <button type="submit">Export their data</button>
+    </form>
+    <form action="/admin/subscribers/erase" method="post">
+        <label>Email
+            <input type="text" name="email">
+        </label>
+        <button type="submit">Erase them</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub struct ExportParameters {
+    format: ExportFormat,
+    /// Left empty to export subscribers in any state.
+    status: Option<String>,
+    /// Left empty to export subscribers whatever their lists.
+    list_id: Option<String>,
+}
+
+#[tracing::instrument(name = "Exporting subscribers.", skip_all)]
+pub async fn export_subscribers(
+    pool: web::Data<PgPool>,
+    parameters: web::Query<ExportParameters>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ExportParameters {
+        format,
+        status,
+        list_id,
+    } = parameters.into_inner();
+    let status = match status.as_deref() {
+        None | Some("") => None,
+        Some(status) => Some(status.parse::<SubscriptionStatus>().map_err(e400)?),
+    };
+    let list_id = match list_id.as_deref() {
+        None | Some("") => None,
+        Some(list_id) => Some(list_id.parse().map_err(e400)?),
+    };
+    let subscribers = get_subscribers_for_export(pool.get_ref(), status, list_id)
+        .await
+        .map_err(e500)?;
+    let response = match format {
+        ExportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(attachment("subscribers.csv"))
+            .body(subscribers_csv(&subscribers).map_err(e500)?),
+        ExportFormat::Json => HttpResponse::Ok()
+            .insert_header(attachment("subscribers.json"))
+            .json(subscribers),
+    };
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct SubscriberParameters {
+    email: String,
+}
+
+/// Everything we store about a subscriber, to answer their access request.
+#[tracing::instrument(name = "Exporting the data of a subscriber.", skip_all)]
+pub async fn export_subscriber_data(
+    pool: web::Data<PgPool>,
+    parameters: web::Query<SubscriberParameters>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = parameters.email.trim();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let data = match get_subscriber_id(&mut *transaction, email)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber_id) => collect_subscriber_data(&mut transaction, subscriber_id)
+            .await
+            .map_err(e500)?,
+        None => None,
+    };
+    let Some(data) = data else {
+        FlashMessage::error(format!("There is no subscriber with the email {email}.")).send();
+        return Ok(see_other("/admin/subscribers"));
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(attachment("subscriber-data.json"))
+        .json(data))
+}
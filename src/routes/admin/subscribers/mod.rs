@@ -0,0 +1,4 @@
+mod get;
+pub use get::{export_subscriber_data, export_subscribers, subscribers_page};
+mod post;
+pub use post::erase_subscriber_by_email;
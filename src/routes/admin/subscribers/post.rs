@@ -0,0 +1,45 @@
+use crate::startup::HmacSecret;
+use crate::subscriber_data::{erase_subscriber, get_subscriber_id};
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Erasing a subscriber.", skip_all)]
+pub async fn erase_subscriber_by_email(
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = form.email.trim();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let Some(subscriber_id) = get_subscriber_id(&mut *transaction, email)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error(format!("There is no subscriber with the email {email}.")).send();
+        return Ok(see_other("/admin/subscribers"));
+    };
+    erase_subscriber(&mut transaction, &hmac_secret, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber")
+        .map_err(e500)?;
+    FlashMessage::info(format!("The data of {email} has been erased.")).send();
+    Ok(see_other("/admin/subscribers"))
+}
@@ -0,0 +1,78 @@
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+struct TagSummary {
+    tag: String,
+    n_confirmed_subscribers: i64,
+}
+
+pub async fn tags_form(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, r#"<p><i>{}</i></p>"#, escape_html(m.content())).unwrap();
+    }
+    let mut tags_html = String::new();
+    for tag in get_tag_summaries(&pool).await.map_err(e500)? {
+        writeln!(
+            tags_html,
+            r#"<li>{} ({} confirmed subscribers)</li>"#,
+            escape_html(&tag.tag),
+            tag.n_confirmed_subscribers
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Tags</title>
+</head>
+<body>
+    {msg_html}
+    <ul>
+        {tags_html}
+    </ul>
+    <form action="/admin/tags" method="post">
+        <label>Subscriber email
+            <input type="text" name="email">
+        </label>
+        <br>
+        <label>Tags (comma separated, replacing their current tags)
+            <input type="text" name="tags">
+        </label>
+        <br>
+        <button type="submit">Tag subscriber</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_tag_summaries(pool: &PgPool) -> Result<Vec<TagSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        TagSummary,
+        r#"
+        SELECT
+            t.tag,
+            COUNT(*) FILTER (WHERE s.status = 'confirmed') AS "n_confirmed_subscribers!"
+        FROM subscriber_tags t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        GROUP BY t.tag
+        ORDER BY t.tag
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
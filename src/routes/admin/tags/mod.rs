@@ -0,0 +1,4 @@
+mod get;
+pub use get::tags_form;
+mod post;
+pub use post::tag_subscriber;
@@ -0,0 +1,67 @@
+use crate::domain::{SubscriberEmail, SubscriberTag};
+use crate::tags::replace_subscriber_tags;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    email: String,
+    tags: String,
+}
+
+#[tracing::instrument(name = "Tagging a subscriber.", skip(pool, form), fields(email = %form.email))]
+pub async fn tag_subscriber(
+    pool: web::Data<PgPool>,
+    form: web::Form<FormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let parsed = form
+        .email
+        .parse::<SubscriberEmail>()
+        .and_then(|email| Ok((email, SubscriberTag::parse_list(&form.tags)?)));
+    let (email, tags) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/tags"));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let subscriber_id = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions WHERE email = $1
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(e500)?
+    .map(|r| r.id);
+    let Some(subscriber_id) = subscriber_id else {
+        FlashMessage::error(format!(
+            "There is no subscriber with the email {}.",
+            email.as_ref()
+        ))
+        .send();
+        return Ok(see_other("/admin/tags"));
+    };
+    replace_subscriber_tags(&mut transaction, subscriber_id, &tags)
+        .await
+        .context("Failed to store the tags of the subscriber")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to tag a subscriber")
+        .map_err(e500)?;
+    FlashMessage::info(format!("The tags of {} have been updated.", email.as_ref())).send();
+    Ok(see_other("/admin/tags"))
+}
@@ -0,0 +1,49 @@
+use crate::click_links::{ClickLinks, TrackedClick};
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+/// Record the click and send the reader on to the link they clicked.
+///
+/// Links we did not sign are not found: the redirect only ever leads to
+/// targets that were in one of our issues.
+#[tracing::instrument(name = "Tracking a click.", skip(token, pool, links))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    links: web::Data<ClickLinks>,
+) -> HttpResponse {
+    let Some(click) = links.verify(&token) else {
+        return HttpResponse::NotFound().finish();
+    };
+    // Readers get to their link whatever happens to the record of their click
+    if let Err(e) = store_click(&pool, &click).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to store a click."
+        );
+    }
+    HttpResponse::Found()
+        .insert_header((LOCATION, click.target))
+        .finish()
+}
+
+/// Clicks of subscribers who have since been erased are not recorded.
+#[tracing::instrument(skip(pool))]
+async fn store_click(pool: &PgPool, click: &TrackedClick) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO link_clicks (newsletter_issue_id, subscriber_id, url)
+        SELECT i.newsletter_issue_id, s.id, $3
+        FROM newsletter_issues i, subscriptions s
+        WHERE i.newsletter_issue_id = $1 AND s.id = $2
+        "#,
+        click.newsletter_issue_id,
+        click.subscriber_id,
+        click.target
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -1,8 +1,47 @@
+use crate::lists::{get_lists, list_checkboxes_html};
+use crate::utils::e500;
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
+pub async fn home(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(pool.get_ref()).await.map_err(e500)?;
+    let lists_html = if lists.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"<fieldset>
+            <legend>Lists</legend>
+            {}
+        </fieldset>"#,
+            list_checkboxes_html(&lists, &[])
+        )
+    };
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+
+<head>
+    <meta http-equiv="Content-Type" content="text/html; charset=UTF-8">
+    <title>Home</title>
+</head>
+
+<body>
+    <p>Welcome to our newsletter</p>
+    <form action="/subscriptions" method="post">
+        <label>Name
+            <input type="text" name="name">
+        </label>
+        <label>Email
+            <input type="email" name="email">
+        </label>
+        {lists_html}
+        <button type="submit">Subscribe</button>
+    </form>
+</body>
+
+</html>"#
+        )))
 }
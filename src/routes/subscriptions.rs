@@ -1,7 +1,13 @@
+use super::subscriptions_confirm::delete_subscription_tokens;
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTag, SubscriptionStatus},
+    email_client::{EmailSender, SendEmailError},
+    email_layout::{get_default_layout, EmailLayout},
+    lists::{parse_list_ids, replace_list_memberships},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    subscriber_data::is_suppressed_after_erasure,
+    tags::add_subscriber_tags,
+    utils::split_repeated_field,
 };
 use actix_web::{http, web, HttpResponse, ResponseError};
 use anyhow::Context;
@@ -43,39 +49,122 @@ impl TryFrom<FormData> for NewSubscriber {
         Ok(NewSubscriber { email, name })
     }
 }
+#[derive(Deserialize)]
+pub struct SignupParameters {
+    /// Comma separated tags to put on the subscriber, e.g. for the page they
+    /// signed up from: `/subscriptions?tags=podcast,rustconf`.
+    tags: Option<String>,
+}
+
+/// The form may also tick any number of `list_id` checkboxes, for the lists
+/// to join.
 #[tracing::instrument(
     name = "Adding a new subscriber.",
-    skip(form, pool, email_client, base_url),
+    skip(form, parameters, pool, email_client, base_url, hmac_secret),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    form: web::Form<Vec<(String, String)>>,
+    parameters: web::Query<SignupParameters>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailSender>,
     base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let (form, list_ids): (FormData, _) = split_repeated_field(form.into_inner(), "list_id")
+        .map_err(|e| SubscribeError::ValidationError(e.to_string()))?;
+    tracing::Span::current()
+        .record("subscriber_email", tracing::field::display(&form.email))
+        .record("subscriber_name", tracing::field::display(&form.name));
+    let list_ids = parse_list_ids(&list_ids).map_err(SubscribeError::ValidationError)?;
+    let tags = SubscriberTag::parse_list(parameters.tags.as_deref().unwrap_or_default())
+        .map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    if is_suppressed_after_erasure(&mut *transaction, &hmac_secret, &new_subscriber.email)
         .await
-        .context("Failed to insert new subscriber in the database")?;
-    let subscription_token = gen_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .context("Failed to look the email up among erased subscribers")?
+    {
+        // Same answer as for a subscriber who is still suppressed
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let mut existing_subscriber = get_existing_subscriber(&mut transaction, &new_subscriber.email)
         .await
-        .context("Failed to store the confirmation token in the database")?;
+        .context("Failed to look the subscriber up in the database")?;
+    let mut inserted_subscriber_id = None;
+    if existing_subscriber.is_none() {
+        inserted_subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to insert new subscriber in the database")?;
+        if inserted_subscriber_id.is_none() {
+            // A concurrent request signed them up since we looked, and has committed by now
+            existing_subscriber = get_existing_subscriber(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look the subscriber up in the database")?;
+        }
+    }
+    let subscription_token = gen_subscription_token();
+    match (inserted_subscriber_id, existing_subscriber) {
+        (Some(subscriber_id), _) => {
+            replace_list_memberships(&mut transaction, subscriber_id, &list_ids)
+                .await
+                .context("Failed to store the lists the subscriber joins")?;
+            add_subscriber_tags(&mut transaction, subscriber_id, &tags)
+                .await
+                .context("Failed to store the tags of the subscriber")?;
+            store_token(&mut transaction, subscriber_id, &subscription_token, None)
+                .await
+                .context("Failed to store the confirmation token in the database")?;
+        }
+        (
+            None,
+            Some((
+                subscriber_id,
+                SubscriptionStatus::PendingConfirmation | SubscriptionStatus::Unsubscribed,
+            )),
+        ) => {
+            restart_confirmation(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to reset the subscriber for a new confirmation")?;
+            let pending_changes = PendingChanges {
+                name: new_subscriber.name.as_ref().to_owned(),
+                list_ids,
+                tags: tags.iter().map(ToString::to_string).collect(),
+            };
+            store_token(
+                &mut transaction,
+                subscriber_id,
+                &subscription_token,
+                Some(&pending_changes),
+            )
+            .await
+            .context("Failed to store the confirmation token in the database")?;
+        }
+        (None, Some((_, SubscriptionStatus::Confirmed | SubscriptionStatus::Suppressed))) => {
+            // Same answer as for a new subscriber: who is on the list is nobody's business
+            return Ok(HttpResponse::Ok().finish());
+        }
+        (None, None) => {
+            return Err(anyhow::anyhow!("The subscriber vanished while signing them up.").into());
+        }
+    }
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber")?;
+    let layout = get_default_layout(pool.get_ref())
+        .await
+        .context("Failed to fetch the layout of the confirmation email")?;
     send_confirmation_email(
-        &email_client,
+        email_client.as_ref(),
+        &layout,
         new_subscriber,
         &base_url.0,
         &subscription_token,
@@ -84,6 +173,50 @@ pub async fn subscribe(
     .context("Failed to send a confirmation email.")?;
     Ok(HttpResponse::Ok().finish())
 }
+/// Lock the subscriber with this email, if there is one, until the end of the transaction.
+#[tracing::instrument(name = "Looking up an existing subscriber.", skip(transaction, email))]
+async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, SubscriptionStatus)>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    match record {
+        Some(r) => Ok(Some((r.id, r.status.parse().map_err(anyhow::Error::msg)?))),
+        None => Ok(None),
+    }
+}
+
+/// Send a pending or unsubscribed subscriber through double opt-in again: the
+/// confirmation links they were sent before stop working.
+#[tracing::instrument(
+    name = "Resetting a subscriber for a new confirmation.",
+    skip(transaction)
+)]
+async fn restart_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', unsubscribed_at = NULL
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    );
+    transaction.execute(query).await?;
+    delete_subscription_tokens(transaction, subscriber_id).await
+}
+
+/// Insert a pending subscriber, unless there already is one with this email:
+/// returns their id if they are new.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database.",
     skip(transaction, new_subscriber)
@@ -91,38 +224,59 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    let query = sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
-    );
-    transaction.execute(query).await?;
-    Ok(subscriber_id)
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(record.map(|r| r.id))
+}
+
+/// What signing up again asks for, for an address we already know. It is kept
+/// with the confirmation token and only applied once the address is confirmed:
+/// anybody can submit the form, only its owner can click the link.
+pub struct PendingChanges {
+    pub name: String,
+    pub list_ids: Vec<Uuid>,
+    pub tags: Vec<String>,
 }
 
 #[tracing::instrument(
     name = "Store subscription_token in the database",
-    skip(transaction, subscription_token)
+    skip(transaction, subscription_token, pending_changes)
 )]
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    pending_changes: Option<&PendingChanges>,
 ) -> Result<(), sqlx::Error> {
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (
+            subscription_token,
+            subscriber_id,
+            pending_name,
+            pending_list_ids,
+            pending_tags
+        )
+        VALUES ($1, $2, $3, $4, $5)
         "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        pending_changes.map(|c| c.name.as_str()),
+        pending_changes.map(|c| c.list_ids.as_slice()),
+        pending_changes.map(|c| c.tags.as_slice())
     );
     transaction.execute(query).await?;
     Ok(())
@@ -130,14 +284,15 @@ pub async fn store_token(
 
 #[tracing::instrument(
     name = "Sending confirmation email to new subscriber.",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, layout, new_subscriber, base_url)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+    email_client: &dyn EmailSender,
+    layout: &EmailLayout,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let confirmation_link =
         format!("{base_url}/subscriptions/confirm?subscription_token={subscription_token}");
     let subject = "Welcome!";
@@ -145,13 +300,22 @@ pub async fn send_confirmation_email(
         "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
         confirmation_link
     );
-    let html_body = format!("Welcome to our newsletter!<br />\nClick <a href=\"{}\">here</a> to confirm your subscription.", confirmation_link);
+    let html_content = format!("<p>Welcome to our newsletter!<br />\nClick <a href=\"{}\">here</a> to confirm your subscription.</p>", confirmation_link);
+    let html_body = match layout.wrap(subject, &html_content, None) {
+        Ok(html_body) => html_body,
+        // Better without its layout than not at all
+        Err(e) => {
+            error!(error.message = %e, "Failed to wrap the confirmation email in its layout.");
+            html_content
+        }
+    };
     email_client
         .send_email(&new_subscriber.email, subject, &html_body, &text_body)
         .await
+        .map(|_| ())
 }
 
-fn gen_subscription_token() -> String {
+pub fn gen_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,7 +1,20 @@
+use super::subscriptions::{
+    gen_subscription_token, send_confirmation_email, store_token, PendingChanges,
+};
+use crate::config::SubscriptionSettings;
+use crate::domain::{NewSubscriber, SubscriberTag, SubscriptionStatus};
+use crate::email_client::EmailSender;
+use crate::email_layout::get_default_layout;
+use crate::lists::replace_list_memberships;
+use crate::startup::ApplicationBaseUrl;
+use crate::tags::add_subscriber_tags;
+use crate::utils::escape_html;
+use actix_web::http::header::ContentType;
 use actix_web::{http, web, HttpResponse, ResponseError};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use tracing::error;
 use uuid::Uuid;
 
@@ -25,50 +38,272 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirming a pending subscriber.", skip(pool, parameters))]
+struct StoredToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    pending_changes: Option<PendingChanges>,
+}
+
+#[tracing::instrument(
+    name = "Confirming a pending subscriber.",
+    skip(pool, parameters, settings)
+)]
 pub async fn confirm(
     pool: web::Data<PgPool>,
     parameters: web::Query<Parameters>,
+    settings: web::Data<SubscriptionSettings>,
 ) -> Result<HttpResponse, ConfirmError> {
     let subscription_token = &parameters.subscription_token;
 
-    let subscriber_id = get_subscriber_id_from_token(&pool, subscription_token)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, subscription_token)
         .await
         .context("Failed to retrieve the subscriber id associated with the provided token.")?
         .ok_or(ConfirmError::UnknownToken)?;
-    confirm_subscriber(&pool, subscriber_id)
+    if token.created_at + settings.confirmation_token_ttl() < Utc::now() {
+        return Ok(expired_token_page(subscription_token));
+    }
+    // They may have unsubscribed, or been suppressed, since the link was sent
+    if !confirm_subscriber(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`.")?
+    {
+        return Err(ConfirmError::UnknownToken);
+    }
+    if let Some(pending_changes) = &token.pending_changes {
+        apply_pending_changes(&mut transaction, token.subscriber_id, pending_changes)
+            .await
+            .context("Failed to apply the changes the subscriber signed up with.")?;
+    }
+    // Confirmation links only work once
+    delete_subscription_tokens(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to delete the confirmation tokens of the subscriber.")?;
+    transaction
+        .commit()
         .await
-        .context("Failed to update the subscriber status to `confirmed`.")?;
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Mark subcriber as confirmed.", skip(pool, subscriber_id))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), anyhow::Error> {
-    sqlx::query!(
+fn expired_token_page(subscription_token: &str) -> HttpResponse {
+    let subscription_token = escape_html(subscription_token);
+    HttpResponse::Gone()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation link expired</title>
+</head>
+<body>
+    <p>This confirmation link has expired.</p>
+    <form action="/subscriptions/confirm/resend" method="post">
+        <input hidden type="text" name="subscription_token" value="{subscription_token}">
+        <button type="submit">Send me a new link</button>
+    </form>
+</body>
+</html>"#
+        ))
+}
+
+#[derive(Deserialize)]
+pub struct ResendFormData {
+    subscription_token: String,
+}
+
+/// Email a fresh confirmation link to the subscriber an expired token was issued for.
+#[tracing::instrument(
+    name = "Resending a confirmation email.",
+    skip(form, pool, email_client, base_url)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, &form.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the provided token.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    let mut subscriber = get_pending_subscriber(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to fetch the pending subscriber.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if let Some(pending_changes) = &token.pending_changes {
+        subscriber.name = pending_changes
+            .name
+            .parse()
+            .map_err(anyhow::Error::msg)
+            .context("Failed to parse the name the subscriber signed up with.")?;
+    }
+    delete_subscription_tokens(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to delete the confirmation tokens of the subscriber.")?;
+    // The new link carries over whatever the expired one would have changed
+    let subscription_token = gen_subscription_token();
+    store_token(
+        &mut transaction,
+        token.subscriber_id,
+        &subscription_token,
+        token.pending_changes.as_ref(),
+    )
+    .await
+    .context("Failed to store the confirmation token in the database")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new confirmation token")?;
+    let layout = get_default_layout(pool.get_ref())
+        .await
+        .context("Failed to fetch the layout of the confirmation email")?;
+    send_confirmation_email(
+        email_client.as_ref(),
+        &layout,
+        subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation link sent</title>
+</head>
+<body>
+    <p>We sent you a new confirmation link: check your inbox.</p>
+</body>
+</html>"#,
+    ))
+}
+
+/// Only pending subscribers can be confirmed: returns whether they were.
+#[tracing::instrument(
+    name = "Mark subcriber as confirmed.",
+    skip(transaction, subscriber_id)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let query = sqlx::query!(
         r#"
         UPDATE subscriptions
         SET status = 'confirmed'
-        WHERE id = $1
+        WHERE id = $1 AND status = 'pending_confirmation'
         "#,
         subscriber_id
-    )
-    .execute(pool)
-    .await?;
+    );
+    let result = transaction.execute(query).await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Give the subscriber the name, lists and tags they signed up again with.
+#[tracing::instrument(skip(transaction, pending_changes))]
+async fn apply_pending_changes(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    pending_changes: &PendingChanges,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET name = $2 WHERE id = $1
+        "#,
+        subscriber_id,
+        pending_changes.name
+    );
+    transaction.execute(query).await?;
+    replace_list_memberships(transaction, subscriber_id, &pending_changes.list_ids).await?;
+    let tags = pending_changes
+        .tags
+        .iter()
+        .map(|tag| tag.parse())
+        .collect::<Result<Vec<SubscriberTag>, _>>()
+        .map_err(anyhow::Error::msg)?;
+    add_subscriber_tags(transaction, subscriber_id, &tags).await?;
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+#[tracing::instrument(
+    name = "Get subscriber_id from token",
+    skip(transaction, subscription_token)
+)]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, anyhow::Error> {
-    let result = sqlx::query!(
+) -> Result<Option<StoredToken>, anyhow::Error> {
+    let record = sqlx::query!(
         r#"
-        SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1
+        SELECT subscriber_id, created_at, pending_name, pending_list_ids, pending_tags
+        FROM subscription_tokens
+        WHERE subscription_token = $1
         "#,
         subscription_token,
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut **transaction)
     .await?;
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(record.map(|r| StoredToken {
+        subscriber_id: r.subscriber_id,
+        created_at: r.created_at,
+        pending_changes: r.pending_name.map(|name| PendingChanges {
+            name,
+            list_ids: r.pending_list_ids.unwrap_or_default(),
+            tags: r.pending_tags.unwrap_or_default(),
+        }),
+    }))
+}
+
+/// Only subscribers still waiting for confirmation are sent a new link.
+#[tracing::instrument(skip(transaction))]
+async fn get_pending_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<NewSubscriber>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT email, name FROM subscriptions WHERE id = $1 AND status = $2
+        FOR UPDATE
+        "#,
+        subscriber_id,
+        SubscriptionStatus::PendingConfirmation.as_str()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    let Some(record) = record else {
+        return Ok(None);
+    };
+    Ok(Some(NewSubscriber {
+        email: record.email.parse().map_err(anyhow::Error::msg)?,
+        name: record.name.parse().map_err(anyhow::Error::msg)?,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Delete the subscription tokens of a subscriber",
+    skip(transaction)
+)]
+pub async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
 }
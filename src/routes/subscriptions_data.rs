@@ -0,0 +1,118 @@
+use super::subscriptions_preferences::PreferencesError;
+use super::subscriptions_unsubscribe::SignedLinkParameters;
+use crate::startup::HmacSecret;
+use crate::subscriber_data::{collect_subscriber_data, erase_subscriber};
+use crate::unsubscribe_links::UnsubscribeLinks;
+use crate::utils::{attachment, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+/// Let the subscriber behind a signed link download everything we store about
+/// them.
+#[tracing::instrument(
+    name = "Exporting the data of a subscriber on their request.",
+    skip(parameters, pool, links),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn export_my_data(
+    parameters: web::Query<SignedLinkParameters>,
+    pool: web::Data<PgPool>,
+    links: web::Data<UnsubscribeLinks>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    if !parameters.is_signed_by(&links) {
+        return Err(PreferencesError::InvalidLink);
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let data = collect_subscriber_data(&mut transaction, parameters.subscriber_id)
+        .await
+        .context("Failed to collect the data of the subscriber.")?
+        .ok_or(PreferencesError::UnknownSubscriber)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(attachment("my-data.json"))
+        .json(data))
+}
+
+/// Ask for confirmation, for the same reason as `unsubscribe_form`: erasing
+/// cannot be undone.
+#[tracing::instrument(
+    name = "Showing the erasure page.",
+    skip(parameters, links, request),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn erase_my_data_form(
+    parameters: web::Query<SignedLinkParameters>,
+    links: web::Data<UnsubscribeLinks>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    if !parameters.is_signed_by(&links) {
+        return Err(PreferencesError::InvalidLink);
+    }
+    let query_string = escape_html(request.query_string());
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>We will delete your subscription and everything we know about you.
+    We only keep a fingerprint of your email address, to never email you again.
+    This cannot be undone.</p>
+    <form action="/subscriptions/erase?{query_string}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#
+        )))
+}
+
+#[tracing::instrument(
+    name = "Erasing a subscriber on their request.",
+    skip(parameters, pool, links, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn erase_my_data(
+    parameters: web::Query<SignedLinkParameters>,
+    pool: web::Data<PgPool>,
+    links: web::Data<UnsubscribeLinks>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    if !parameters.is_signed_by(&links) {
+        return Err(PreferencesError::InvalidLink);
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let erased = erase_subscriber(&mut transaction, &hmac_secret, parameters.subscriber_id)
+        .await
+        .context("Failed to erase the subscriber.")?;
+    if !erased {
+        return Err(PreferencesError::UnknownSubscriber);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data has been erased</title>
+</head>
+<body>
+    <p>Your data has been erased.</p>
+</body>
+</html>"#,
+    ))
+}
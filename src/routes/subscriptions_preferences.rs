@@ -0,0 +1,255 @@
+use super::subscriptions_unsubscribe::SignedLinkParameters;
+use crate::domain::{EmailFormat, SubscriberName, SubscriptionStatus};
+use crate::lists::{
+    get_list_ids_of_subscriber, get_lists, list_checkboxes_html, parse_list_ids,
+    replace_list_memberships,
+};
+use crate::unsubscribe_links::UnsubscribeLinks;
+use crate::utils::{escape_html, see_other, split_repeated_field};
+use actix_web::http::header::ContentType;
+use actix_web::{http, web, HttpRequest, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages, Level};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The preferences link is invalid.")]
+    InvalidLink,
+    #[error("There is no subscriber associated with the preferences link.")]
+    UnknownSubscriber,
+    #[error("{0}")]
+    InvalidForm(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            PreferencesError::InvalidLink => http::StatusCode::UNAUTHORIZED,
+            PreferencesError::UnknownSubscriber => http::StatusCode::NOT_FOUND,
+            PreferencesError::InvalidForm(_) => http::StatusCode::BAD_REQUEST,
+            PreferencesError::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+struct Preferences {
+    name: String,
+    status: SubscriptionStatus,
+    email_format: EmailFormat,
+}
+
+/// Let the subscriber behind a signed link review what they receive.
+#[tracing::instrument(
+    name = "Showing the preferences page.",
+    skip(parameters, pool, links, request, flash_messages),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn preferences_form(
+    parameters: web::Query<SignedLinkParameters>,
+    pool: web::Data<PgPool>,
+    links: web::Data<UnsubscribeLinks>,
+    request: HttpRequest,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PreferencesError> {
+    if !parameters.is_signed_by(&links) {
+        return Err(PreferencesError::InvalidLink);
+    }
+    let preferences = get_preferences(&pool, parameters.subscriber_id)
+        .await
+        .context("Failed to fetch the preferences of the subscriber.")?
+        .ok_or(PreferencesError::UnknownSubscriber)?;
+    let lists = get_lists(pool.get_ref())
+        .await
+        .context("Failed to fetch the lists.")?;
+    let memberships = get_list_ids_of_subscriber(pool.get_ref(), parameters.subscriber_id)
+        .await
+        .context("Failed to fetch the lists of the subscriber.")?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        let color = match m.level() {
+            Level::Error => "red",
+            _ => "green",
+        };
+        msg_html.push_str(&format!(
+            r#"<p style="color: {color};">{}</p>"#,
+            escape_html(m.content())
+        ));
+    }
+    let query_string = escape_html(request.query_string());
+    let name = escape_html(&preferences.name);
+    let format_input = |format: EmailFormat, label: &str| {
+        let checked = if preferences.email_format == format {
+            " checked"
+        } else {
+            ""
+        };
+        format!(
+            r#"<label><input type="radio" name="email_format" value="{}"{checked}> {label}</label><br>"#,
+            format.as_str()
+        )
+    };
+    let format_inputs = format!(
+        "{}\n{}",
+        format_input(EmailFormat::Html, "HTML, with a plain text fallback"),
+        format_input(EmailFormat::PlainText, "Plain text only")
+    );
+    let lists_html = if lists.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<fieldset>\n<legend>Topics</legend>\n{}</fieldset>",
+            list_checkboxes_html(&lists, &memberships)
+        )
+    };
+    let unsubscribe_html = match preferences.status {
+        SubscriptionStatus::PendingConfirmation | SubscriptionStatus::Confirmed => format!(
+            r#"<form action="/subscriptions/unsubscribe?{query_string}" method="post">
+        <button type="submit">Unsubscribe from all emails</button>
+    </form>"#
+        ),
+        SubscriptionStatus::Unsubscribed | SubscriptionStatus::Suppressed => {
+            "<p>You are not subscribed to our newsletter anymore.</p>".to_string()
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/subscriptions/preferences?{query_string}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <fieldset>
+            <legend>Email format</legend>
+            {format_inputs}
+        </fieldset>
+        {lists_html}
+        <button type="submit">Save preferences</button>
+    </form>
+    {unsubscribe_html}
+    <p><a href="/subscriptions/data?{query_string}">Download your data</a></p>
+    <p><a href="/subscriptions/erase?{query_string}">Erase your data</a></p>
+</body>
+</html>"#
+        )))
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesFormData {
+    name: String,
+    email_format: String,
+}
+
+#[tracing::instrument(
+    name = "Updating the preferences of a subscriber.",
+    skip(form, parameters, pool, links, request),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn update_preferences(
+    form: web::Form<Vec<(String, String)>>,
+    parameters: web::Query<SignedLinkParameters>,
+    pool: web::Data<PgPool>,
+    links: web::Data<UnsubscribeLinks>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    if !parameters.is_signed_by(&links) {
+        return Err(PreferencesError::InvalidLink);
+    }
+    let preferences_page = format!("/subscriptions/preferences?{}", request.query_string());
+    let (form, list_ids): (PreferencesFormData, _) =
+        split_repeated_field(form.into_inner(), "list_id")
+            .map_err(|e| PreferencesError::InvalidForm(e.to_string()))?;
+    let email_format: EmailFormat = form
+        .email_format
+        .parse()
+        .map_err(PreferencesError::InvalidForm)?;
+    let list_ids = parse_list_ids(&list_ids).map_err(PreferencesError::InvalidForm)?;
+    let name = match form.name.parse::<SubscriberName>() {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let n_updated = update_subscriber(
+        &mut transaction,
+        parameters.subscriber_id,
+        &name,
+        email_format,
+    )
+    .await
+    .context("Failed to update the subscriber.")?;
+    if n_updated == 0 {
+        return Err(PreferencesError::UnknownSubscriber);
+    }
+    replace_list_memberships(&mut transaction, parameters.subscriber_id, &list_ids)
+        .await
+        .context("Failed to update the lists of the subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the preferences of a subscriber.")?;
+    FlashMessage::info("Your preferences have been saved.").send();
+    Ok(see_other(&preferences_page))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Preferences>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT name, status, email_format FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(record) = record else {
+        return Ok(None);
+    };
+    Ok(Some(Preferences {
+        name: record.name,
+        status: record.status.parse().map_err(anyhow::Error::msg)?,
+        email_format: record.email_format.parse().map_err(anyhow::Error::msg)?,
+    }))
+}
+
+#[tracing::instrument(skip(transaction, name))]
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    email_format: EmailFormat,
+) -> Result<u64, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2, email_format = $3
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref(),
+        email_format.as_str()
+    );
+    Ok(transaction.execute(query).await?.rows_affected())
+}
@@ -0,0 +1,128 @@
+use crate::unsubscribe_links::UnsubscribeLinks;
+use crate::utils::escape_html;
+use actix_web::http::header::ContentType;
+use actix_web::{http, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            UnsubscribeError::InvalidLink => http::StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The query string of the links built by `UnsubscribeLinks`.
+#[derive(Deserialize)]
+pub struct SignedLinkParameters {
+    pub subscriber_id: Uuid,
+    token: String,
+}
+
+impl SignedLinkParameters {
+    pub fn is_signed_by(&self, links: &UnsubscribeLinks) -> bool {
+        links.is_valid(self.subscriber_id, &self.token)
+    }
+
+    fn verify(&self, links: &UnsubscribeLinks) -> Result<(), UnsubscribeError> {
+        if self.is_signed_by(links) {
+            Ok(())
+        } else {
+            Err(UnsubscribeError::InvalidLink)
+        }
+    }
+}
+
+/// Ask for confirmation: link scanners and prefetchers follow every link they
+/// see, a `GET` must not unsubscribe anybody.
+#[tracing::instrument(
+    name = "Showing the unsubscribe page.",
+    skip(parameters, links, request)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<SignedLinkParameters>,
+    links: web::Data<UnsubscribeLinks>,
+    request: HttpRequest,
+) -> Result<HttpResponse, UnsubscribeError> {
+    parameters.verify(&links)?;
+    let action = escape_html(&format!("{}?{}", request.path(), request.query_string()));
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="{action}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#
+        )))
+}
+
+/// Both the confirmation form and mailbox providers' one-click unsubscribe
+/// (RFC 8058) end up here. The latter posts `List-Unsubscribe=One-Click`,
+/// which we do not need to look at: the link alone is enough.
+#[tracing::instrument(
+    name = "Unsubscribing a subscriber.",
+    skip(parameters, pool, links),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<SignedLinkParameters>,
+    pool: web::Data<PgPool>,
+    links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    parameters.verify(&links)?;
+    mark_subscriber_as_unsubscribed(&pool, parameters.subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`.")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed: you will not receive our newsletter anymore.</p>
+</body>
+</html>"#,
+    ))
+}
+
+/// Unsubscribing twice is not an error. Suppressed subscribers stay suppressed.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed.", skip(pool))]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed', unsubscribed_at = now()
+        WHERE id = $1 AND status IN ('pending_confirmation', 'confirmed')
+        "#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use crate::domain::Segment;
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+/// A segment definition saved under a name, to target issues with.
+pub struct SavedSegment {
+    pub segment_id: Uuid,
+    pub name: String,
+    pub definition: String,
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_segments(executor: impl PgExecutor<'_>) -> Result<Vec<SavedSegment>, sqlx::Error> {
+    sqlx::query_as!(
+        SavedSegment,
+        r#"
+        SELECT segment_id, name, definition FROM segments ORDER BY name
+        "#
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// The segment the issue is sent to, if any.
+#[tracing::instrument(skip(executor))]
+pub async fn get_issue_segment(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<Segment>, anyhow::Error> {
+    let definition = sqlx::query!(
+        r#"
+        SELECT s.definition
+        FROM newsletter_issues i
+        JOIN segments s ON s.segment_id = i.segment_id
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(|r| r.definition);
+    // Definitions are validated when they are saved
+    definition
+        .map(|d| d.parse().map_err(anyhow::Error::msg))
+        .transpose()
+        .context("Invalid segment definition")
+}
+
+/// Send the issue to the subscribers in this segment only. Returns whether the
+/// segment exists.
+#[tracing::instrument(skip(transaction))]
+pub async fn set_issue_segment(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET segment_id = s.segment_id
+        FROM segments s
+        WHERE i.newsletter_issue_id = $1 AND s.segment_id = $2
+        "#,
+        newsletter_issue_id,
+        segment_id
+    );
+    Ok(transaction.execute(query).await?.rows_affected() > 0)
+}
+
+/// How many confirmed subscribers are in the segment right now.
+#[tracing::instrument(skip(pool))]
+pub async fn count_confirmed_members(pool: &PgPool, segment: &Segment) -> Result<i64, sqlx::Error> {
+    let mut query =
+        QueryBuilder::new("SELECT COUNT(*) FROM subscriptions s WHERE s.status = 'confirmed' AND ");
+    push_segment_condition(&mut query, segment);
+    query.build_query_scalar().fetch_one(pool).await
+}
+
+/// Compile the segment to a boolean SQL expression on the subscriber `s`, with
+/// every value passed as a bind parameter.
+pub fn push_segment_condition(query: &mut QueryBuilder<'_, Postgres>, segment: &Segment) {
+    match segment {
+        Segment::Tag(tag) => {
+            query.push(
+                "EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = ",
+            );
+            query.push_bind(tag.as_ref().to_owned());
+            query.push(")");
+        }
+        Segment::List(name) => {
+            query.push(
+                "EXISTS (SELECT 1 FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id \
+                WHERE ls.subscriber_id = s.id AND l.name = ",
+            );
+            query.push_bind(name.clone());
+            query.push(")");
+        }
+        Segment::SignedUpBefore(date) => {
+            query.push("s.subscribed_at < ");
+            query.push_bind(start_of_day(*date));
+        }
+        Segment::SignedUpSince(date) => {
+            query.push("s.subscribed_at >= ");
+            query.push_bind(start_of_day(*date));
+        }
+        Segment::Not(segment) => {
+            query.push("NOT (");
+            push_segment_condition(query, segment);
+            query.push(")");
+        }
+        Segment::And(left, right) => push_binary_condition(query, left, "AND", right),
+        Segment::Or(left, right) => push_binary_condition(query, left, "OR", right),
+    }
+}
+
+fn push_binary_condition(
+    query: &mut QueryBuilder<'_, Postgres>,
+    left: &Segment,
+    operator: &str,
+    right: &Segment,
+) {
+    query.push("(");
+    push_segment_condition(query, left);
+    query.push(format!(") {operator} ("));
+    push_segment_condition(query, right);
+    query.push(")");
+}
+
+fn start_of_day(date: NaiveDate) -> chrono::DateTime<Utc> {
+    Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_segment_condition;
+    use crate::domain::Segment;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn compile(definition: &str) -> String {
+        let segment: Segment = definition.parse().unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("");
+        push_segment_condition(&mut query, &segment);
+        query.into_sql()
+    }
+
+    #[test]
+    fn values_are_bound_not_inlined() {
+        let sql = compile(r#"list:"Robert'); DROP TABLE lists; --""#);
+
+        assert!(sql.ends_with("l.name = $1)"), "{sql}");
+        assert!(!sql.contains("DROP"));
+    }
+
+    #[test]
+    fn boolean_operators_are_parenthesised() {
+        let sql =
+            compile("not tag:a and (signed_up_before:2024-01-01 or signed_up_since:2024-03-01)");
+
+        assert_eq!(
+            sql,
+            "(NOT (EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = $1))) \
+            AND ((s.subscribed_at < $2) OR (s.subscribed_at >= $3))"
+        );
+    }
+}
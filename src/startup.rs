@@ -1,10 +1,22 @@
 use crate::authentication::reject_anonymous_users;
-use crate::config::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::click_links::ClickLinks;
+use crate::config::{ApplicationSettings, DatabaseSettings, Settings, SubscriptionSettings};
+use crate::email_client::EmailSender;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, login,
-    login_form, logout, publish_newsletter, publish_newsletter_form, subscribe,
+    admin_dashboard, cancel_newsletter_issue, change_password, change_password_form, confirm,
+    create_draft, create_layout, create_list, create_segment, delete_draft, delete_layout,
+    delivery_failures, discard_failures, edit_draft_form, edit_layout_form, erase_my_data,
+    erase_my_data_form, erase_subscriber_by_email, export_my_data, export_subscriber_data,
+    export_subscribers, health_check, home, import_form_config, import_report, imports_form,
+    layouts_page, lists_form, login, login_form, logout, newsletter_issue_progress,
+    pause_newsletter_issue, preferences_form, preview_built_in_layout, preview_layout,
+    preview_newsletter_issue, publish_draft, publish_newsletter, publish_newsletter_form,
+    requeue_failures, reschedule_newsletter_issue, resend_confirmation, resume_newsletter_issue,
+    segments_form, send_test_newsletter, subscribe, subscribers_page, tag_subscriber, tags_form,
+    track_click, unsubscribe, unsubscribe_form, update_draft, update_layout, update_preferences,
+    upload_import,
 };
+use crate::unsubscribe_links::UnsubscribeLinks;
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
@@ -18,6 +30,8 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::io;
 use std::net::TcpListener;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
 // A new type to exposure the server local port
@@ -41,9 +55,10 @@ impl Application {
             listener,
             conn_pool,
             email_client,
-            config.application.base_url,
-            config.application.hmac_secret,
+            config.application,
+            config.subscriptions,
             config.redis_uri,
+            config.worker.notify_channel,
         )
         .await?;
         Ok(Application { server, port })
@@ -53,7 +68,14 @@ impl Application {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), io::Error> {
+    /// Serve requests until `shutdown` is cancelled, then stop accepting
+    /// connections and let in-flight requests finish.
+    pub async fn run_until_stopped(self, shutdown: CancellationToken) -> Result<(), io::Error> {
+        let server = self.server.handle();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            server.stop(true).await;
+        });
         self.server.await
     }
 }
@@ -69,17 +91,35 @@ pub fn get_conn_pool(config: &DatabaseSettings) -> PgPool {
 pub struct ApplicationBaseUrl(pub String);
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
+/// The channel to `NOTIFY` when delivery tasks are enqueued.
+#[derive(Clone)]
+pub struct DeliveryQueueChannel(pub String);
 async fn run(
     listener: TcpListener,
     conn_pool: PgPool,
-    email_client: EmailClient,
-    confirm_base_url: String,
-    hmac_secret: Secret<String>,
+    email_client: Arc<dyn EmailSender>,
+    application: ApplicationSettings,
+    subscriptions: SubscriptionSettings,
     redis_uri: Secret<String>,
+    queue_channel: String,
 ) -> Result<Server, anyhow::Error> {
-    let email_client = web::Data::new(email_client);
+    let shutdown_timeout = application.shutdown_timeout();
+    let ApplicationSettings {
+        base_url: confirm_base_url,
+        hmac_secret,
+        ..
+    } = application;
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client);
     let conn_pool = web::Data::new(conn_pool);
     let base_url = web::Data::new(ApplicationBaseUrl(confirm_base_url));
+    let queue_channel = web::Data::new(DeliveryQueueChannel(queue_channel));
+    let subscriptions = web::Data::new(subscriptions);
+    let unsubscribe_links = web::Data::new(UnsubscribeLinks::new(
+        base_url.0.clone(),
+        hmac_secret.clone(),
+    ));
+    let click_links = web::Data::new(ClickLinks::new(base_url.0.clone(), hmac_secret.clone()));
+    let hmac_data = web::Data::new(HmacSecret(hmac_secret.clone()));
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
@@ -99,6 +139,27 @@ async fn run(
             .route("/login", web::post().to(login))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(preferences_form),
+            )
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(update_preferences),
+            )
+            .route("/subscriptions/data", web::get().to(export_my_data))
+            .route("/subscriptions/erase", web::get().to(erase_my_data_form))
+            .route("/subscriptions/erase", web::post().to(erase_my_data))
+            .route("/t/c/{token}", web::get().to(track_click))
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
@@ -107,12 +168,102 @@ async fn run(
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(logout))
                     .route("/newsletters", web::get().to(publish_newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter)),
+                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .route("/newsletters/drafts", web::post().to(create_draft))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(newsletter_issue_progress),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/edit",
+                        web::get().to(edit_draft_form),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/edit",
+                        web::post().to(update_draft),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/delete",
+                        web::post().to(delete_draft),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/preview",
+                        web::get().to(preview_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/publish",
+                        web::post().to(publish_draft),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/test",
+                        web::post().to(send_test_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/reschedule",
+                        web::post().to(reschedule_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/pause",
+                        web::post().to(pause_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/resume",
+                        web::post().to(resume_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/cancel",
+                        web::post().to(cancel_newsletter_issue),
+                    )
+                    .route("/lists", web::get().to(lists_form))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/tags", web::get().to(tags_form))
+                    .route("/tags", web::post().to(tag_subscriber))
+                    .route("/segments", web::get().to(segments_form))
+                    .route("/segments", web::post().to(create_segment))
+                    .route("/layouts", web::get().to(layouts_page))
+                    .route("/layouts", web::post().to(create_layout))
+                    // Ahead of the layout id routes, which would match it and fail to parse it
+                    .route(
+                        "/layouts/built-in/preview",
+                        web::get().to(preview_built_in_layout),
+                    )
+                    .route("/layouts/{layout_id}/edit", web::get().to(edit_layout_form))
+                    .route("/layouts/{layout_id}/edit", web::post().to(update_layout))
+                    .route(
+                        "/layouts/{layout_id}/preview",
+                        web::get().to(preview_layout),
+                    )
+                    .route("/layouts/{layout_id}/delete", web::post().to(delete_layout))
+                    .service(
+                        web::resource("/imports")
+                            .app_data(import_form_config())
+                            .route(web::get().to(imports_form))
+                            .route(web::post().to(upload_import)),
+                    )
+                    .route("/imports/{import_id}/report", web::get().to(import_report))
+                    .route("/subscribers", web::get().to(subscribers_page))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/subscribers/data", web::get().to(export_subscriber_data))
+                    .route(
+                        "/subscribers/erase",
+                        web::post().to(erase_subscriber_by_email),
+                    )
+                    .route("/failures", web::get().to(delivery_failures))
+                    .route("/failures/requeue", web::post().to(requeue_failures))
+                    .route("/failures/discard", web::post().to(discard_failures)),
             )
             .app_data(conn_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(queue_channel.clone())
+            .app_data(unsubscribe_links.clone())
+            .app_data(click_links.clone())
+            .app_data(hmac_data.clone())
+            .app_data(subscriptions.clone())
     })
+    // Signals are handled in `main`, which stops the worker at the same time
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
     Ok(server)
@@ -0,0 +1,488 @@
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::startup::HmacSecret;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{Executor, PgExecutor, Postgres, Transaction};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// An irreversible stand-in for an email address, to recognise it once the
+/// address itself is gone. Case does not matter to mail servers, nor here.
+/// It is keyed so that a leaked table cannot be matched against a list of
+/// known addresses.
+pub fn email_hash(secret: &HmacSecret, email: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.expose_secret().as_bytes()).unwrap();
+    mac.update(email.trim().to_lowercase().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Everything we store about a subscriber, to answer their access requests.
+#[derive(Serialize)]
+pub struct SubscriberData {
+    pub subscriber: SubscriberRecord,
+    pub lists: Vec<String>,
+    pub tags: Vec<String>,
+    /// When we sent them confirmation links: the tokens are credentials, not data.
+    pub confirmation_links_sent_at: Vec<DateTime<Utc>>,
+    pub queued_deliveries: Vec<QueuedDelivery>,
+    pub deliveries: Vec<Delivery>,
+    pub delivery_failures: Vec<DeliveryFailure>,
+    pub link_clicks: Vec<LinkClick>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub email_format: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct QueuedDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub execute_after: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct Delivery {
+    pub newsletter_issue_id: Uuid,
+    pub status: String,
+    pub provider_message_id: Option<String>,
+    pub error_message: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryFailure {
+    pub newsletter_issue_id: Uuid,
+    pub last_error: String,
+    pub n_attempts: i16,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct LinkClick {
+    pub newsletter_issue_id: Uuid,
+    pub url: String,
+    pub clicked_at: DateTime<Utc>,
+}
+
+/// A row of the export of all subscribers.
+#[derive(Serialize)]
+pub struct ExportedSubscriber {
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub email_format: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub lists: Vec<String>,
+}
+
+/// Every subscriber, or only those with this status and in this list.
+#[tracing::instrument(skip(executor))]
+pub async fn get_subscribers_for_export(
+    executor: impl PgExecutor<'_>,
+    status: Option<SubscriptionStatus>,
+    list_id: Option<Uuid>,
+) -> Result<Vec<ExportedSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT
+            s.email,
+            s.name,
+            s.status,
+            s.email_format,
+            s.subscribed_at,
+            s.unsubscribed_at,
+            ARRAY(
+                SELECT t.tag FROM subscriber_tags t
+                WHERE t.subscriber_id = s.id
+                ORDER BY t.tag
+            ) AS "tags!",
+            ARRAY(
+                SELECT l.name FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id
+                ORDER BY l.name
+            ) AS "lists!"
+        FROM subscriptions s
+        WHERE ($1::text IS NULL OR s.status = $1)
+            AND (
+                $2::uuid IS NULL
+                OR EXISTS (
+                    SELECT 1 FROM list_subscriptions ls
+                    WHERE ls.subscriber_id = s.id AND ls.list_id = $2
+                )
+            )
+        ORDER BY s.subscribed_at, s.email
+        "#,
+        status.map(|s| s.as_str()),
+        list_id
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// The export as a CSV file. Its `email`, `name` and `tags` columns can be
+/// imported back.
+pub fn subscribers_csv(subscribers: &[ExportedSubscriber]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "email",
+        "name",
+        "status",
+        "email_format",
+        "subscribed_at",
+        "unsubscribed_at",
+        "tags",
+        "lists",
+    ])?;
+    for s in subscribers {
+        writer.write_record([
+            s.email.as_str(),
+            &s.name,
+            &s.status,
+            &s.email_format,
+            &s.subscribed_at.to_rfc3339(),
+            &s.unsubscribed_at
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            &s.tags.join(","),
+            &s.lists.join(";"),
+        ])?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// The id of the subscriber with this email, if any.
+#[tracing::instrument(skip(executor))]
+pub async fn get_subscriber_id(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(record.map(|r| r.id))
+}
+
+/// Collect the data of the subscriber, `None` if there is no such subscriber.
+#[tracing::instrument(skip(transaction))]
+pub async fn collect_subscriber_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, sqlx::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, name, status, email_format, subscribed_at, unsubscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    let Some(subscriber) = subscriber else {
+        return Ok(None);
+    };
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.name
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.name)
+    .collect();
+    let tags = sqlx::query!(
+        r#"
+        SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.tag)
+    .collect();
+    let confirmation_links_sent_at = sqlx::query!(
+        r#"
+        SELECT created_at FROM subscription_tokens WHERE subscriber_id = $1 ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.created_at)
+    .collect();
+    let queued_deliveries = sqlx::query_as!(
+        QueuedDelivery,
+        r#"
+        SELECT newsletter_issue_id, execute_after
+        FROM issue_delivery_queue
+        WHERE subscriber_email = $1
+        ORDER BY execute_after
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT newsletter_issue_id, status, provider_message_id, error_message, attempted_at
+        FROM issue_deliveries
+        WHERE subscriber_email = $1
+        ORDER BY attempted_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let delivery_failures = sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT newsletter_issue_id, last_error, n_attempts, failed_at
+        FROM issue_delivery_failures
+        WHERE subscriber_email = $1
+        ORDER BY failed_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let link_clicks = sqlx::query_as!(
+        LinkClick,
+        r#"
+        SELECT newsletter_issue_id, url, clicked_at
+        FROM link_clicks
+        WHERE subscriber_id = $1
+        ORDER BY clicked_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(Some(SubscriberData {
+        subscriber,
+        lists,
+        tags,
+        confirmation_links_sent_at,
+        queued_deliveries,
+        deliveries,
+        delivery_failures,
+        link_clicks,
+    }))
+}
+
+/// Purge the subscriber: their own rows, pending deliveries and delivery
+/// failures are deleted and their email is replaced by its hash in the delivery
+/// log, which the progress of past issues is computed from. Returns whether
+/// there was such a subscriber.
+#[tracing::instrument(skip(transaction, hmac_secret))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT email, status FROM subscriptions WHERE id = $1 FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    let Some(subscriber) = subscriber else {
+        return Ok(false);
+    };
+    let email_hash = email_hash(hmac_secret, &subscriber.email);
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue WHERE subscriber_email = $1
+        "#,
+        subscriber.email
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_failures WHERE subscriber_email = $1
+        "#,
+        subscriber.email
+    );
+    transaction.execute(query).await?;
+    // Error messages may quote the address
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET subscriber_email = $2, error_message = NULL
+        WHERE subscriber_email = $1
+        "#,
+        subscriber.email,
+        email_hash
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM subscriber_import_rejections WHERE email = $1
+        "#,
+        subscriber.email
+    );
+    transaction.execute(query).await?;
+    // Tokens, list memberships, tags and clicks go with it
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO erased_subscribers (email_hash, status)
+        VALUES ($1, $2)
+        ON CONFLICT (email_hash) DO UPDATE
+        SET status = EXCLUDED.status, erased_at = now()
+        "#,
+        email_hash,
+        subscriber.status
+    );
+    transaction.execute(query).await?;
+    Ok(true)
+}
+
+/// Whether the address was suppressed (it bounced, or its owner complained)
+/// before its subscriber was erased: it must not be signed up again.
+#[tracing::instrument(skip(executor, hmac_secret, email))]
+pub async fn is_suppressed_after_erasure(
+    executor: impl PgExecutor<'_>,
+    hmac_secret: &HmacSecret,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT status FROM erased_subscribers WHERE email_hash = $1
+        "#,
+        email_hash(hmac_secret, email.as_ref())
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(record.is_some_and(|r| r.status == SubscriptionStatus::Suppressed.as_str()))
+}
+
+/// The hashes of the addresses among `emails` that were suppressed before their
+/// subscriber was erased.
+#[tracing::instrument(skip_all)]
+pub async fn get_suppressed_after_erasure(
+    executor: impl PgExecutor<'_>,
+    hmac_secret: &HmacSecret,
+    emails: &[&str],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let hashes: Vec<String> = emails.iter().map(|e| email_hash(hmac_secret, e)).collect();
+    let suppressed = sqlx::query!(
+        r#"
+        SELECT email_hash FROM erased_subscribers
+        WHERE email_hash = ANY($1) AND status = 'suppressed'
+        "#,
+        &hashes
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(|r| r.email_hash)
+    .collect();
+    Ok(suppressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{email_hash, subscribers_csv, ExportedSubscriber};
+    use crate::startup::HmacSecret;
+    use chrono::{TimeZone, Utc};
+    use secrecy::Secret;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn exports_list_tags_and_lists_in_a_single_column_each() {
+        let csv = subscribers_csv(&[ExportedSubscriber {
+            email: "ursula@example.com".into(),
+            name: "Ursula Le Guin".into(),
+            status: "confirmed".into(),
+            email_format: "html".into(),
+            subscribed_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            unsubscribed_at: None,
+            tags: vec!["sf".into(), "vip".into()],
+            lists: vec!["Events".into(), "Release notes".into()],
+        }])
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "email,name,status,email_format,subscribed_at,unsubscribed_at,tags,lists\n\
+            ursula@example.com,Ursula Le Guin,confirmed,html,2024-01-02T03:04:05+00:00,,\
+            \"sf,vip\",Events;Release notes\n"
+        );
+    }
+
+    fn secret(secret: &str) -> HmacSecret {
+        HmacSecret(Secret::new(secret.into()))
+    }
+
+    #[test]
+    fn email_hashes_ignore_case_and_surrounding_spaces() {
+        let secret = secret("a-secret");
+        assert_eq!(
+            email_hash(&secret, "Ursula@Example.com "),
+            email_hash(&secret, "ursula@example.com")
+        );
+        assert_ne!(
+            email_hash(&secret, "ursula@example.com"),
+            email_hash(&secret, "octavia@example.com")
+        );
+    }
+
+    #[test]
+    fn email_hashes_do_not_contain_the_address() {
+        let hash = email_hash(&secret("a-secret"), "ursula@example.com");
+
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("ursula"));
+    }
+
+    #[test]
+    fn email_hashes_are_keyed_by_the_secret() {
+        let hash = email_hash(&secret("a-secret"), "ursula@example.com");
+
+        assert_ne!(
+            hash,
+            hex::encode(Sha256::digest("ursula@example.com".as_bytes()))
+        );
+        assert_ne!(
+            hash,
+            email_hash(&secret("another-secret"), "ursula@example.com")
+        );
+    }
+}
@@ -0,0 +1,649 @@
+use crate::domain::{ImportMode, NewSubscriber, SubscriberTag};
+use crate::email_client::EmailSender;
+use crate::email_layout::{get_default_layout, EmailLayout};
+use crate::lists::get_lists;
+use crate::routes::{gen_subscription_token, send_confirmation_email};
+use crate::startup::HmacSecret;
+use crate::subscriber_data::{email_hash, get_suppressed_after_erasure};
+use anyhow::Context;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+/// How many rows are inserted per transaction.
+const BATCH_SIZE: usize = 500;
+/// How many confirmation emails are in flight at once, for double opt-in imports.
+const CONCURRENT_CONFIRMATION_EMAILS: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Why a row of an import did not make it in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionKind {
+    Invalid,
+    Duplicate,
+    /// Suppressed (it bounced, or its owner complained) before its subscriber
+    /// was erased.
+    Suppressed,
+    /// Imported, but the confirmation email could not be sent.
+    Unsent,
+}
+
+impl RejectionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionKind::Invalid => "invalid",
+            RejectionKind::Duplicate => "duplicate",
+            RejectionKind::Suppressed => "suppressed",
+            RejectionKind::Unsent => "unsent",
+        }
+    }
+}
+
+impl FromStr for RejectionKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "invalid" => Ok(RejectionKind::Invalid),
+            "duplicate" => Ok(RejectionKind::Duplicate),
+            "suppressed" => Ok(RejectionKind::Suppressed),
+            "unsent" => Ok(RejectionKind::Unsent),
+            other => Err(format!("{} is not a valid rejection kind.", other)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rejection {
+    pub line_number: i32,
+    pub email: String,
+    pub name: String,
+    pub kind: RejectionKind,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub struct ImportSummary {
+    pub import_id: Uuid,
+    pub n_rows: i32,
+    pub n_imported: i32,
+    pub n_rejected: i32,
+    pub n_duplicates: i32,
+}
+
+/// A row that passed validation, waiting for its batch to be inserted.
+#[derive(Debug)]
+struct ImportRow {
+    line_number: i32,
+    subscriber: NewSubscriber,
+    tags: Vec<SubscriberTag>,
+    list_id: Option<Uuid>,
+}
+
+/// Where the columns we know about are in the file: `email` and `name` are
+/// required, `tags` (comma separated) and `list` (a list name) are optional.
+struct Columns {
+    email: usize,
+    name: usize,
+    tags: Option<usize>,
+    list: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &StringRecord) -> Result<Self, String> {
+        let find = |column: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(column));
+        match (find("email"), find("name")) {
+            (Some(email), Some(name)) => Ok(Columns {
+                email,
+                name,
+                tags: find("tags"),
+                list: find("list"),
+            }),
+            _ => Err("The CSV file needs a header row with `email` and `name` columns.".into()),
+        }
+    }
+}
+
+/// Import every valid row of the CSV file, in batches, and store a report of
+/// the rows that were left out. Addresses that are already subscribed, in any
+/// state, are left untouched and reported as duplicates.
+#[tracing::instrument(skip(pool, email_client, base_url, hmac_secret, csv), fields(import_id = tracing::field::Empty))]
+pub async fn import_subscribers(
+    pool: &PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    mode: ImportMode,
+    csv: &[u8],
+) -> Result<ImportSummary, ImportError> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(csv);
+    let headers = reader
+        .headers()
+        .map_err(|e| ImportError::InvalidFile(format!("The CSV file cannot be read: {e}")))?;
+    let columns = Columns::from_headers(headers).map_err(ImportError::InvalidFile)?;
+    let lists: HashMap<String, Uuid> = get_lists(pool)
+        .await
+        .context("Failed to fetch the lists")?
+        .into_iter()
+        .map(|l| (l.name, l.list_id))
+        .collect();
+    let layout = Arc::new(
+        get_default_layout(pool)
+            .await
+            .context("Failed to fetch the layout of the confirmation email")?,
+    );
+
+    let mut n_rows = 0;
+    let mut n_imported = 0;
+    let mut rejections = Vec::new();
+    let mut seen: HashMap<String, i32> = HashMap::new();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for record in reader.records() {
+        n_rows += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                rejections.push(Rejection {
+                    line_number: e.position().map_or(0, |p| p.line() as i32),
+                    email: String::new(),
+                    name: String::new(),
+                    kind: RejectionKind::Invalid,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line_number = record.position().map_or(0, |p| p.line() as i32);
+        let row = match parse_row(line_number, &record, &columns, &lists) {
+            Ok(row) => row,
+            Err(reason) => {
+                rejections.push(rejection(
+                    line_number,
+                    &record,
+                    &columns,
+                    RejectionKind::Invalid,
+                    reason,
+                ));
+                continue;
+            }
+        };
+        if let Some(first_line) = seen.get(row.subscriber.email.as_ref()) {
+            rejections.push(rejection(
+                line_number,
+                &record,
+                &columns,
+                RejectionKind::Duplicate,
+                format!("Duplicate of line {first_line}."),
+            ));
+            continue;
+        }
+        seen.insert(row.subscriber.email.as_ref().to_owned(), line_number);
+        batch.push(row);
+        if batch.len() == BATCH_SIZE {
+            n_imported += import_batch(
+                pool,
+                &email_client,
+                &layout,
+                base_url,
+                hmac_secret,
+                mode,
+                &mut batch,
+                &mut rejections,
+            )
+            .await?;
+        }
+    }
+    if !batch.is_empty() {
+        n_imported += import_batch(
+            pool,
+            &email_client,
+            &layout,
+            base_url,
+            hmac_secret,
+            mode,
+            &mut batch,
+            &mut rejections,
+        )
+        .await?;
+    }
+
+    let summary = ImportSummary {
+        import_id: Uuid::new_v4(),
+        n_rows,
+        n_imported,
+        n_rejected: count(&rejections, RejectionKind::Invalid)
+            + count(&rejections, RejectionKind::Suppressed),
+        n_duplicates: count(&rejections, RejectionKind::Duplicate),
+    };
+    tracing::Span::current().record("import_id", tracing::field::display(&summary.import_id));
+    store_import_report(pool, mode, &summary, &rejections)
+        .await
+        .context("Failed to store the import report")?;
+    Ok(summary)
+}
+
+fn parse_row(
+    line_number: i32,
+    record: &StringRecord,
+    columns: &Columns,
+    lists: &HashMap<String, Uuid>,
+) -> Result<ImportRow, String> {
+    let field = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or_default();
+    let email = field(Some(columns.email)).parse()?;
+    let name = field(Some(columns.name)).parse()?;
+    let tags = SubscriberTag::parse_list(field(columns.tags))?;
+    let list_id = match field(columns.list) {
+        "" => None,
+        list => Some(
+            *lists
+                .get(list)
+                .ok_or_else(|| format!("There is no list named {list}."))?,
+        ),
+    };
+    Ok(ImportRow {
+        line_number,
+        subscriber: NewSubscriber { email, name },
+        tags,
+        list_id,
+    })
+}
+
+fn rejection(
+    line_number: i32,
+    record: &StringRecord,
+    columns: &Columns,
+    kind: RejectionKind,
+    reason: String,
+) -> Rejection {
+    Rejection {
+        line_number,
+        email: record.get(columns.email).unwrap_or_default().to_owned(),
+        name: record.get(columns.name).unwrap_or_default().to_owned(),
+        kind,
+        reason,
+    }
+}
+
+fn count(rejections: &[Rejection], kind: RejectionKind) -> i32 {
+    rejections.iter().filter(|r| r.kind == kind).count() as i32
+}
+
+/// Insert the rows in a single transaction, then send the confirmation emails
+/// if they have to go through double opt-in. Empties `batch` and returns how
+/// many rows were imported.
+#[allow(clippy::too_many_arguments)]
+async fn import_batch(
+    pool: &PgPool,
+    email_client: &Arc<dyn EmailSender>,
+    layout: &Arc<EmailLayout>,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    mode: ImportMode,
+    batch: &mut Vec<ImportRow>,
+    rejections: &mut Vec<Rejection>,
+) -> Result<i32, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let emails: Vec<&str> = batch.iter().map(|r| r.subscriber.email.as_ref()).collect();
+    let suppressed = get_suppressed_after_erasure(&mut *transaction, hmac_secret, &emails)
+        .await
+        .context("Failed to look a batch of emails up among erased subscribers")?;
+    let (suppressed, kept): (Vec<_>, Vec<_>) = batch.drain(..).partition(|row| {
+        suppressed.contains(&email_hash(hmac_secret, row.subscriber.email.as_ref()))
+    });
+    *batch = kept;
+    rejections.extend(suppressed.into_iter().map(|row| Rejection {
+        line_number: row.line_number,
+        email: row.subscriber.email.as_ref().to_owned(),
+        name: row.subscriber.name.as_ref().to_owned(),
+        kind: RejectionKind::Suppressed,
+        reason: "The address was suppressed before its subscriber was erased.".into(),
+    }));
+    let inserted = insert_subscribers(&mut transaction, mode, batch)
+        .await
+        .context("Failed to insert a batch of subscribers")?;
+    let (rows, duplicates): (Vec<_>, Vec<_>) = batch
+        .drain(..)
+        .map(|row| (inserted.get(row.subscriber.email.as_ref()).copied(), row))
+        .partition(|(subscriber_id, _)| subscriber_id.is_some());
+    rejections.extend(duplicates.into_iter().map(|(_, row)| Rejection {
+        line_number: row.line_number,
+        email: row.subscriber.email.as_ref().to_owned(),
+        name: row.subscriber.name.as_ref().to_owned(),
+        kind: RejectionKind::Duplicate,
+        reason: "Already a subscriber.".into(),
+    }));
+    let rows: Vec<_> = rows
+        .into_iter()
+        .map(|(subscriber_id, row)| (subscriber_id.unwrap(), row))
+        .collect();
+    insert_tags_and_memberships(&mut transaction, &rows)
+        .await
+        .context("Failed to store the tags and lists of a batch of subscribers")?;
+    let n_imported = rows.len() as i32;
+    if mode == ImportMode::Confirmed {
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to import subscribers")?;
+        return Ok(n_imported);
+    }
+
+    let rows: Vec<_> = rows
+        .into_iter()
+        .map(|(subscriber_id, row)| (subscriber_id, gen_subscription_token(), row))
+        .collect();
+    let (subscriber_ids, tokens): (Vec<Uuid>, Vec<String>) = rows
+        .iter()
+        .map(|(subscriber_id, token, _)| (*subscriber_id, token.clone()))
+        .unzip();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        SELECT * FROM UNNEST($1::text[], $2::uuid[])
+        "#,
+        &tokens,
+        &subscriber_ids
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to store the confirmation tokens of a batch of subscribers")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers")?;
+    let mut rows = rows.into_iter().peekable();
+    while rows.peek().is_some() {
+        let mut sends = JoinSet::new();
+        for (_, token, row) in rows.by_ref().take(CONCURRENT_CONFIRMATION_EMAILS) {
+            let email_client = email_client.clone();
+            let layout = layout.clone();
+            let base_url = base_url.to_owned();
+            sends.spawn(async move {
+                let email = row.subscriber.email.as_ref().to_owned();
+                let name = row.subscriber.name.as_ref().to_owned();
+                let outcome = send_confirmation_email(
+                    email_client.as_ref(),
+                    &layout,
+                    row.subscriber,
+                    &base_url,
+                    &token,
+                )
+                .await;
+                (row.line_number, email, name, outcome)
+            });
+        }
+        while let Some(sent) = sends.join_next().await {
+            let (line_number, email, name, outcome) =
+                sent.context("A confirmation email task panicked")?;
+            if let Err(e) = outcome {
+                tracing::warn!(error.cause_chain = ?e, line_number, "Failed to send a confirmation email.");
+                // Like any pending subscriber who never confirms, they get
+                // cleaned up eventually: importing the row again is safe.
+                rejections.push(Rejection {
+                    line_number,
+                    email,
+                    name,
+                    kind: RejectionKind::Unsent,
+                    reason: "Imported, but the confirmation email could not be sent.".into(),
+                });
+            }
+        }
+    }
+    Ok(n_imported)
+}
+
+/// Insert the subscribers whose email is not taken yet: returns their ids, by email.
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    mode: ImportMode,
+    rows: &[ImportRow],
+) -> Result<HashMap<String, Uuid>, sqlx::Error> {
+    let status = match mode {
+        ImportMode::DoubleOptIn => "pending_confirmation",
+        ImportMode::Confirmed => "confirmed",
+    };
+    let ids: Vec<Uuid> = rows.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = rows
+        .iter()
+        .map(|r| r.subscriber.email.as_ref().to_owned())
+        .collect();
+    let names: Vec<String> = rows
+        .iter()
+        .map(|r| r.subscriber.name.as_ref().to_owned())
+        .collect();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT id, email, name, now(), $4
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS r (id, email, name)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id, email
+        "#,
+        &ids,
+        &emails,
+        &names,
+        status
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(inserted.into_iter().map(|r| (r.email, r.id)).collect())
+}
+
+async fn insert_tags_and_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    rows: &[(Uuid, ImportRow)],
+) -> Result<(), sqlx::Error> {
+    let (tagged_ids, tags): (Vec<Uuid>, Vec<String>) = rows
+        .iter()
+        .flat_map(|(id, row)| row.tags.iter().map(|t| (*id, t.as_ref().to_owned())))
+        .unzip();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT * FROM UNNEST($1::uuid[], $2::text[])
+        "#,
+        &tagged_ids,
+        &tags
+    );
+    transaction.execute(query).await?;
+    let (list_ids, member_ids): (Vec<Uuid>, Vec<Uuid>) = rows
+        .iter()
+        .filter_map(|(id, row)| row.list_id.map(|list_id| (list_id, *id)))
+        .unzip();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id)
+        SELECT * FROM UNNEST($1::uuid[], $2::uuid[])
+        "#,
+        &list_ids,
+        &member_ids
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+async fn store_import_report(
+    pool: &PgPool,
+    mode: ImportMode,
+    summary: &ImportSummary,
+    rejections: &[Rejection],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports
+            (import_id, mode, n_rows, n_imported, n_rejected, n_duplicates)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        summary.import_id,
+        mode.as_str(),
+        summary.n_rows,
+        summary.n_imported,
+        summary.n_rejected,
+        summary.n_duplicates
+    );
+    transaction.execute(query).await?;
+    let mut line_numbers = Vec::with_capacity(rejections.len());
+    let mut emails = Vec::with_capacity(rejections.len());
+    let mut names = Vec::with_capacity(rejections.len());
+    let mut kinds = Vec::with_capacity(rejections.len());
+    let mut reasons = Vec::with_capacity(rejections.len());
+    for rejection in rejections {
+        line_numbers.push(rejection.line_number);
+        emails.push(rejection.email.as_str());
+        names.push(rejection.name.as_str());
+        kinds.push(rejection.kind.as_str());
+        reasons.push(rejection.reason.as_str());
+    }
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_import_rejections
+            (import_id, line_number, email, name, kind, reason)
+        SELECT $1, * FROM UNNEST($2::int[], $3::text[], $4::text[], $5::text[], $6::text[])
+        "#,
+        summary.import_id,
+        &line_numbers,
+        &emails as &[&str],
+        &names as &[&str],
+        &kinds as &[&str],
+        &reasons as &[&str]
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await
+}
+
+/// The rows of an import that did not make it in, `None` if there is no such import.
+#[tracing::instrument(skip(pool))]
+pub async fn get_import_rejections(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<Vec<Rejection>>, anyhow::Error> {
+    let exists = sqlx::query!(
+        r#"
+        SELECT import_id FROM subscriber_imports WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+    if !exists {
+        return Ok(None);
+    }
+    let rejections = sqlx::query!(
+        r#"
+        SELECT line_number, email, name, kind, reason
+        FROM subscriber_import_rejections
+        WHERE import_id = $1
+        ORDER BY line_number
+        "#,
+        import_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(Rejection {
+            line_number: r.line_number,
+            email: r.email,
+            name: r.name,
+            kind: r.kind.parse().map_err(anyhow::Error::msg)?,
+            reason: r.reason,
+        })
+    })
+    .collect::<Result<_, anyhow::Error>>()?;
+    Ok(Some(rejections))
+}
+
+/// The rows of an import that did not make it in, as a CSV file.
+pub fn rejections_csv(rejections: &[Rejection]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["line", "email", "name", "kind", "reason"])?;
+    for r in rejections {
+        writer.write_record([
+            r.line_number.to_string().as_str(),
+            &r.email,
+            &r.name,
+            r.kind.as_str(),
+            &r.reason,
+        ])?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_row, rejections_csv, Columns, Rejection, RejectionKind};
+    use csv::StringRecord;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn columns(headers: &[&str]) -> Result<Columns, String> {
+        Columns::from_headers(&StringRecord::from(headers.to_vec()))
+    }
+
+    #[test]
+    fn email_and_name_columns_are_required_in_any_order_and_case() {
+        let columns = columns(&["Name", "tags", "EMAIL"]).unwrap();
+        assert_eq!((columns.email, columns.name), (2, 0));
+        assert_eq!((columns.tags, columns.list), (Some(1), None));
+
+        assert!(super::Columns::from_headers(&StringRecord::from(vec!["email"])).is_err());
+    }
+
+    #[test]
+    fn rows_are_validated_like_signups() {
+        let columns = columns(&["email", "name", "tags", "list"]).unwrap();
+        let list_id = Uuid::new_v4();
+        let lists = HashMap::from([("Events".to_string(), list_id)]);
+        let parse =
+            |fields: &[&str]| parse_row(2, &StringRecord::from(fields.to_vec()), &columns, &lists);
+
+        let row = parse(&["ursula@example.com", "Ursula", "vip,beta", "Events"]).unwrap();
+        assert_eq!(row.tags.len(), 2);
+        assert_eq!(row.list_id, Some(list_id));
+        assert!(parse(&["ursula@example.com", "Ursula"]).is_ok());
+        assert!(parse(&["not an email", "Ursula", "", ""]).is_err());
+        assert!(parse(&["ursula@example.com", "", "", ""]).is_err());
+        assert!(parse(&["ursula@example.com", "Ursula", "a b", ""]).is_err());
+        assert_eq!(
+            parse(&["ursula@example.com", "Ursula", "", "Offers"]).unwrap_err(),
+            "There is no list named Offers."
+        );
+    }
+
+    #[test]
+    fn reports_are_csv_files() {
+        let csv = rejections_csv(&[Rejection {
+            line_number: 3,
+            email: "ursula@example.com".into(),
+            name: "Le Guin, Ursula".into(),
+            kind: RejectionKind::Duplicate,
+            reason: "Duplicate of line 2.".into(),
+        }])
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "line,email,name,kind,reason\n\
+            3,ursula@example.com,\"Le Guin, Ursula\",duplicate,Duplicate of line 2.\n"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use crate::config::SubscriptionSettings;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Delete subscribers who never confirmed, every `config.cleanup_interval()`,
+/// until `shutdown` is cancelled.
+pub async fn run_subscription_cleanup(
+    pool: PgPool,
+    config: SubscriptionSettings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        let cutoff = Utc::now() - config.pending_retention();
+        let wait = match delete_stale_pending_subscribers(&pool, cutoff).await {
+            Ok(_) => config.cleanup_interval(),
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to delete stale pending subscribers."
+                );
+                Duration::from_secs(1)
+            }
+        };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Delete the subscribers still pending confirmation who signed up before
+/// `cutoff` and were not sent a confirmation link since, along with their
+/// tokens. Returns how many subscribers were deleted.
+#[tracing::instrument(skip(pool))]
+pub async fn delete_stale_pending_subscribers(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> Result<u64, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    let subscriber_ids = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE status = 'pending_confirmation'
+            AND subscribed_at < $1
+            AND NOT EXISTS (
+                SELECT 1 FROM subscription_tokens
+                WHERE subscriber_id = subscriptions.id AND created_at >= $1
+            )
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        cutoff
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect::<Vec<_>>();
+    if subscriber_ids.is_empty() {
+        return Ok(0);
+    }
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)",
+        &subscriber_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    let n_deleted = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = ANY($1)",
+        &subscriber_ids
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    tracing::info!(n_deleted, "Deleted stale pending subscribers.");
+    Ok(n_deleted)
+}
@@ -0,0 +1,41 @@
+use crate::domain::SubscriberTag;
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Add these tags to the subscriber, on top of the ones they already have.
+#[tracing::instrument(skip(transaction))]
+pub async fn add_subscriber_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<(), sqlx::Error> {
+    let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT $1, tag FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        &tags as &[&str]
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Give the subscriber exactly these tags.
+#[tracing::instrument(skip(transaction))]
+pub async fn replace_subscriber_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM subscriber_tags WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    add_subscriber_tags(transaction, subscriber_id, tags).await
+}
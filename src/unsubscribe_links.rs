@@ -0,0 +1,196 @@
+use crate::utils::escape_html;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Builds and checks the links subscribers follow to manage their preferences
+/// or leave the newsletter.
+///
+/// A link carries the subscriber id and an HMAC of it: there is nothing to
+/// store, and links keep working for as long as the secret does not change.
+/// Both pages accept the same token: whoever can leave can also stay on their
+/// own terms.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn link(&self, subscriber_id: Uuid) -> String {
+        self.signed_link("/subscriptions/unsubscribe", subscriber_id)
+    }
+
+    pub fn preferences_link(&self, subscriber_id: Uuid) -> String {
+        self.signed_link("/subscriptions/preferences", subscriber_id)
+    }
+
+    /// Whether `token` was issued by us for this subscriber.
+    pub fn is_valid(&self, subscriber_id: Uuid, token: &str) -> bool {
+        let Ok(tag) = hex::decode(token) else {
+            return false;
+        };
+        self.mac(subscriber_id).verify_slice(&tag).is_ok()
+    }
+
+    fn signed_link(&self, path: &str, subscriber_id: Uuid) -> String {
+        format!(
+            "{}{path}?subscriber_id={}&token={}",
+            self.base_url,
+            subscriber_id,
+            self.token(subscriber_id)
+        )
+    }
+
+    fn token(&self, subscriber_id: Uuid) -> String {
+        hex::encode(self.mac(subscriber_id).finalize().into_bytes())
+    }
+
+    fn mac(&self, subscriber_id: Uuid) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes()).unwrap();
+        // The secret also signs cookies: keep unsubscribe tokens apart
+        mac.update(b"unsubscribe:");
+        mac.update(subscriber_id.as_bytes());
+        mac
+    }
+}
+
+/// Append the preferences and unsubscribe links to the HTML version of an
+/// email, inside its `<body>` if it has one.
+pub fn html_with_unsubscribe_footer(html: &str, link: &str, preferences_link: &str) -> String {
+    let footer = format!(
+        r#"<p><a href="{}">Manage your preferences</a> or <a href="{}">unsubscribe</a> from this newsletter.</p>"#,
+        escape_html(preferences_link),
+        escape_html(link)
+    );
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(i) => format!("{}{footer}\n{}", &html[..i], &html[i..]),
+        None => format!("{html}\n{footer}"),
+    }
+}
+
+/// Append the preferences and unsubscribe links to the plain text version of
+/// an email.
+pub fn text_with_unsubscribe_footer(text: &str, link: &str, preferences_link: &str) -> String {
+    format!(
+        "{text}\n\n--\nManage your preferences: {preferences_link}\n\
+        Unsubscribe from this newsletter: {link}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{html_with_unsubscribe_footer, text_with_unsubscribe_footer, UnsubscribeLinks};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn links(secret: &str) -> UnsubscribeLinks {
+        UnsubscribeLinks::new("http://localhost".into(), Secret::new(secret.into()))
+    }
+
+    fn token_of(link: &str) -> &str {
+        link.rsplit_once("token=").unwrap().1
+    }
+
+    #[test]
+    fn links_we_issued_are_valid() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+
+        let link = links.link(subscriber_id);
+
+        assert!(link.starts_with(&format!(
+            "http://localhost/subscriptions/unsubscribe?subscriber_id={subscriber_id}&token="
+        )));
+        assert!(links.is_valid(subscriber_id, token_of(&link)));
+    }
+
+    #[test]
+    fn preferences_links_carry_the_same_token() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+
+        let link = links.preferences_link(subscriber_id);
+
+        assert!(link.starts_with(&format!(
+            "http://localhost/subscriptions/preferences?subscriber_id={subscriber_id}&token="
+        )));
+        assert!(links.is_valid(subscriber_id, token_of(&link)));
+    }
+
+    #[test]
+    fn a_token_is_only_valid_for_its_subscriber() {
+        let links = links("secret");
+        let link = links.link(Uuid::new_v4());
+
+        assert!(!links.is_valid(Uuid::new_v4(), token_of(&link)));
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let link = links("another secret").link(subscriber_id);
+
+        assert!(!links("secret").is_valid(subscriber_id, token_of(&link)));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let links = links("secret");
+
+        assert!(!links.is_valid(Uuid::new_v4(), "not-hex"));
+        assert!(!links.is_valid(Uuid::new_v4(), ""));
+    }
+
+    #[test]
+    fn the_html_footer_goes_inside_the_body() {
+        let html = html_with_unsubscribe_footer(
+            "<html><body><p>Hi!</p></BODY></html>",
+            "http://localhost/unsubscribe?a=1&b=2",
+            "http://localhost/preferences?a=1&b=2",
+        );
+
+        assert_eq!(
+            html,
+            "<html><body><p>Hi!</p>\
+            <p><a href=\"http://localhost/preferences?a=1&amp;b=2\">Manage your preferences</a> \
+            or <a href=\"http://localhost/unsubscribe?a=1&amp;b=2\">unsubscribe</a> \
+            from this newsletter.</p>\n</BODY></html>"
+        );
+    }
+
+    #[test]
+    fn the_html_footer_is_appended_to_fragments() {
+        let html = html_with_unsubscribe_footer(
+            "<p>Hi!</p>",
+            "http://localhost/unsubscribe",
+            "http://localhost/preferences",
+        );
+
+        assert!(html.starts_with("<p>Hi!</p>\n<p><a href=\"http://localhost/preferences\">"));
+    }
+
+    #[test]
+    fn the_text_footer_is_appended() {
+        let text = text_with_unsubscribe_footer(
+            "Hi!",
+            "http://localhost/unsubscribe",
+            "http://localhost/preferences",
+        );
+
+        assert_eq!(
+            text,
+            "Hi!\n\n--\nManage your preferences: http://localhost/preferences\n\
+            Unsubscribe from this newsletter: http://localhost/unsubscribe"
+        );
+    }
+}
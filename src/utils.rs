@@ -1,5 +1,6 @@
-use actix_web::http::header::LOCATION;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, LOCATION};
 use actix_web::HttpResponse;
+use serde::de::DeserializeOwned;
 pub fn e500<T>(e: T) -> actix_web::Error
 where
     T: std::fmt::Debug + std::fmt::Display + 'static,
@@ -19,3 +20,89 @@ where
 {
     actix_web::error::ErrorBadRequest(e)
 }
+
+pub fn e404<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorNotFound(e)
+}
+
+/// A `Content-Disposition` header making browsers download the body as `file_name`.
+pub fn attachment(file_name: impl Into<String>) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(file_name.into())],
+    }
+}
+
+/// Escape `s` so it can be embedded in HTML, as text or inside a quoted attribute.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Split the values of `key` out of a urlencoded form and deserialize the rest
+/// into `T`. Checkboxes sharing a name repeat their key, which
+/// `serde_urlencoded` refuses to collect into a struct field.
+pub fn split_repeated_field<T: DeserializeOwned>(
+    pairs: Vec<(String, String)>,
+    key: &str,
+) -> Result<(T, Vec<String>), serde_urlencoded::de::Error> {
+    let (values, rest): (Vec<_>, Vec<_>) = pairs.into_iter().partition(|(k, _)| k == key);
+    let rest = serde_urlencoded::to_string(rest).map_err(serde::de::Error::custom)?;
+    let form = serde_urlencoded::from_str(&rest)?;
+    Ok((form, values.into_iter().map(|(_, v)| v).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_repeated_field;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Form {
+        name: String,
+        note: Option<String>,
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_values_are_collected_in_order() {
+        let (form, values) = split_repeated_field::<Form>(
+            pairs(&[("id", "b"), ("name", "Ursula"), ("id", "a")]),
+            "id",
+        )
+        .unwrap();
+
+        assert_eq!(
+            form,
+            Form {
+                name: "Ursula".into(),
+                note: None
+            }
+        );
+        assert_eq!(values, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn the_rest_of_the_form_is_still_validated() {
+        assert!(split_repeated_field::<Form>(pairs(&[("id", "a")]), "id").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::click_links::ClickLinks;
+
+/// Publish an issue with a link to the confirmed subscriber and return the
+/// message sent to Postmark.
+async fn publish_and_deliver(app: &TestApp, click_tracking: Option<&str>) -> serde_json::Value {
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let mut body = serde_json::json!({
+        "title": "Release notes",
+        "text_content": "Read https://example.com/post?a=1&b=2 now.",
+        "html_content": r#"<p>Read <a href="https://example.com/post?a=1&amp;b=2">the post</a> or <a href="mailto:editor@example.com">write to us</a>.</p>"#,
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    if let Some(click_tracking) = click_tracking {
+        body["click_tracking"] = click_tracking.into();
+    }
+    let response = app.post_newsletters(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    body[0].clone()
+}
+
+/// The click tracking redirects among the links of an HTML body.
+fn tracked_links(html: &str) -> Vec<String> {
+    html.split(r#"href=""#)
+        .skip(1)
+        .filter_map(|s| s.split('"').next())
+        .filter(|link| link.contains("/t/c/"))
+        .map(String::from)
+        .collect()
+}
+
+async fn get_ids(app: &TestApp) -> (Uuid, Uuid) {
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    (issue_id, subscriber_id)
+}
+
+#[tokio::test]
+async fn clicks_on_tracked_links_are_recorded_and_redirected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let message = publish_and_deliver(&app, Some("html")).await;
+
+    let html_body = message["HtmlBody"].as_str().unwrap();
+    // The mailto link and the footer's own links are left alone
+    let links = tracked_links(html_body);
+    assert_eq!(links.len(), 1);
+    assert!(html_body.contains(r#"href="mailto:editor@example.com""#));
+    let (issue_id, subscriber_id) = get_ids(&app).await;
+    let unsubscribe_link = app.unsubscribe_links.link(subscriber_id);
+    assert!(html_body.contains(&unsubscribe_link.replace('&', "&amp;")));
+    // Only the HTML version is tracked
+    assert!(message["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Read https://example.com/post?a=1&b=2 now."));
+
+    let response = app.get_tracked_link(&links[0]).await;
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        "https://example.com/post?a=1&b=2"
+    );
+    let click = sqlx::query!("SELECT newsletter_issue_id, subscriber_id, url FROM link_clicks")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(click.newsletter_issue_id, issue_id);
+    assert_eq!(click.subscriber_id, subscriber_id);
+    assert_eq!(click.url, "https://example.com/post?a=1&b=2");
+    // Clicks are part of what we store about the subscriber
+    let response = app
+        .get_subscriber_data(message["To"].as_str().unwrap())
+        .await;
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        data["link_clicks"][0]["url"],
+        "https://example.com/post?a=1&b=2"
+    );
+
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains(
+        "<td>https://example.com/post?a=1&amp;b=2</td>\n            <td>1</td>\n            <td>1</td>"
+    ));
+}
+
+#[tokio::test]
+async fn links_of_the_plain_text_version_can_be_tracked_too() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let message = publish_and_deliver(&app, Some("html_and_text")).await;
+
+    assert_eq!(
+        tracked_links(message["HtmlBody"].as_str().unwrap()).len(),
+        1
+    );
+    let text_body = message["TextBody"].as_str().unwrap();
+    let link = linkify::LinkFinder::new()
+        .links(text_body)
+        .find(|l| l.as_str().contains("/t/c/"))
+        .unwrap()
+        .as_str()
+        .to_owned();
+    assert!(!text_body.contains("https://example.com/post"));
+    // The footer's links are not
+    let (_, subscriber_id) = get_ids(&app).await;
+    assert!(text_body.contains(&app.unsubscribe_links.link(subscriber_id)));
+
+    let response = app.get_tracked_link(&link).await;
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        "https://example.com/post?a=1&b=2"
+    );
+}
+
+#[tokio::test]
+async fn clicks_are_not_tracked_unless_asked_for() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    for click_tracking in [None, Some("off")] {
+        let message = publish_and_deliver(&app, click_tracking).await;
+
+        let html_body = message["HtmlBody"].as_str().unwrap();
+        assert!(html_body.contains(r#"<a href="https://example.com/post?a=1&amp;b=2""#));
+        assert!(tracked_links(html_body).is_empty());
+        app.email_server.reset().await;
+    }
+}
+
+#[tokio::test]
+async fn only_links_we_signed_are_redirected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    publish_and_deliver(&app, Some("html")).await;
+    let (issue_id, subscriber_id) = get_ids(&app).await;
+    let link = app
+        .click_links
+        .link(issue_id, subscriber_id, "https://example.com");
+    let (_, tag) = link.rsplit_once('.').unwrap();
+    let other_link = app
+        .click_links
+        .link(issue_id, subscriber_id, "https://evil.example.com");
+    let (payload, _) = other_link.rsplit_once('.').unwrap();
+    let foreign_link = ClickLinks::new(
+        "http://127.0.0.1".into(),
+        Secret::new("another secret".into()),
+    )
+    .link(issue_id, subscriber_id, "https://evil.example.com");
+
+    for link in [
+        format!("{payload}.{tag}"),
+        foreign_link,
+        "http://127.0.0.1/t/c/not-a-token".into(),
+    ] {
+        let response = app.get_tracked_link(&link).await;
+
+        assert_eq!(response.status().as_u16(), 404, "{link}");
+        assert!(response.headers().get("Location").is_none());
+    }
+    let n_clicks = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM link_clicks"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_clicks, 0);
+}
+
+#[tokio::test]
+async fn erased_subscribers_still_get_to_their_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let message = publish_and_deliver(&app, Some("html")).await;
+    let link = tracked_links(message["HtmlBody"].as_str().unwrap()).remove(0);
+    let email = message["To"].as_str().unwrap();
+    let response = app.post_erase_subscriber(email).await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    let response = app.get_tracked_link(&link).await;
+
+    assert_eq!(response.status().as_u16(), 302);
+    let n_clicks = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM link_clicks"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_clicks, 0);
+}
+
+#[tokio::test]
+async fn drafts_keep_their_click_tracking_setting() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_create_draft(&serde_json::json!({
+            "title": "Release notes",
+            "text_content": "Read https://example.com/post now.",
+            "html_content": r#"<p><a href="https://example.com/post">Read</a></p>"#,
+            "click_tracking": "html_and_text",
+        }))
+        .await;
+
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}/edit"));
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains(r#"<option value="html_and_text" selected>"#));
+}
@@ -0,0 +1,188 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publish an issue to a single confirmed subscriber and exhaust its retries.
+async fn create_delivery_failure(app: &TestApp) -> uuid::Uuid {
+    create_confirmed_subscriber(app).await;
+    app.test_user.login(app).await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET n_retries = $1",
+        app.worker_config.max_retries
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a failed delivery.")
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_failed_deliveries() {
+    let app = spawn_app().await;
+
+    let response = app.get_delivery_failures().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_requeue_failed_deliveries() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_requeue_failures(&serde_json::json!({
+            "newsletter_issue_id": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_listed_per_issue() {
+    let app = spawn_app().await;
+    create_delivery_failure(&app).await;
+
+    let html_page = app.get_delivery_failures_html().await;
+
+    assert!(html_page.contains("Newsletter title"));
+    assert!(html_page.contains("500"));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_escaped() {
+    let app = spawn_app().await;
+    create_delivery_failure(&app).await;
+    sqlx::query!("UPDATE issue_delivery_failures SET last_error = '<script>alert(1)</script>'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let html_page = app.get_delivery_failures_html().await;
+
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+}
+
+#[tokio::test]
+async fn requeued_failures_are_delivered_again() {
+    let app = spawn_app().await;
+    let issue_id = create_delivery_failure(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_requeue_failures(&serde_json::json!({
+            "newsletter_issue_id": issue_id.to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/failures");
+
+    let html_page = app.get_delivery_failures_html().await;
+    assert!(html_page.contains("1 deliveries have been requeued."));
+    assert!(html_page.contains("There are no failed deliveries."));
+
+    let task = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The failure should be back in the queue.");
+    assert_eq!(task.n_retries, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_single_recipient_can_be_requeued() {
+    let app = spawn_app().await;
+    let issue_id = create_delivery_failure(&app).await;
+    let email = sqlx::query!("SELECT subscriber_email FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscriber_email;
+
+    let response = app
+        .post_requeue_failures(&serde_json::json!({
+            "newsletter_issue_id": issue_id.to_string(),
+            "subscriber_email": "someone-else@example.com"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/failures");
+    let html_page = app.get_delivery_failures_html().await;
+    assert!(html_page.contains("0 deliveries have been requeued."));
+
+    app.post_requeue_failures(&serde_json::json!({
+        "newsletter_issue_id": issue_id.to_string(),
+        "subscriber_email": &email
+    }))
+    .await;
+    let html_page = app.get_delivery_failures_html().await;
+    assert!(html_page.contains("1 deliveries have been requeued."));
+}
+
+#[tokio::test]
+async fn discarded_failures_are_removed() {
+    let app = spawn_app().await;
+    let issue_id = create_delivery_failure(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_discard_failures(&serde_json::json!({
+            "newsletter_issue_id": issue_id.to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/failures");
+
+    let html_page = app.get_delivery_failures_html().await;
+    assert!(html_page.contains("1 deliveries have been discarded."));
+    assert!(html_page.contains("There are no failed deliveries."));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requeueing_failures_notifies_the_delivery_workers() {
+    let app = spawn_app().await;
+    let issue_id = create_delivery_failure(&app).await;
+    let mut listener = app.listen_to_delivery_queue().await;
+
+    app.post_requeue_failures(&serde_json::json!({
+        "newsletter_issue_id": issue_id.to_string()
+    }))
+    .await;
+
+    tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv())
+        .await
+        .expect("The delivery workers were not notified.")
+        .unwrap();
+}
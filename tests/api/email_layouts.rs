@@ -0,0 +1,255 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const LAYOUT: &str = r#"<html>
+<head><style>.brand { color: #ff6600; } a:hover { color: red; }</style></head>
+<body>
+<h1 class="brand">Acme news: {{ title }}</h1>
+{{ content }}
+{% if unsubscribe_url %}<a href="{{ unsubscribe_url }}">Unsubscribe</a>{% endif %}
+<p>{{ postal_address }}</p>
+</body>
+</html>"#;
+
+async fn create_layout(app: &TestApp, name: &str, is_default: bool) -> Uuid {
+    let mut form = serde_json::json!({
+        "name": name,
+        "postal_address": "1 Main Street, Springfield",
+        "template": LAYOUT.replace("Acme news", name),
+    });
+    if is_default {
+        form["is_default"] = "on".into();
+    }
+    let response = app.post_layouts(&form).await;
+    assert_is_redirect_to(&response, "/admin/layouts");
+    sqlx::query!("SELECT layout_id FROM email_layouts WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected the layout to be saved.")
+        .layout_id
+}
+
+/// Publish an issue to the confirmed subscribers and return the HTML bodies
+/// sent to Postmark.
+async fn publish_and_deliver(app: &TestApp, layout: &str) -> Vec<String> {
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Release notes",
+        "text_content": "Version 2.0 is out.",
+        "html_content": "<p>Version 2.0 is out.</p>",
+        "layout": layout,
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    body.iter()
+        .map(|message| message["HtmlBody"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn issues_go_out_in_their_layout_with_inlined_css() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    create_layout(&app, "Acme", true).await;
+    let layout_id = create_layout(&app, "Globex", false).await;
+
+    let html_bodies = publish_and_deliver(&app, &layout_id.to_string()).await;
+
+    let html_body = &html_bodies[0];
+    assert!(html_body.contains(
+        r#"<h1 class="brand" style="color: #ff6600">Globex: Release notes</h1>
+<p>Version 2.0 is out.</p>"#
+    ));
+    assert!(html_body.contains("<style>a:hover { color: red; }</style>"));
+    assert!(html_body.contains("<p>1 Main Street, Springfield</p>"));
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let link = app.unsubscribe_links.link(subscriber_id);
+    assert!(html_body.contains(&format!(
+        r#"<a href="{}">Unsubscribe</a>"#,
+        link.replace('&', "&amp;")
+    )));
+}
+
+#[tokio::test]
+async fn issues_go_out_in_the_default_layout_unless_they_pick_another() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    create_layout(&app, "Acme", true).await;
+
+    let html_bodies = publish_and_deliver(&app, "").await;
+
+    assert!(html_bodies[0].contains(">Acme: Release notes</h1>"));
+}
+
+#[tokio::test]
+async fn issues_without_a_layout_go_out_as_written() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    create_layout(&app, "Acme", true).await;
+
+    let html_bodies = publish_and_deliver(&app, "none").await;
+
+    let html_body = &html_bodies[0];
+    assert!(html_body.starts_with("<p>Version 2.0 is out.</p>\n"));
+    assert!(html_body.contains(">unsubscribe</a> from this newsletter."));
+}
+
+#[tokio::test]
+async fn the_confirmation_email_goes_out_in_the_default_layout() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_layout(&app, "Acme", true).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(r#"<h1 class="brand" style="color: #ff6600">Acme: Welcome!</h1>"#));
+    assert!(html_body.contains("to confirm your subscription."));
+    // Transactional messages have nothing to unsubscribe from
+    assert!(!html_body.contains("Unsubscribe"));
+}
+
+#[tokio::test]
+async fn layouts_that_cannot_be_used_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = [
+        (
+            "<div>{{ content }</div>",
+            "The layout is not a valid template",
+        ),
+        (
+            "<div>{{ content }}</div>",
+            "The layout must link to {{ unsubscribe_url }} in newsletter issues.",
+        ),
+        (
+            r#"{{ content }}<a href="{{ unsubscribe_url }}">Unsubscribe</a>"#,
+            "Failed to render the layout",
+        ),
+    ];
+
+    for (template, error) in test_cases {
+        let response = app
+            .post_layouts(&serde_json::json!({
+                "name": "Broken",
+                "template": template,
+            }))
+            .await;
+
+        assert_is_redirect_to(&response, "/admin/layouts");
+        let html_page = app.get_layouts_html().await;
+        assert!(html_page.contains(error), "{template}: {html_page}");
+    }
+    let n_layouts = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_layouts"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_layouts, 0);
+}
+
+#[tokio::test]
+async fn there_is_only_one_default_layout() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_layout(&app, "Acme", true).await;
+    let layout_id = create_layout(&app, "Globex", true).await;
+
+    let defaults = sqlx::query!("SELECT layout_id FROM email_layouts WHERE is_default")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(defaults.len(), 1);
+    assert_eq!(defaults[0].layout_id, layout_id);
+    let html_page = app.get_layouts_html().await;
+    assert!(html_page.contains("Globex</a> (default)"));
+    assert!(!html_page.contains("Built-in (default)"));
+}
+
+#[tokio::test]
+async fn layouts_can_be_edited_and_previewed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, "Acme", false).await.to_string();
+
+    let response = app
+        .post_update_layout(
+            &layout_id,
+            &serde_json::json!({
+                "name": "Acme Weekly",
+                "postal_address": "2 Side Street, Shelbyville",
+                "template": LAYOUT,
+            }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/admin/layouts/{layout_id}/edit"));
+    let html_page = app.get_edit_layout_html(&layout_id).await;
+    assert!(html_page.contains("The layout Acme Weekly has been saved."));
+    assert!(html_page.contains("2 Side Street, Shelbyville</textarea>"));
+    // The sample issue is embedded, escaped, in a sandboxed frame
+    let html_page = app.get_layout_preview_html(&layout_id).await;
+    assert!(html_page.contains("Acme news: Release notes&lt;/h1&gt;"));
+    assert!(html_page.contains("&lt;p&gt;2 Side Street, Shelbyville&lt;/p&gt;"));
+}
+
+#[tokio::test]
+async fn issues_fall_back_to_the_default_layout_when_theirs_is_deleted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    create_layout(&app, "Acme", true).await;
+    let layout_id = create_layout(&app, "Globex", false).await;
+    sqlx::query!(
+        "INSERT INTO newsletter_issues (newsletter_issue_id, title, text_content, html_content, layout_id, status) \
+        VALUES ($1, 'Release notes', 'Hi', '<p>Hi</p>', $2, 'draft')",
+        Uuid::new_v4(),
+        layout_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.post_delete_layout(&layout_id.to_string()).await;
+
+    assert_is_redirect_to(&response, "/admin/layouts");
+    let html_page = app.get_layouts_html().await;
+    assert!(html_page.contains("The layout Globex has been deleted."));
+    let issue = sqlx::query!("SELECT use_layout, layout_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issue.use_layout);
+    assert_eq!(issue.layout_id, None);
+}
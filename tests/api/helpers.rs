@@ -2,18 +2,28 @@ use argon2::{
     password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
     Algorithm, Argon2, Params, Version,
 };
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
 use serde::Serialize;
+use sqlx::postgres::PgListener;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use std::io;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::click_links::ClickLinks;
 use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
-use zero2prod::startup::{get_conn_pool, Application};
+use zero2prod::rate_limiter::RateLimiter;
+use zero2prod::startup::{get_conn_pool, Application, HmacSecret};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::unsubscribe_links::UnsubscribeLinks;
 use zero2prod::{
-    config::{get_config, DatabaseSettings},
-    email_client::EmailClient,
+    config::{get_config, DatabaseSettings, WorkerSettings},
+    email_client::EmailSender,
 };
 static TRACING: Lazy<()> = Lazy::new(|| {
     if std::env::var("TEST_LOG").is_ok() {
@@ -32,7 +42,13 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
-    pub email_client: EmailClient,
+    pub email_client: Arc<dyn EmailSender>,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub click_links: ClickLinks,
+    pub hmac_secret: HmacSecret,
+    pub worker_config: WorkerSettings,
+    /// Stops the API when cancelled.
+    pub shutdown: CancellationToken,
 }
 
 pub struct ConfirmationLinks {
@@ -42,16 +58,48 @@ pub struct ConfirmationLinks {
 
 impl TestApp {
     pub async fn dispatch_all_pending_emails(&self) {
+        let rate_limiter = RateLimiter::new(&self.worker_config);
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .expect("Failed to execute task.")
+            if let ExecutionOutcome::EmptyQueue | ExecutionOutcome::QueuePaused = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.unsubscribe_links,
+                &self.click_links,
+                &rate_limiter,
+                &self.worker_config,
+            )
+            .await
+            .expect("Failed to execute task.")
             {
                 break;
             }
         }
     }
+    /// Send a single batch of emails, as a delivery worker would.
+    pub async fn dispatch_one_batch(&self) {
+        try_execute_task(
+            &self.db_pool,
+            self.email_client.as_ref(),
+            &self.unsubscribe_links,
+            &self.click_links,
+            &RateLimiter::new(&self.worker_config),
+            &self.worker_config,
+        )
+        .await
+        .expect("Failed to execute task.");
+    }
+    /// Subscribe to the notifications enqueuers send to the delivery workers.
+    pub async fn listen_to_delivery_queue(&self) -> PgListener {
+        let mut listener = PgListener::connect_with(&self.db_pool)
+            .await
+            .expect("Failed to connect the listener.");
+        listener
+            .listen(&self.worker_config.notify_channel)
+            .await
+            .expect("Failed to listen to the delivery queue channel.");
+        listener
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: Serialize,
@@ -130,6 +178,15 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_confirmation(&self, subscription_token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/confirm/resend", &self.address))
+            .form(&serde_json::json!({ "subscription_token": subscription_token }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: Serialize,
@@ -158,6 +215,465 @@ impl TestApp {
             .expect("Failed to get response text.")
     }
 
+    pub async fn get_newsletter_issue(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_issue_html(&self, newsletter_issue_id: &str) -> String {
+        self.get_newsletter_issue(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+
+    pub async fn post_create_draft<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_edit_draft(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/edit",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_edit_draft_html(&self, newsletter_issue_id: &str) -> String {
+        self.get_edit_draft(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+
+    pub async fn post_update_draft<Body>(
+        &self,
+        newsletter_issue_id: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/edit",
+                &self.address, newsletter_issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_delete_draft(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/delete",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_preview_html(&self, newsletter_issue_id: &str) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/preview",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+
+    pub async fn post_publish_draft<Body>(
+        &self,
+        newsletter_issue_id: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/publish",
+                &self.address, newsletter_issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_test_send<Body>(
+        &self,
+        newsletter_issue_id: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/test",
+                &self.address, newsletter_issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reschedule_newsletter_issue<Body>(
+        &self,
+        newsletter_issue_id: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/reschedule",
+                &self.address, newsletter_issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_cancel_newsletter_issue(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/cancel",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_pause_newsletter_issue(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/pause",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resume_newsletter_issue(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/resume",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    pub async fn post_lists<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_tags_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/tags", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    pub async fn post_tags<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/tags", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_segments_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/segments", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    pub async fn post_segments<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/segments", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_imports_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/imports", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    /// Upload `csv` the way the import form does, as `multipart/form-data`.
+    pub async fn post_import(&self, csv: &str, mode: &str) -> reqwest::Response {
+        let boundary = "zero2prod-import-boundary";
+        let body = format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"mode\"\r\n\r\n\
+            {mode}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\n\
+            {csv}\r\n\
+            --{boundary}--\r\n"
+        );
+        self.api_client
+            .post(format!("{}/admin/imports", &self.address))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_import_report(&self, import_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/imports/{}/report",
+                &self.address, import_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_subscribers_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    pub async fn get_subscribers_export<Query>(&self, query: &Query) -> reqwest::Response
+    where
+        Query: Serialize,
+    {
+        self.api_client
+            .get(format!("{}/admin/subscribers/export", &self.address))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_subscriber_data(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/data", &self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_erase_subscriber(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/erase", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_layouts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/layouts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    pub async fn post_layouts<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/layouts", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_edit_layout_html(&self, layout_id: &str) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/layouts/{}/edit",
+                &self.address, layout_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    pub async fn post_update_layout<Body>(&self, layout_id: &str, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/layouts/{}/edit",
+                &self.address, layout_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_delete_layout(&self, layout_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/layouts/{}/delete",
+                &self.address, layout_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_layout_preview_html(&self, layout_id: &str) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/layouts/{}/preview",
+                &self.address, layout_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+    /// Follow a click tracking redirect found in an email, on the test server.
+    pub async fn get_tracked_link(&self, link: &str) -> reqwest::Response {
+        let mut link = reqwest::Url::parse(link).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        self.api_client
+            .get(link)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_delivery_failures(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/failures", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_delivery_failures_html(&self) -> String {
+        self.get_delivery_failures()
+            .await
+            .text()
+            .await
+            .expect("Failed to get response text.")
+    }
+
+    pub async fn post_requeue_failures<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/failures/requeue", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_discard_failures<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/failures/discard", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -179,6 +695,22 @@ impl TestApp {
         let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
+
+    /// The link a subscriber follows to leave, pointing at the test server.
+    pub fn unsubscribe_link(&self, subscriber_id: Uuid) -> reqwest::Url {
+        let mut link = reqwest::Url::parse(&self.unsubscribe_links.link(subscriber_id)).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+
+    /// The link a subscriber follows to manage their preferences, pointing at
+    /// the test server.
+    pub fn preferences_link(&self, subscriber_id: Uuid) -> reqwest::Url {
+        let mut link =
+            reqwest::Url::parse(&self.unsubscribe_links.preferences_link(subscriber_id)).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
 }
 pub async fn spawn_app() -> TestApp {
     Lazy::force(&TRACING);
@@ -213,8 +745,12 @@ pub async fn spawn_app() -> TestApp {
         .unwrap();
 
     let email_client = config.email_client.client();
+    let unsubscribe_links = config.application.unsubscribe_links();
+    let click_links = config.application.click_links();
+    let hmac_secret = HmacSecret(config.application.hmac_secret.clone());
 
-    tokio::spawn(application.run_until_stopped());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(application.run_until_stopped(shutdown.clone()));
     TestApp {
         address,
         port,
@@ -223,6 +759,11 @@ pub async fn spawn_app() -> TestApp {
         test_user,
         api_client,
         email_client,
+        unsubscribe_links,
+        click_links,
+        hmac_secret,
+        worker_config: config.worker,
+        shutdown,
     }
 }
 
@@ -290,6 +831,68 @@ impl TestUser {
     }
 }
 
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": &name,
+        "email": &email
+    }))
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .expect("Failed to create subscriber.");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let links = create_unconfirmed_subscriber(app).await;
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Save a draft through the API and return its id.
+pub async fn create_newsletter_draft(app: &TestApp) -> Uuid {
+    let response = app
+        .post_create_draft(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .await;
+    let issue_id = sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues ORDER BY updated_at DESC LIMIT 1"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Expected a newsletter draft.")
+    .newsletter_issue_id;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}/edit"));
+    issue_id
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("location").unwrap(), location);
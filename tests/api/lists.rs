@@ -0,0 +1,311 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_newsletter_draft, spawn_app, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let response = app
+        .post_lists(&serde_json::json!({ "name": name, "description": "" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    sqlx::query!("SELECT list_id FROM lists WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected the list to be created.")
+        .list_id
+}
+
+/// Create a confirmed subscriber and make them a member of `list_ids`.
+async fn create_member(app: &TestApp, list_ids: &[Uuid]) -> String {
+    create_confirmed_subscriber(app).await;
+    let subscriber =
+        sqlx::query!("SELECT id, email FROM subscriptions ORDER BY subscribed_at DESC, id LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    for list_id in list_ids {
+        sqlx::query!(
+            "INSERT INTO list_subscriptions (list_id, subscriber_id) VALUES ($1, $2)",
+            list_id,
+            subscriber.id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    subscriber.email
+}
+
+fn newsletter_form(list_ids: &[Uuid]) -> Vec<(&'static str, String)> {
+    let mut form = vec![
+        ("title", "Newsletter title".to_string()),
+        ("text_content", "Newsletter body as plain text".to_string()),
+        ("html_content", "<p>Newsletter body as HTML</p>".to_string()),
+        ("idempotency_key", Uuid::new_v4().to_string()),
+    ];
+    form.extend(list_ids.iter().map(|id| ("list_id", id.to_string())));
+    form
+}
+
+async fn queued_recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<_> = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.subscriber_email)
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn admins_can_create_lists() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    create_list(&app, "Release notes").await;
+
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The list Release notes has been created."));
+    assert!(html_page.contains("Release notes (0 confirmed members)"));
+}
+
+#[tokio::test]
+async fn list_names_are_unique() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_list(&app, "Release notes").await;
+
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Release notes", "description": "" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("There already is a list named Release notes."));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Release notes", "description": "" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_join_the_lists_they_tick() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    create_list(&app, "Events").await;
+
+    let home_page = app
+        .api_client
+        .get(&app.address)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(home_page.contains(&format!(r#"value="{release_notes}"> Release notes"#)));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = format!("name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={release_notes}");
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let memberships = sqlx::query!("SELECT list_id FROM list_subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(memberships.len(), 1);
+    assert_eq!(memberships[0].list_id, release_notes);
+}
+
+#[tokio::test]
+async fn signing_up_again_only_changes_the_lists_once_confirmed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let events = create_list(&app, "Events").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = format!("name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={release_notes}");
+    app.post_subscriptions(body).await;
+    let body = format!("name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={events}");
+    app.post_subscriptions(body).await;
+
+    let memberships = || async {
+        sqlx::query!("SELECT list_id FROM list_subscriptions")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.list_id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(memberships().await, vec![release_notes]);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(memberships().await, vec![events]);
+}
+
+#[tokio::test]
+async fn subscribing_with_an_invalid_list_id_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list_id=all".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_publish_forms_offer_every_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let list_id = create_list(&app, "Release notes").await;
+    let newsletter_issue_id = create_newsletter_draft(&app).await;
+
+    let checkbox = format!(r#"name="list_id" value="{list_id}"> Release notes"#);
+    assert!(app.get_newsletters_html().await.contains(&checkbox));
+    assert!(app
+        .get_edit_draft_html(&newsletter_issue_id.to_string())
+        .await
+        .contains(&checkbox));
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_list_only_reach_its_confirmed_members() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let events = create_list(&app, "Events").await;
+    let member = create_member(&app, &[release_notes]).await;
+    create_member(&app, &[events]).await;
+    create_member(&app, &[]).await;
+
+    app.post_newsletters(&newsletter_form(&[release_notes]))
+        .await;
+
+    assert_eq!(queued_recipients(&app).await, vec![member]);
+}
+
+#[tokio::test]
+async fn members_of_several_targeted_lists_receive_the_issue_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let events = create_list(&app, "Events").await;
+    let mut expected = vec![
+        create_member(&app, &[release_notes, events]).await,
+        create_member(&app, &[events]).await,
+    ];
+    expected.sort();
+    create_member(&app, &[]).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&newsletter_form(&[release_notes, events]))
+        .await;
+
+    assert_eq!(queued_recipients(&app).await, expected);
+    app.dispatch_all_pending_emails().await;
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "sent");
+}
+
+#[tokio::test]
+async fn issues_sent_to_lists_that_no_longer_exist_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_member(&app, &[]).await;
+
+    let response = app
+        .post_newsletters(&newsletter_form(&[Uuid::new_v4()]))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert!(queued_recipients(&app).await.is_empty());
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("None of the lists the newsletter issue is sent to exist anymore."));
+}
+
+#[tokio::test]
+async fn issues_sent_to_no_list_reach_every_confirmed_subscriber() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let mut expected = vec![
+        create_member(&app, &[release_notes]).await,
+        create_member(&app, &[]).await,
+    ];
+    expected.sort();
+
+    app.post_newsletters(&newsletter_form(&[])).await;
+
+    assert_eq!(queued_recipients(&app).await, expected);
+}
+
+#[tokio::test]
+async fn published_drafts_and_scheduled_issues_keep_their_lists() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let member = create_member(&app, &[release_notes]).await;
+    create_member(&app, &[]).await;
+    let newsletter_issue_id = create_newsletter_draft(&app).await;
+
+    app.post_publish_draft(
+        &newsletter_issue_id.to_string(),
+        &[
+            ("idempotency_key", Uuid::new_v4().to_string()),
+            ("scheduled_for", "2099-01-01T00:00".to_string()),
+            ("list_id", release_notes.to_string()),
+        ],
+    )
+    .await;
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    zero2prod::issue_scheduler::publish_due_issues(
+        &app.db_pool,
+        chrono::Utc::now(),
+        &app.worker_config.notify_channel,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(queued_recipients(&app).await, vec![member]);
+}
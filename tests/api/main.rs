@@ -1,8 +1,27 @@
 mod admin_dashboard;
 mod change_password;
+mod click_tracking;
+mod delivery_failures;
+mod email_layouts;
 mod health_check;
 mod helpers;
+mod lists;
 mod login;
 mod newsletter;
+mod newsletter_delivery_controls;
+mod newsletter_drafts;
+mod newsletter_markdown;
+mod newsletter_progress;
+mod newsletter_templates;
+mod newsletter_test_sends;
+mod pending_subscriber_cleanup;
+mod scheduled_newsletters;
+mod segments;
+mod shutdown;
+mod subscriber_data;
+mod subscriber_imports;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod tags;
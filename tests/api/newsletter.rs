@@ -1,13 +1,12 @@
 use crate::helpers::assert_is_redirect_to;
 use crate::helpers::spawn_app;
-use crate::helpers::ConfirmationLinks;
-use crate::helpers::TestApp;
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use chrono::Utc;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use wiremock::matchers::{method, path};
-use wiremock::{Mock, ResponseTemplate};
+use wiremock::{Mock, Respond, ResponseTemplate};
+use zero2prod::issue_delivery_worker::run_delivery_workers;
 
 #[tokio::test]
 async fn concurrent_form_submission_is_handled_gracefully() {
@@ -15,7 +14,7 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
         // For idempotency, we expect only one email request to be fired
@@ -46,7 +45,7 @@ async fn newsletters_creation_is_idempotent() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         // For idempotency, we expect only one email request to be fired
@@ -146,7 +145,7 @@ async fn newsletter_are_not_delivered_to_unconfirmed_subscriber() {
 
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         // Assert no request is fired
@@ -175,7 +174,7 @@ async fn newsletters_are_delivered_to_confirmed_subscriber() {
 
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         // Assert one request is fired
@@ -200,38 +199,743 @@ async fn newsletters_are_delivered_to_confirmed_subscriber() {
     app.dispatch_all_pending_emails().await;
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(serde_json::json!({
-        "name": &name,
-        "email": &email
-    }))
+#[tokio::test]
+async fn failed_deliveries_are_rescheduled_with_backoff() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    // The failed task is pushed into the future, so the queue looks empty afterwards
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The failed task should still be in the queue.");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.execute_after > Utc::now());
+}
+
+#[tokio::test]
+async fn rescheduled_deliveries_are_sent_once_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Fast-forward to the next attempt
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let remaining = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_moved_to_the_failures_table_after_the_maximum_number_of_retries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    // Pretend every retry but the last one has already been used up
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET n_retries = $1",
+        app.worker_config.max_retries
+    )
+    .execute(&app.db_pool)
+    .await
     .unwrap();
+    app.dispatch_all_pending_emails().await;
 
-    let _mock_guard = Mock::given(path("/email"))
+    let remaining = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+    let failure =
+        sqlx::query!("SELECT last_error, http_status, n_attempts FROM issue_delivery_failures")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("The failed delivery should be kept.");
+    assert_eq!(failure.http_status, Some(500));
+    assert_eq!(failure.n_attempts, app.worker_config.max_retries + 1);
+    assert!(!failure.last_error.is_empty());
+}
+
+#[tokio::test]
+async fn deliveries_to_invalid_stored_emails_are_moved_to_the_failures_table() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    sqlx::query!("UPDATE subscriptions SET email = 'not-an-email'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let failure = sqlx::query!(
+        "SELECT subscriber_email, http_status, n_attempts FROM issue_delivery_failures"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The failed delivery should be kept.");
+    assert_eq!(failure.subscriber_email, "not-an-email");
+    assert_eq!(failure.http_status, None);
+    assert_eq!(failure.n_attempts, 1);
+}
+
+fn postmark_error(status: u16, error_code: i64) -> ResponseTemplate {
+    ResponseTemplate::new(status).set_body_json(serde_json::json!({
+        "ErrorCode": error_code,
+        "Message": "Something went wrong"
+    }))
+}
+
+/// A batch whose single message failed with `error_code`.
+fn postmark_batch_error(error_code: i64) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+        "ErrorCode": error_code,
+        "Message": "Something went wrong"
+    }]))
+}
+
+/// Answers a Postmark batch with one result per message, rejecting `rejected`
+/// as an inactive recipient.
+struct BatchResponder {
+    rejected: String,
+}
+
+impl Respond for BatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                if message["To"] == self.rejected.as_str() {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive."
+                    })
+                } else {
+                    serde_json::json!({
+                        "ErrorCode": 0,
+                        "Message": "OK",
+                        "MessageID": uuid::Uuid::new_v4().to_string()
+                    })
+                }
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
+#[tokio::test]
+async fn an_auth_failure_pauses_the_delivery_queue() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(postmark_error(401, 10))
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body)
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let tasks = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks.iter().all(|t| t.n_retries == 0));
+}
+
+#[tokio::test]
+async fn an_auth_failure_pauses_every_delivery_loop() {
+    let mut app = spawn_app().await;
+    for _ in 0..10 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.concurrency = 3;
+    app.worker_config.batch_size = 1;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(postmark_error(401, 10))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    // Slow enough for the auth failure to come back before anything else
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    let workers = tokio::spawn(run_delivery_workers(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.unsubscribe_links.clone(),
+        app.click_links.clone(),
+        app.worker_config.clone(),
+        CancellationToken::new(),
+    ));
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    workers.abort();
+
+    // The other loops only finish the batch they were already sending
+    let sent =
+        sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_deliveries WHERE status = 'sent'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(sent.count <= 2);
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
         .await
-        .error_for_status()
-        .expect("Failed to create subscriber.");
+        .unwrap();
+    assert!(queued.count >= 8);
+}
+
+#[tokio::test]
+async fn rejected_recipients_are_suppressed_without_retrying() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(postmark_batch_error(406))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
 
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
 
-    app.get_confirmation_links(email_request)
+    let failure = sqlx::query!("SELECT http_status, n_attempts FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The rejected delivery should be dead-lettered.");
+    assert_eq!(failure.http_status, None);
+    assert_eq!(failure.n_attempts, 1);
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "suppressed");
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let links = create_unconfirmed_subscriber(app).await;
-    reqwest::get(links.html)
+#[tokio::test]
+async fn a_rejected_batch_is_retried_without_suppressing_anyone() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(postmark_error(422, 406))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let tasks = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks.iter().all(|t| t.n_retries == 1));
+    let suppressed = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\" FROM subscriptions WHERE status = 'suppressed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(suppressed.count, 0);
+}
+
+#[tokio::test]
+async fn malformed_requests_are_not_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(postmark_batch_error(300))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let failure = sqlx::query!("SELECT n_attempts FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The malformed delivery should be dead-lettered.");
+    assert_eq!(failure.n_attempts, 1);
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "confirmed");
+}
+
+#[tokio::test]
+async fn rate_limited_deliveries_honour_retry_after_without_using_a_retry() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The throttled task should still be in the queue.");
+    assert_eq!(task.n_retries, 0);
+    assert!(task.execute_after > Utc::now() + chrono::Duration::minutes(59));
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_in_batches() {
+    let mut app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.batch_size = 2;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let batch_sizes: Vec<usize> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .map(|r| {
+            serde_json::from_slice::<Vec<serde_json::Value>>(&r.body)
+                .unwrap()
+                .len()
+        })
+        .collect();
+    assert_eq!(batch_sizes, vec![2, 1]);
+}
+
+#[tokio::test]
+async fn partially_failed_batches_record_the_outcome_of_each_recipient() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    let rejected = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
         .await
         .unwrap()
-        .error_for_status()
+        .email;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder {
+            rejected: rejected.clone(),
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let remaining = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+    let deliveries =
+        sqlx::query!("SELECT subscriber_email, status, provider_message_id FROM issue_deliveries")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(deliveries.len(), 3);
+    for delivery in deliveries {
+        if delivery.subscriber_email == rejected {
+            assert_eq!(delivery.status, "failed");
+            assert_eq!(delivery.provider_message_id, None);
+        } else {
+            assert_eq!(delivery.status, "sent");
+            assert!(delivery.provider_message_id.is_some());
+        }
+    }
+    let failure = sqlx::query!("SELECT subscriber_email FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The rejected delivery should be dead-lettered.");
+    assert_eq!(failure.subscriber_email, rejected);
+    let suppressed = sqlx::query!("SELECT email FROM subscriptions WHERE status = 'suppressed'")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressed.len(), 1);
+    assert_eq!(suppressed[0].email, rejected);
+}
+
+#[tokio::test]
+async fn concurrent_workers_never_deliver_the_same_task_twice() {
+    let mut app = spawn_app().await;
+    for _ in 0..4 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.batch_size = 1;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    tokio::join!(
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails()
+    );
+
+    let recipients = sqlx::query!(
+        "SELECT COUNT(DISTINCT subscriber_email) AS \"count!\" FROM issue_deliveries WHERE status = 'sent'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(recipients.count, 4);
+}
+
+#[tokio::test]
+async fn batches_never_exceed_the_global_send_rate() {
+    let mut app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.messages_per_second = 2;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    let started_at = std::time::Instant::now();
+    app.dispatch_all_pending_emails().await;
+
+    // The third message has to wait for the bucket to refill
+    assert!(started_at.elapsed() >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn deliveries_over_the_per_domain_rate_are_deferred_without_using_a_retry() {
+    let mut app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.per_domain_messages_per_second = Some(1);
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // One message per second: the first straight away, the others once deferred
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    sqlx::query!("UPDATE subscriptions SET email = split_part(email, '@', 1) || '@gmail.com'")
+        .execute(&app.db_pool)
+        .await
         .unwrap();
+    app.post_newsletters(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let tasks = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks
+        .iter()
+        .all(|t| t.n_retries == 0 && t.execute_after > Utc::now()));
+
+    // Deferring them again and again must not starve the domain
+    let workers = tokio::spawn(run_delivery_workers(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.unsubscribe_links.clone(),
+        app.click_links.clone(),
+        app.worker_config.clone(),
+        CancellationToken::new(),
+    ));
+    let delivered = async {
+        loop {
+            let sent = sqlx::query!(
+                "SELECT COUNT(*) AS \"count!\" FROM issue_deliveries WHERE status = 'sent'"
+            )
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+            if sent.count == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), delivered)
+        .await
+        .expect("The deferred deliveries were never sent.");
+    workers.abort();
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_notifies_the_delivery_workers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let mut listener = app.listen_to_delivery_queue().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+
+    let notification = tokio::time::timeout(Duration::from_secs(5), listener.recv())
+        .await
+        .expect("The delivery workers were not notified.")
+        .unwrap();
+    assert_eq!(notification.channel(), app.worker_config.notify_channel);
+}
+
+#[tokio::test]
+async fn idle_workers_are_woken_up_as_soon_as_an_issue_is_published() {
+    let mut app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    // Make sure the workers would not find the task by polling
+    app.worker_config.poll_interval_seconds = 3600;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let workers = tokio::spawn(run_delivery_workers(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.unsubscribe_links.clone(),
+        app.click_links.clone(),
+        app.worker_config.clone(),
+        CancellationToken::new(),
+    ));
+    // Give the workers time to go idle
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+
+    let delivered = async {
+        loop {
+            let sent = sqlx::query!(
+                "SELECT COUNT(*) AS \"count!\" FROM issue_deliveries WHERE status = 'sent'"
+            )
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+            if sent.count == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), delivered)
+        .await
+        .expect("The idle workers were not woken up.");
+    workers.abort();
 }
@@ -0,0 +1,285 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Publish an issue right away and return its id.
+async fn publish_newsletter(app: &TestApp) -> Uuid {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a newsletter issue.")
+        .newsletter_issue_id
+}
+
+async fn issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_pause_or_resume_an_issue() {
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4().to_string();
+
+    let response = app.post_pause_newsletter_issue(&issue_id).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_resume_newsletter_issue(&issue_id).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn paused_issues_are_not_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_newsletter(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_pause_newsletter_issue(&issue_id.to_string()).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "paused");
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Delivery of the newsletter issue has been paused."));
+    assert!(html_page.contains("Resume"));
+}
+
+#[tokio::test]
+async fn resumed_issues_are_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_newsletter(&app).await;
+    app.post_pause_newsletter_issue(&issue_id.to_string()).await;
+    let mut listener = app.listen_to_delivery_queue().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_resume_newsletter_issue(&issue_id.to_string())
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    // The workers are woken up to pick the issue back up
+    tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv())
+        .await
+        .expect("The delivery workers were not notified.")
+        .unwrap();
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn only_issues_being_sent_can_be_paused() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    // Nobody to deliver to: the issue is sent straight away
+    let issue_id = publish_newsletter(&app).await;
+
+    app.post_pause_newsletter_issue(&issue_id.to_string()).await;
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only issues being sent can be paused."));
+
+    app.post_resume_newsletter_issue(&issue_id.to_string())
+        .await;
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only paused issues can be resumed."));
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn cancelling_an_issue_mid_send_drops_the_remaining_deliveries() {
+    let mut app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.batch_size = 1;
+    let issue_id = publish_newsletter(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_one_batch().await;
+
+    let response = app
+        .post_cancel_newsletter_issue(&issue_id.to_string())
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains(
+        "The newsletter issue has been cancelled: 1 emails had already been sent, 2 will not be."
+    ));
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let issue = sqlx::query!(
+        r#"
+        SELECT status, n_sent_before_cancellation, n_cancelled_deliveries
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "cancelled");
+    assert_eq!(issue.n_sent_before_cancellation, Some(1));
+    assert_eq!(issue.n_cancelled_deliveries, Some(2));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn cancelling_an_issue_while_a_worker_is_sending_a_batch_keeps_its_log() {
+    let mut app = spawn_app().await;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+    app.worker_config.batch_size = 1;
+    let issue_id = publish_newsletter(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let cancel = async {
+        // Let the worker lock its batch first
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        app.post_cancel_newsletter_issue(&issue_id.to_string())
+            .await
+    };
+    let (_, response) = tokio::join!(app.dispatch_one_batch(), cancel);
+
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let sent =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE status = 'sent'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(sent.count, 1);
+    let issue = sqlx::query!(
+        r#"
+        SELECT status, n_sent_before_cancellation, n_cancelled_deliveries
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "cancelled");
+    assert_eq!(issue.n_sent_before_cancellation, Some(1));
+    assert_eq!(issue.n_cancelled_deliveries, Some(2));
+}
+
+#[tokio::test]
+async fn paused_issues_can_be_cancelled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_newsletter(&app).await;
+    app.post_pause_newsletter_issue(&issue_id.to_string()).await;
+
+    app.post_cancel_newsletter_issue(&issue_id.to_string())
+        .await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "cancelled");
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("0 emails had already been sent, 1 were not."));
+}
+
+#[tokio::test]
+async fn sent_issues_cannot_be_cancelled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_newsletter(&app).await;
+
+    app.post_cancel_newsletter_issue(&issue_id.to_string())
+        .await;
+
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only issues that are not fully sent yet can be cancelled."));
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn failures_of_cancelled_issues_are_not_requeued() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_newsletter(&app).await;
+    sqlx::query!(
+        r#"
+        WITH failed AS (
+            DELETE FROM issue_delivery_queue
+            RETURNING newsletter_issue_id, subscriber_email
+        )
+        INSERT INTO issue_delivery_failures
+            (newsletter_issue_id, subscriber_email, last_error, n_attempts, failed_at)
+        SELECT newsletter_issue_id, subscriber_email, 'Boom', 1, now()
+        FROM failed
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.post_cancel_newsletter_issue(&issue_id.to_string())
+        .await;
+
+    app.post_requeue_failures(&serde_json::json!({
+        "newsletter_issue_id": issue_id.to_string()
+    }))
+    .await;
+
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    assert_eq!(issue_status(&app, issue_id).await, "cancelled");
+}
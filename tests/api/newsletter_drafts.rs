@@ -0,0 +1,327 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_newsletter_draft, spawn_app, TestApp,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_save_a_draft() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_create_draft(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn saved_drafts_are_not_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let issue_id = create_newsletter_draft(&app).await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("The draft has been saved."));
+    assert!(html_page.contains("Newsletter body as plain text"));
+}
+
+#[tokio::test]
+async fn incomplete_drafts_can_be_saved() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_create_draft(&serde_json::json!({
+            "title": "",
+            "text_content": "",
+            "html_content": "",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn drafts_can_be_updated() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    let response = app
+        .post_update_draft(
+            &issue_id.to_string(),
+            &serde_json::json!({
+                "title": "A better title",
+                "text_content": "Better plain text",
+                "html_content": "<p>Better HTML</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}/edit"));
+
+    let issue = sqlx::query!(
+        "SELECT title, text_content, html_content FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "A better title");
+    assert_eq!(issue.text_content, "Better plain text");
+    assert_eq!(issue.html_content, "<p>Better HTML</p>");
+}
+
+#[tokio::test]
+async fn drafts_can_be_deleted() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    let response = app.post_delete_draft(&issue_id.to_string()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The draft has been deleted."));
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn the_preview_shows_both_versions_of_the_issue() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    let html_page = app.get_newsletter_preview_html(&issue_id.to_string()).await;
+
+    // The HTML version is embedded, escaped and in its layout, in a sandboxed frame
+    assert!(html_page.contains(r#"srcdoc="&lt;!DOCTYPE html&gt;"#));
+    assert!(html_page.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(html_page.contains("<pre>Newsletter body as plain text</pre>"));
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_publish_draft(
+            &issue_id.to_string(),
+            &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn publishing_a_draft_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // For idempotency, we expect only one email request to be fired
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() });
+    let response1 = app.post_publish_draft(&issue_id.to_string(), &body).await;
+    let response2 = app.post_publish_draft(&issue_id.to_string(), &body).await;
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.headers().get("location"),
+        response2.headers().get("location")
+    );
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_published_draft_cannot_be_published_again_with_a_new_key() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    for _ in 0..2 {
+        app.post_publish_draft(
+            &issue_id.to_string(),
+            &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+    }
+
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only drafts can be published."));
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+}
+
+#[tokio::test]
+async fn drafts_can_be_published_at_a_later_time() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    let send_time = (Utc::now() + Duration::hours(1)).format("%Y-%m-%dT%H:%M");
+    app.post_publish_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "scheduled_for": send_time.to_string(),
+        }),
+    )
+    .await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn incomplete_drafts_cannot_be_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    app.post_update_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "",
+        }),
+    )
+    .await;
+
+    let idempotency_key = Uuid::new_v4().to_string();
+    let response = app
+        .post_publish_draft(
+            &issue_id.to_string(),
+            &serde_json::json!({ "idempotency_key": idempotency_key }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}/edit"));
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("before it can be published."));
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+
+    // Once fixed, the draft can be published with the same key
+    app.post_update_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }),
+    )
+    .await;
+    app.post_publish_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({ "idempotency_key": idempotency_key }),
+    )
+    .await;
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+}
+
+#[tokio::test]
+async fn published_issues_can_no_longer_be_edited_or_deleted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    app.post_publish_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+    )
+    .await;
+
+    let response = app
+        .post_update_draft(
+            &issue_id.to_string(),
+            &serde_json::json!({
+                "title": "Too late",
+                "text_content": "Too late",
+                "html_content": "<p>Too late</p>",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only drafts can be edited."));
+
+    let response = app.post_delete_draft(&issue_id.to_string()).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only drafts can be deleted."));
+
+    let response = app.get_edit_draft(&issue_id.to_string()).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+}
+
+#[tokio::test]
+async fn issues_published_to_nobody_are_sent_straight_away() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    app.post_publish_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+    )
+    .await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
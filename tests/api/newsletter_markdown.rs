@@ -0,0 +1,143 @@
+use crate::helpers::{create_confirmed_subscriber, create_newsletter_draft, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const MARKDOWN: &str = "Hi {{ subscriber.name | default('there') }}, version **2.0** is out.\n\n\
+    Read [the announcement](https://example.com/blog/2-0).<script>alert(1)</script>";
+
+/// Publish an issue to a single confirmed subscriber and return the message
+/// sent to Postmark.
+async fn publish_and_deliver(app: &TestApp, body: serde_json::Value) -> serde_json::Value {
+    create_confirmed_subscriber(app).await;
+    app.test_user.login(app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let mut messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    messages.remove(0)
+}
+
+#[tokio::test]
+async fn both_contents_are_generated_from_markdown() {
+    let app = spawn_app().await;
+
+    let message = publish_and_deliver(
+        &app,
+        serde_json::json!({
+            "title": "Release notes",
+            "markdown_content": MARKDOWN,
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }),
+    )
+    .await;
+
+    let html_body = message["HtmlBody"].as_str().unwrap();
+    assert!(
+        html_body.contains("<title>Release notes</title>"),
+        "{html_body}"
+    );
+    assert!(html_body.contains("version <strong>2.0</strong> is out.</p>"));
+    assert!(html_body
+        .contains(r#"<p>Read <a href="https://example.com/blog/2-0" rel="noopener noreferrer""#));
+    assert!(!html_body.contains("<script>"));
+    let text_body = message["TextBody"].as_str().unwrap();
+    assert!(
+        text_body.contains(
+            "version 2.0 is out.\n\n\
+        Read the announcement [1].\n\n\
+        [1] https://example.com/blog/2-0"
+        ),
+        "{text_body}"
+    );
+}
+
+#[tokio::test]
+async fn explicit_contents_override_the_generated_ones() {
+    let app = spawn_app().await;
+
+    let message = publish_and_deliver(
+        &app,
+        serde_json::json!({
+            "title": "Release notes",
+            "markdown_content": MARKDOWN,
+            "text_content": "Version 2.0 is out, see https://example.com/blog/2-0",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }),
+    )
+    .await;
+
+    assert!(message["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<strong>2.0</strong>"));
+    assert!(message["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Version 2.0 is out, see https://example.com/blog/2-0"));
+}
+
+#[tokio::test]
+async fn drafts_keep_following_their_markdown() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    let update = |markdown_content: &str| {
+        serde_json::json!({
+            "title": "Release notes",
+            "markdown_content": markdown_content,
+            "text_content": "",
+            "html_content": "",
+        })
+    };
+
+    app.post_update_draft(&issue_id.to_string(), &update("Version **2.0**"))
+        .await;
+    app.post_update_draft(&issue_id.to_string(), &update("Version **2.1**"))
+        .await;
+
+    let issue = sqlx::query!(
+        "SELECT markdown_content, text_content, html_content FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.markdown_content.as_deref(), Some("Version **2.1**"));
+    assert_eq!(issue.text_content, "Version 2.1");
+    assert!(issue
+        .html_content
+        .contains("<p>Version <strong>2.1</strong></p>"));
+    // Generated contents are not shown as overrides
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Version **2.1**</textarea>"));
+    assert!(!html_page.contains("Version 2.1</textarea>"));
+}
+
+#[tokio::test]
+async fn issues_need_markdown_or_both_contents() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Release notes",
+            "html_content": "<p>Version 2.0</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,180 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_newsletter(app: &TestApp) -> uuid::Uuid {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the published issue.")
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_progress_of_an_issue() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_newsletter_issue(&uuid::Uuid::new_v4().to_string())
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn unknown_issues_return_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .get_newsletter_issue(&uuid::Uuid::new_v4().to_string())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn successful_deliveries_are_logged_with_the_provider_message_id() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+                "ErrorCode": 0,
+                "Message": "OK"
+            }])),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let entry = sqlx::query!(
+        "SELECT status, provider_message_id FROM issue_deliveries WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Expected a delivery log entry.");
+    assert_eq!(entry.status, "sent");
+    assert_eq!(
+        entry.provider_message_id.as_deref(),
+        Some("0a129aee-e1cd-480d-b08d-4f48548ff48d")
+    );
+}
+
+#[tokio::test]
+async fn every_attempt_is_appended_to_the_delivery_log() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let statuses: Vec<String> =
+        sqlx::query!("SELECT status FROM issue_deliveries ORDER BY attempted_at, id")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.status)
+            .collect();
+    assert_eq!(statuses, vec!["retrying", "sent"]);
+}
+
+#[tokio::test]
+async fn the_progress_page_shows_queued_sent_and_failed_counts() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await.to_string();
+
+    let html_page = app.get_newsletter_issue_html(&issue_id).await;
+    assert!(html_page.contains("Newsletter title"));
+    assert!(html_page.contains("Queued: 2"));
+    assert!(html_page.contains("Sent: 0"));
+    assert!(html_page.contains(r#"<progress value="0" max="2">"#));
+
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_newsletter_issue_html(&issue_id).await;
+    assert!(html_page.contains("Queued: 0"));
+    assert!(html_page.contains("Sent: 2"));
+    assert!(html_page.contains("Failed: 0"));
+    assert!(html_page.contains(r#"<progress value="2" max="2">"#));
+}
+
+#[tokio::test]
+async fn the_delivery_log_is_escaped() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE issue_deliveries SET error_message = '<script>alert(1)</script>'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+}
+
+#[tokio::test]
+async fn published_issues_are_linked_from_the_newsletter_form() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let issue_id = publish_newsletter(&app).await;
+
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(&format!(r#"href="/admin/newsletters/{}""#, issue_id)));
+}
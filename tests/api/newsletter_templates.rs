@@ -0,0 +1,171 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_newsletter_draft, spawn_app, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn n_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn issues_are_rendered_for_every_recipient() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET name = '<b>' || email || '</b>'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&serde_json::json!({
+        "title": "Release notes",
+        "text_content": "Hi {{ subscriber.name }}, here are the {{ issue.title | lower }}.",
+        "html_content": r#"<p>Hi {{ subscriber.name }}</p><a href="{{ unsubscribe_url }}">Leave</a>"#,
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body.len(), 2);
+    for message in body {
+        let email = message["To"].as_str().unwrap();
+        let subscriber_id = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .id;
+        let unsubscribe_link = app.unsubscribe_links.link(subscriber_id);
+        let (_, token) = unsubscribe_link.rsplit_once("token=").unwrap();
+        assert!(message["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("Hi <b>{email}</b>, here are the release notes.")));
+        let html_body = message["HtmlBody"].as_str().unwrap();
+        assert!(html_body.contains(&format!(
+            r#"<p>Hi &lt;b&gt;{email}&lt;&#x2f;b&gt;</p><a href=""#
+        )));
+        // The layout styles the link inline, after its href
+        assert!(html_body.contains(&format!(r#"token={token}" style="#)));
+        assert!(html_body.contains(">Leave</a>"));
+    }
+}
+
+#[tokio::test]
+async fn issues_with_invalid_templates_are_rejected_at_publish_time() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = [
+        (
+            "<p>Hi {{ subscriber.name </p>",
+            "Hi",
+            "The HTML content is not a valid template",
+        ),
+        (
+            "<p>Hi</p>",
+            "Hi {{ subscriber.nmae }}",
+            "Failed to render the plain text content",
+        ),
+    ];
+
+    for (html_content, text_content, error) in test_cases {
+        let response = app
+            .post_newsletters(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": text_content,
+                "html_content": html_content,
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+
+        assert_is_redirect_to(&response, "/admin/newsletters");
+        let html_page = app.get_newsletters_html().await;
+        assert!(html_page.contains(error), "{html_page}");
+    }
+    assert_eq!(n_issues(&app).await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn drafts_with_invalid_templates_cannot_be_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    app.post_update_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "{% if subscriber.name %}Hi",
+            "html_content": "<p>Hi</p>",
+        }),
+    )
+    .await;
+
+    let response = app
+        .post_publish_draft(
+            &issue_id.to_string(),
+            &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+        )
+        .await;
+
+    let edit_page = format!("/admin/newsletters/{issue_id}/edit");
+    assert_is_redirect_to(&response, &edit_page);
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("The plain text content is not a valid template"));
+    let status = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status;
+    assert_eq!(status, "draft");
+}
+
+#[tokio::test]
+async fn previews_are_rendered_for_a_sample_subscriber() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    app.post_update_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Hi {{ subscriber.name | default('there') }}",
+            "html_content": "<p>Hi {{ subscriber.name }}</p>",
+        }),
+    )
+    .await;
+
+    let html_page = app.get_newsletter_preview_html(&issue_id.to_string()).await;
+
+    assert!(html_page.contains("<pre>Hi Ursula Le Guin</pre>"));
+}
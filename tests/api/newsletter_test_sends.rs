@@ -0,0 +1,283 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_newsletter_draft, spawn_app,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_send_a_test_copy() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_test_send(
+            &Uuid::new_v4().to_string(),
+            &serde_json::json!({ "recipients": "editor@example.com" }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn test_copies_only_go_to_the_given_addresses() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "first" },
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "second" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_test_send(
+            &issue_id.to_string(),
+            &serde_json::json!({ "recipients": "editor@example.com, proofreader@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}/edit"));
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let recipients: Vec<_> = body.iter().map(|m| m["To"].as_str().unwrap()).collect();
+    assert_eq!(
+        recipients,
+        ["editor@example.com", "proofreader@example.com"]
+    );
+    assert!(body
+        .iter()
+        .all(|m| m["Subject"].as_str().unwrap() == "[Test] Newsletter title"));
+}
+
+#[tokio::test]
+async fn test_copies_never_touch_the_delivery_queue_or_the_issue_status() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_test_send(
+        &issue_id.to_string(),
+        &serde_json::json!({ "recipients": "editor@example.com" }),
+    )
+    .await;
+
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let n_deliveries = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_deliveries, 0);
+    let issue = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "draft");
+}
+
+#[tokio::test]
+async fn test_sends_are_recorded_in_the_issue_history() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "a-message-id" },
+            { "ErrorCode": 406, "Message": "Inactive recipient" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_test_send(
+        &issue_id.to_string(),
+        &serde_json::json!({ "recipients": "editor@example.com inactive@example.com" }),
+    )
+    .await;
+
+    let test_sends = sqlx::query!(
+        r#"
+        SELECT recipient, sent_by, provider_message_id, error_message
+        FROM newsletter_issue_test_sends
+        WHERE newsletter_issue_id = $1
+        ORDER BY id
+        "#,
+        issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(test_sends.len(), 2);
+    assert_eq!(test_sends[0].recipient, "editor@example.com");
+    assert_eq!(test_sends[0].sent_by, app.test_user.user_id);
+    assert_eq!(
+        test_sends[0].provider_message_id.as_deref(),
+        Some("a-message-id")
+    );
+    assert!(test_sends[0].error_message.is_none());
+    assert_eq!(test_sends[1].recipient, "inactive@example.com");
+    assert!(test_sends[1].error_message.is_some());
+
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("A test copy was sent to editor@example.com."));
+    assert!(html_page.contains("Failed to send a test copy to inactive@example.com"));
+    assert!(html_page.contains("sent (a-message-id)"));
+}
+
+#[tokio::test]
+async fn test_copies_of_published_issues_can_be_sent_too() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    app.post_publish_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+    )
+    .await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_test_send(
+            &issue_id.to_string(),
+            &serde_json::json!({ "recipients": "editor@example.com" }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("editor@example.com"));
+}
+
+#[tokio::test]
+async fn invalid_recipients_are_rejected_without_sending_anything() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_test_send(
+            &issue_id.to_string(),
+            &serde_json::json!({ "recipients": "editor@example.com, not-an-email" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}/edit"));
+
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("not-an-email is not a valid subscriber email."));
+    let n_test_sends =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issue_test_sends"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_test_sends, 0);
+}
+
+#[tokio::test]
+async fn invalid_recipients_are_escaped_in_the_error_message() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    app.post_test_send(
+        &issue_id.to_string(),
+        &serde_json::json!({ "recipients": "<script>alert(1)</script>" }),
+    )
+    .await;
+
+    let html_page = app.get_edit_draft_html(&issue_id.to_string()).await;
+    assert!(!html_page.contains("<script>alert(1)</script>"));
+    assert!(html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt; is not a valid"));
+}
+
+#[tokio::test]
+async fn provider_errors_are_escaped_in_the_error_message() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+    app.post_publish_draft(
+        &issue_id.to_string(),
+        &serde_json::json!({ "idempotency_key": Uuid::new_v4().to_string() }),
+    )
+    .await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("<script>alert(1)</script>"))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_test_send(
+        &issue_id.to_string(),
+        &serde_json::json!({ "recipients": "editor@example.com" }),
+    )
+    .await;
+
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Failed to send a test copy to editor@example.com"));
+    assert!(!html_page.contains("<script>alert(1)</script>"));
+}
+
+#[tokio::test]
+async fn deleting_a_draft_deletes_its_test_sends() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_newsletter_draft(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_test_send(
+        &issue_id.to_string(),
+        &serde_json::json!({ "recipients": "editor@example.com" }),
+    )
+    .await;
+
+    let response = app.post_delete_draft(&issue_id.to_string()).await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
@@ -0,0 +1,84 @@
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+use chrono::{Duration, Utc};
+use zero2prod::subscription_cleanup::delete_stale_pending_subscribers;
+
+/// Pretend every subscriber signed up, and was sent their confirmation
+/// link, `days` ago.
+async fn age_subscribers(app: &TestApp, days: i64) {
+    let then = Utc::now() - Duration::days(days);
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = $1", then)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET created_at = $1", then)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn subscriber_statuses(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.status)
+        .collect()
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_are_deleted_along_with_their_tokens() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    age_subscribers(&app, 31).await;
+
+    let n_deleted = delete_stale_pending_subscribers(&app.db_pool, Utc::now() - Duration::days(30))
+        .await
+        .unwrap();
+
+    assert_eq!(n_deleted, 1);
+    assert!(subscriber_statuses(&app).await.is_empty());
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn confirmed_and_recent_pending_subscribers_are_kept() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    age_subscribers(&app, 31).await;
+    create_unconfirmed_subscriber(&app).await;
+
+    let n_deleted = delete_stale_pending_subscribers(&app.db_pool, Utc::now() - Duration::days(30))
+        .await
+        .unwrap();
+
+    assert_eq!(n_deleted, 0);
+    let mut statuses = subscriber_statuses(&app).await;
+    statuses.sort();
+    assert_eq!(statuses, vec!["confirmed", "pending_confirmation"]);
+}
+
+#[tokio::test]
+async fn pending_subscribers_sent_a_new_link_recently_are_kept() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    age_subscribers(&app, 31).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let n_deleted = delete_stale_pending_subscribers(&app.db_pool, Utc::now() - Duration::days(30))
+        .await
+        .unwrap();
+
+    assert_eq!(n_deleted, 0);
+    assert_eq!(subscriber_statuses(&app).await.len(), 1);
+}
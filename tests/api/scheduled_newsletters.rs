@@ -0,0 +1,245 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use zero2prod::issue_scheduler::{publish_due_issues, run_scheduler, Clock};
+
+fn send_time_input(send_time: DateTime<Utc>) -> String {
+    send_time.format("%Y-%m-%dT%H:%M").to_string()
+}
+
+/// Schedule an issue `delay` from now and return its id.
+async fn schedule_newsletter(app: &TestApp, delay: Duration) -> Uuid {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": send_time_input(Utc::now() + delay),
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a newsletter issue.")
+        .newsletter_issue_id
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+async fn publish_due_issues_at(app: &TestApp, now: DateTime<Utc>) -> Vec<Uuid> {
+    publish_due_issues(&app.db_pool, now, &app.worker_config.notify_channel)
+        .await
+        .expect("Failed to publish the due issues.")
+}
+
+struct FixedClock(DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_enqueued_right_away() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled for"));
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Scheduled for"));
+}
+
+#[tokio::test]
+async fn scheduled_issues_go_to_subscribers_confirmed_by_the_time_they_are_due() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+    // Confirmed after the issue was scheduled, before it is due
+    create_confirmed_subscriber(&app).await;
+
+    assert!(publish_due_issues_at(&app, Utc::now()).await.is_empty());
+    let published = publish_due_issues_at(&app, Utc::now() + Duration::hours(2)).await;
+
+    assert_eq!(published, vec![issue_id]);
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+    let issue = sqlx::query!(
+        "SELECT published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(issue.published_at.is_some());
+    // An issue is only ever published once
+    assert!(publish_due_issues_at(&app, Utc::now() + Duration::hours(3))
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn publishing_a_scheduled_issue_notifies_the_delivery_workers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    schedule_newsletter(&app, Duration::hours(1)).await;
+    let mut listener = app.listen_to_delivery_queue().await;
+
+    publish_due_issues_at(&app, Utc::now() + Duration::hours(2)).await;
+
+    let notification = tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv())
+        .await
+        .expect("The delivery workers were not notified.")
+        .unwrap();
+    assert_eq!(notification.channel(), app.worker_config.notify_channel);
+}
+
+#[tokio::test]
+async fn the_scheduler_publishes_issues_once_its_clock_reaches_their_send_time() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    schedule_newsletter(&app, Duration::days(1)).await;
+
+    let shutdown = CancellationToken::new();
+    let scheduler = tokio::spawn(run_scheduler(
+        app.db_pool.clone(),
+        Arc::new(FixedClock(Utc::now() + Duration::days(2))),
+        app.worker_config.clone(),
+        shutdown.clone(),
+    ));
+
+    let enqueued = async {
+        while n_queued_deliveries(&app).await == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(5), enqueued)
+        .await
+        .expect("The scheduler did not publish the due issue.");
+    shutdown.cancel();
+    tokio::time::timeout(std::time::Duration::from_secs(5), scheduler)
+        .await
+        .expect("The scheduler did not stop.")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rescheduled_issues_are_published_at_their_new_send_time() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+
+    let response = app
+        .post_reschedule_newsletter_issue(
+            &issue_id.to_string(),
+            &serde_json::json!({
+                "scheduled_for": send_time_input(Utc::now() + Duration::days(1)),
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("The newsletter issue has been rescheduled for"));
+
+    assert!(publish_due_issues_at(&app, Utc::now() + Duration::hours(2))
+        .await
+        .is_empty());
+    let published = publish_due_issues_at(&app, Utc::now() + Duration::days(2)).await;
+    assert_eq!(published, vec![issue_id]);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+
+    let response = app
+        .post_cancel_newsletter_issue(&issue_id.to_string())
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("The scheduled newsletter issue has been cancelled."));
+    assert!(html_page.contains("Cancelled at"));
+
+    assert!(publish_due_issues_at(&app, Utc::now() + Duration::days(1))
+        .await
+        .is_empty());
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn published_issues_can_no_longer_be_rescheduled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = schedule_newsletter(&app, Duration::hours(1)).await;
+    publish_due_issues_at(&app, Utc::now() + Duration::hours(2)).await;
+
+    app.post_reschedule_newsletter_issue(
+        &issue_id.to_string(),
+        &serde_json::json!({
+            "scheduled_for": send_time_input(Utc::now() + Duration::days(1)),
+        }),
+    )
+    .await;
+    let html_page = app.get_newsletter_issue_html(&issue_id.to_string()).await;
+    assert!(html_page.contains("Only issues waiting to be sent can be rescheduled."));
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+}
+
+#[tokio::test]
+async fn send_times_in_the_past_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": send_time_input(Utc::now() - Duration::hours(1)),
+    });
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The send time must be in the future."));
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_cancel_a_scheduled_issue() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_cancel_newsletter_issue(&Uuid::new_v4().to_string())
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
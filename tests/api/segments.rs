@@ -0,0 +1,275 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_newsletter_draft, spawn_app, TestApp,
+};
+use uuid::Uuid;
+
+async fn create_segment(app: &TestApp, name: &str, definition: &str) -> Uuid {
+    let response = app
+        .post_segments(&serde_json::json!({ "name": name, "definition": definition }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+    sqlx::query!("SELECT segment_id FROM segments WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected the segment to be saved.")
+        .segment_id
+}
+
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, name) VALUES ($1, $2)",
+        list_id,
+        name
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    list_id
+}
+
+/// Create a confirmed subscriber with these tags and list memberships.
+async fn create_subscriber(app: &TestApp, tags: &[&str], list_ids: &[Uuid]) -> String {
+    create_confirmed_subscriber(app).await;
+    let subscriber =
+        sqlx::query!("SELECT id, email FROM subscriptions ORDER BY subscribed_at DESC, id LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    for tag in tags {
+        sqlx::query!(
+            "INSERT INTO subscriber_tags (subscriber_id, tag) VALUES ($1, $2)",
+            subscriber.id,
+            tag
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    for list_id in list_ids {
+        sqlx::query!(
+            "INSERT INTO list_subscriptions (list_id, subscriber_id) VALUES ($1, $2)",
+            list_id,
+            subscriber.id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    subscriber.email
+}
+
+fn newsletter_form(segment_id: Uuid, list_ids: &[Uuid]) -> Vec<(&'static str, String)> {
+    let mut form = vec![
+        ("title", "Newsletter title".to_string()),
+        ("text_content", "Newsletter body as plain text".to_string()),
+        ("html_content", "<p>Newsletter body as HTML</p>".to_string()),
+        ("idempotency_key", Uuid::new_v4().to_string()),
+        ("segment_id", segment_id.to_string()),
+    ];
+    form.extend(list_ids.iter().map(|id| ("list_id", id.to_string())));
+    form
+}
+
+async fn queued_recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<_> = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.subscriber_email)
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn admins_can_save_segments() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_subscriber(&app, &["vip"], &[]).await;
+    create_subscriber(&app, &[], &[]).await;
+
+    create_segment(&app, "VIPs", "tag:vip").await;
+
+    let html_page = app.get_segments_html().await;
+    assert!(html_page.contains("The segment VIPs has been saved."));
+    assert!(html_page.contains("VIPs (1 confirmed members): <code>tag:vip</code>"));
+}
+
+#[tokio::test]
+async fn invalid_segment_definitions_are_not_saved() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_segments(&serde_json::json!({ "name": "VIPs", "definition": "tag:vip and" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/segments");
+    let html_page = app.get_segments_html().await;
+    assert!(html_page.contains("Invalid segment definition: Expected a condition"));
+    let n_segments = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM segments"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_segments, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_save_segments() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_segments(&serde_json::json!({ "name": "VIPs", "definition": "tag:vip" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_publish_forms_offer_every_segment() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let segment_id = create_segment(&app, "VIPs", "tag:vip").await;
+    let newsletter_issue_id = create_newsletter_draft(&app).await;
+
+    let option = format!(r#"<option value="{segment_id}">VIPs (tag:vip)</option>"#);
+    assert!(app.get_newsletters_html().await.contains(&option));
+    assert!(app
+        .get_edit_draft_html(&newsletter_issue_id.to_string())
+        .await
+        .contains(&option));
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_the_subscribers_in_it() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let events = create_list(&app, "Local events").await;
+    let mut expected = vec![
+        create_subscriber(&app, &["vip"], &[]).await,
+        create_subscriber(&app, &["beta"], &[events]).await,
+    ];
+    expected.sort();
+    create_subscriber(&app, &["vip"], &[events]).await;
+    create_subscriber(&app, &["beta"], &[]).await;
+    let segment_id = create_segment(
+        &app,
+        "Segment",
+        r#"(tag:vip and not list:"Local events") or (tag:beta and list:"Local events")"#,
+    )
+    .await;
+
+    app.post_newsletters(&newsletter_form(segment_id, &[]))
+        .await;
+
+    assert_eq!(queued_recipients(&app).await, expected);
+}
+
+#[tokio::test]
+async fn segments_narrow_down_the_targeted_lists() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let expected = create_subscriber(&app, &["vip"], &[release_notes]).await;
+    create_subscriber(&app, &["vip"], &[]).await;
+    create_subscriber(&app, &[], &[release_notes]).await;
+    let segment_id = create_segment(&app, "VIPs", "tag:vip").await;
+
+    app.post_newsletters(&newsletter_form(segment_id, &[release_notes]))
+        .await;
+
+    assert_eq!(queued_recipients(&app).await, vec![expected]);
+}
+
+#[tokio::test]
+async fn segments_can_target_subscribers_by_signup_date() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let old_timer = create_subscriber(&app, &[], &[]).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2023-12-31T23:59:59Z' WHERE email = $1",
+        old_timer
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    create_subscriber(&app, &[], &[]).await;
+    let segment_id = create_segment(&app, "Old timers", "signed_up_before:2024-01-01").await;
+
+    app.post_newsletters(&newsletter_form(segment_id, &[]))
+        .await;
+
+    assert_eq!(queued_recipients(&app).await, vec![old_timer]);
+}
+
+#[tokio::test]
+async fn an_empty_segment_sends_the_issue_to_nobody() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_subscriber(&app, &[], &[]).await;
+    let segment_id = create_segment(&app, "VIPs", "tag:vip").await;
+
+    app.post_newsletters(&newsletter_form(segment_id, &[]))
+        .await;
+
+    assert!(queued_recipients(&app).await.is_empty());
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "sent");
+}
+
+#[tokio::test]
+async fn published_drafts_and_scheduled_issues_keep_their_segment() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let vip = create_subscriber(&app, &["vip"], &[]).await;
+    create_subscriber(&app, &[], &[]).await;
+    let segment_id = create_segment(&app, "VIPs", "tag:vip").await;
+    let newsletter_issue_id = create_newsletter_draft(&app).await;
+
+    app.post_publish_draft(
+        &newsletter_issue_id.to_string(),
+        &[
+            ("idempotency_key", Uuid::new_v4().to_string()),
+            ("scheduled_for", "2099-01-01T00:00".to_string()),
+            ("segment_id", segment_id.to_string()),
+        ],
+    )
+    .await;
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    zero2prod::issue_scheduler::publish_due_issues(
+        &app.db_pool,
+        chrono::Utc::now(),
+        &app.worker_config.notify_channel,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(queued_recipients(&app).await, vec![vip]);
+}
+
+#[tokio::test]
+async fn publishing_with_an_invalid_segment_id_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_newsletters(&[
+            ("title", "Newsletter title"),
+            ("text_content", "Newsletter body as plain text"),
+            ("html_content", "<p>Newsletter body as HTML</p>"),
+            ("idempotency_key", &Uuid::new_v4().to_string()),
+            ("segment_id", "vip"),
+        ])
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
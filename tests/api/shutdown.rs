@@ -0,0 +1,102 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::run_delivery_workers;
+
+#[tokio::test]
+async fn the_api_stops_accepting_connections_once_shut_down() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let health_check = format!("{}/health_check", &app.address);
+    assert!(client.get(&health_check).send().await.is_ok());
+
+    app.shutdown.cancel();
+
+    let stopped = async {
+        while client.get(&health_check).send().await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), stopped)
+        .await
+        .expect("The API kept accepting connections.");
+}
+
+#[tokio::test]
+async fn idle_workers_stop_as_soon_as_they_are_shut_down() {
+    let app = spawn_app().await;
+    let shutdown = CancellationToken::new();
+    let workers = tokio::spawn(run_delivery_workers(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.unsubscribe_links.clone(),
+        app.click_links.clone(),
+        app.worker_config.clone(),
+        shutdown.clone(),
+    ));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(2), workers)
+        .await
+        .expect("The workers did not stop.")
+        .unwrap()
+        .expect("The workers failed.");
+}
+
+#[tokio::test]
+async fn workers_finish_the_batch_in_flight_before_stopping() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+    let shutdown = CancellationToken::new();
+    let workers = tokio::spawn(run_delivery_workers(
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.unsubscribe_links.clone(),
+        app.click_links.clone(),
+        app.worker_config.clone(),
+        shutdown.clone(),
+    ));
+    // Wait for the batch to be handed to the email provider, right after the
+    // subscriber's confirmation email
+    while app.email_server.received_requests().await.unwrap().len() < 2 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(5), workers)
+        .await
+        .expect("The workers did not stop.")
+        .unwrap()
+        .expect("The workers failed.");
+
+    let remaining = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+    let delivery = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The delivery should have been recorded.");
+    assert_eq!(delivery.status, "sent");
+}
@@ -0,0 +1,372 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    TestApp,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::subscriber_data::email_hash;
+
+async fn subscriber(app: &TestApp) -> (Uuid, String) {
+    let record = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a subscriber.");
+    (record.id, record.email)
+}
+
+async fn n_rows(app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+/// A link for the subscriber to follow, signed like their preferences link.
+fn self_service_link(app: &TestApp, subscriber_id: Uuid, path: &str) -> reqwest::Url {
+    let mut link = app.preferences_link(subscriber_id);
+    link.set_path(path);
+    link
+}
+
+async fn send_newsletter(app: &TestApp) {
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn admins_can_export_subscribers_as_csv_filtered_by_status() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, confirmed_email) = subscriber(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .get_subscribers_export(&[("format", "csv"), ("status", "confirmed"), ("list_id", "")])
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["content-disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "email,name,status,email_format,subscribed_at,unsubscribed_at,tags,lists"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with(&format!("{confirmed_email},")));
+    assert!(lines[1].contains(",confirmed,"));
+}
+
+#[tokio::test]
+async fn admins_can_export_the_members_of_a_list_as_json() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, email) = subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, name) VALUES ($1, 'Release notes')",
+        list_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO list_subscriptions (list_id, subscriber_id) VALUES ($1, $2)",
+        list_id,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    let response = app
+        .get_subscribers_export(&[("format", "json"), ("list_id", &list_id.to_string())])
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0]["email"], email);
+    assert_eq!(
+        subscribers[0]["lists"],
+        serde_json::json!(["Release notes"])
+    );
+}
+
+#[tokio::test]
+async fn invalid_export_filters_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = [
+        vec![("format", "xml")],
+        vec![("format", "csv"), ("status", "banned")],
+        vec![("format", "csv"), ("list_id", "not-a-uuid")],
+    ];
+
+    for query in test_cases {
+        let response = app.get_subscribers_export(&query).await;
+
+        assert_eq!(response.status().as_u16(), 400, "{query:?}");
+    }
+}
+
+#[tokio::test]
+async fn admins_can_export_the_data_of_a_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, email) = subscriber(&app).await;
+    sqlx::query!(
+        "INSERT INTO subscriber_tags (subscriber_id, tag) VALUES ($1, 'vip')",
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    send_newsletter(&app).await;
+
+    let response = app.get_subscriber_data(&email).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], email);
+    assert_eq!(data["subscriber"]["status"], "confirmed");
+    assert_eq!(data["tags"], serde_json::json!(["vip"]));
+    assert_eq!(data["deliveries"].as_array().unwrap().len(), 1);
+    // Tokens are credentials, they are never exported
+    assert!(!data.to_string().contains("subscription_token"));
+}
+
+#[tokio::test]
+async fn requests_about_unknown_subscribers_are_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_subscriber_data("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_subscribers_html().await;
+    assert!(html_page.contains("There is no subscriber with the email nobody@example.com."));
+
+    let response = app.post_erase_subscriber("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_subscribers_html().await;
+    assert!(html_page.contains("There is no subscriber with the email nobody@example.com."));
+}
+
+#[tokio::test]
+async fn erasing_a_pending_subscriber_deletes_their_confirmation_tokens() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let (_, email) = subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_erase_subscriber(&email).await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_subscribers_html().await;
+    assert!(html_page.contains(&format!("The data of {email} has been erased.")));
+    assert_eq!(n_rows(&app, "subscriptions").await, 0);
+    assert_eq!(n_rows(&app, "subscription_tokens").await, 0);
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_hashes_their_email_in_the_delivery_log() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, email) = subscriber(&app).await;
+    app.test_user.login(&app).await;
+    send_newsletter(&app).await;
+
+    app.post_erase_subscriber(&email).await;
+
+    let deliveries = sqlx::query!("SELECT subscriber_email, status FROM issue_deliveries")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(
+        deliveries[0].subscriber_email,
+        email_hash(&app.hmac_secret, &email)
+    );
+    // Keyed: not something anyone could compute from a list of addresses
+    assert_ne!(
+        deliveries[0].subscriber_email,
+        hex::encode(Sha256::digest(email.as_bytes()))
+    );
+    // The progress of the issue still counts the delivery
+    assert_eq!(deliveries[0].status, "sent");
+    assert_eq!(n_rows(&app, "subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_cannot_be_signed_up_again_once_erased() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, email) = subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'suppressed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+    app.post_erase_subscriber(&email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", &email)]).unwrap();
+    let response = app.post_subscriptions(body).await;
+
+    // Whether an address was erased is nobody's business
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_rows(&app, "subscriptions").await, 0);
+
+    app.post_import(&format!("email,name\n{email},Ursula\n"), "confirmed")
+        .await;
+    assert_eq!(n_rows(&app, "subscriptions").await, 0);
+    let rejection = sqlx::query!("SELECT kind FROM subscriber_import_rejections")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(rejection.kind, "suppressed");
+}
+
+#[tokio::test]
+async fn other_erased_addresses_can_be_signed_up_again() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, email) = subscriber(&app).await;
+    app.test_user.login(&app).await;
+    app.post_erase_subscriber(&email).await;
+
+    app.post_import(&format!("email,name\n{email},Ursula\n"), "confirmed")
+        .await;
+
+    assert_eq!(n_rows(&app, "subscriptions").await, 1);
+}
+
+#[tokio::test]
+async fn subscribers_can_download_their_data_from_a_signed_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, email) = subscriber(&app).await;
+
+    let response = reqwest::get(self_service_link(
+        &app,
+        subscriber_id,
+        "/subscriptions/data",
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], email);
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_data_after_confirming() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+    let link = self_service_link(&app, subscriber_id, "/subscriptions/erase");
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Erase my data"));
+    assert_eq!(n_rows(&app, "subscriptions").await, 1);
+
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your data has been erased."));
+    assert_eq!(n_rows(&app, "subscriptions").await, 0);
+    assert_eq!(n_rows(&app, "erased_subscribers").await, 1);
+}
+
+#[tokio::test]
+async fn self_service_links_for_somebody_else_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+    // A valid token, for somebody else
+    let signed = app.preferences_link(Uuid::new_v4());
+    let token = signed
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    for path in ["/subscriptions/data", "/subscriptions/erase"] {
+        let mut link = self_service_link(&app, subscriber_id, path);
+        link.set_query(Some(&format!(
+            "subscriber_id={subscriber_id}&token={token}"
+        )));
+
+        let response = reqwest::get(link).await.unwrap();
+        assert_eq!(response.status().as_u16(), 401, "{path}");
+    }
+    let mut link = self_service_link(&app, subscriber_id, "/subscriptions/erase");
+    link.set_query(Some(&format!(
+        "subscriber_id={subscriber_id}&token={token}"
+    )));
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(n_rows(&app, "subscriptions").await, 1);
+}
+
+#[tokio::test]
+async fn the_preferences_page_links_to_the_data_requests() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+
+    let html_page = reqwest::get(app.preferences_link(subscriber_id))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page.contains(r#"<a href="/subscriptions/data?subscriber_id="#));
+    assert!(html_page.contains(r#"<a href="/subscriptions/erase?subscriber_id="#));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_or_erase_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_subscribers_export(&[("format", "csv")]).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_subscriber_data("ursula@example.com").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_erase_subscriber("ursula@example.com").await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,260 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn last_import_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT import_id FROM subscriber_imports ORDER BY created_at DESC LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected an import.")
+        .import_id
+}
+
+async fn subscribers(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn confirmed_imports_add_confirmed_subscribers_with_their_tags_and_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, name) VALUES ($1, 'Release notes')",
+        list_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .post_import(
+            "email,name,tags,list\n\
+            ursula@example.com,Ursula Le Guin,\"vip,sf\",Release notes\n\
+            octavia@example.com,Octavia Butler,,\n",
+            "confirmed",
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/imports");
+    assert_eq!(
+        subscribers(&app).await,
+        vec![
+            ("octavia@example.com".into(), "confirmed".into()),
+            ("ursula@example.com".into(), "confirmed".into()),
+        ]
+    );
+    let n_tags = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber_tags"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tags, 2);
+    let member = sqlx::query!("SELECT subscriber_id FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let ursula = sqlx::query!("SELECT id FROM subscriptions WHERE email = 'ursula@example.com'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(member.subscriber_id, ursula.id);
+    let html_page = app.get_imports_html().await;
+    assert!(html_page.contains("Imported 2 of 2 rows: 0 rejected, 0 duplicates."));
+}
+
+#[tokio::test]
+async fn double_opt_in_imports_send_a_confirmation_email_to_every_row() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_import(
+        "email,name\n\
+        ursula@example.com,Ursula Le Guin\n\
+        octavia@example.com,Octavia Butler\n",
+        "double_opt_in",
+    )
+    .await;
+
+    assert_eq!(
+        subscribers(&app).await,
+        vec![
+            ("octavia@example.com".into(), "pending_confirmation".into()),
+            ("ursula@example.com".into(), "pending_confirmation".into()),
+        ]
+    );
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_rows_and_duplicates_are_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let existing = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    app.post_import(
+        &format!(
+            "email,name\n\
+            ursula@example.com,Ursula Le Guin\n\
+            not-an-email,Somebody\n\
+            ursula@example.com,Ursula again\n\
+            {existing},Already there\n"
+        ),
+        "confirmed",
+    )
+    .await;
+
+    let html_page = app.get_imports_html().await;
+    assert!(html_page.contains("Imported 1 of 4 rows: 1 rejected, 2 duplicates."));
+    let response = app.get_import_report(last_import_id(&app).await).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let report = response.text().await.unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "line,email,name,kind,reason");
+    assert!(lines[1].starts_with("3,not-an-email,Somebody,invalid,"));
+    assert_eq!(
+        lines[2],
+        "4,ursula@example.com,Ursula again,duplicate,Duplicate of line 2."
+    );
+    assert_eq!(
+        lines[3],
+        format!("5,{existing},Already there,duplicate,Already a subscriber.")
+    );
+    // Existing subscribers are left untouched
+    let name = sqlx::query!("SELECT name FROM subscriptions WHERE email = $1", existing)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .name;
+    assert_ne!(name, "Already there");
+}
+
+#[tokio::test]
+async fn large_files_are_imported_in_several_batches() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut csv = "email,name\n".to_string();
+    for i in 0..1234 {
+        csv.push_str(&format!("subscriber-{i}@example.com,Subscriber {i}\n"));
+    }
+
+    app.post_import(&csv, "confirmed").await;
+
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1234);
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_are_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_import(
+        "email,name\nursula@example.com,Ursula Le Guin\n",
+        "double_opt_in",
+    )
+    .await;
+
+    let report = app
+        .get_import_report(last_import_id(&app).await)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(report.contains(
+        "2,ursula@example.com,Ursula Le Guin,unsent,\
+        \"Imported, but the confirmation email could not be sent.\""
+    ));
+}
+
+#[tokio::test]
+async fn files_without_email_and_name_columns_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_import(
+            "address,full name\nursula@example.com,Ursula\n",
+            "confirmed",
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/imports");
+    assert!(subscribers(&app).await.is_empty());
+    let html_page = app.get_imports_html().await;
+    assert!(html_page.contains("needs a header row with `email` and `name` columns."));
+}
+
+#[tokio::test]
+async fn unknown_import_modes_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_import("email,name\nursula@example.com,Ursula\n", "pending")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_import("email,name\nursula@example.com,Ursula\n", "confirmed")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_import_report(Uuid::new_v4()).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn reports_of_unknown_imports_are_404s() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_import_report(Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
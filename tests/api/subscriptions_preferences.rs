@@ -0,0 +1,323 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a subscriber.")
+        .id
+}
+
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, name) VALUES ($1, $2)",
+        list_id,
+        name
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    list_id
+}
+
+async fn post_preferences(
+    app: &TestApp,
+    subscriber_id: Uuid,
+    body: &[(&str, String)],
+) -> reqwest::Response {
+    app.api_client
+        .post(app.preferences_link(subscriber_id))
+        .form(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn newsletters_link_to_the_preferences_page() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let link = app.unsubscribe_links.preferences_link(subscriber_id);
+    assert!(body[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("Manage your preferences: {link}")));
+}
+
+#[tokio::test]
+async fn the_preferences_page_shows_the_current_preferences() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let list_id = create_list(&app, "Release notes").await;
+    create_list(&app, "Events").await;
+    sqlx::query!(
+        "INSERT INTO list_subscriptions (list_id, subscriber_id) VALUES ($1, $2)",
+        list_id,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::get(app.preferences_link(subscriber_id))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"name="email_format" value="html" checked"#));
+    assert!(html_page.contains(&format!(r#"value="{list_id}" checked> Release notes"#)));
+    assert!(html_page.contains("> Events"));
+    assert!(html_page.contains("Unsubscribe from all emails"));
+}
+
+#[tokio::test]
+async fn preferences_links_with_an_invalid_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    // A valid token, for somebody else
+    let mut link = app.preferences_link(Uuid::new_v4());
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    link.set_query(Some(&format!(
+        "subscriber_id={subscriber_id}&token={token}"
+    )));
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = reqwest::Client::new()
+        .post(link)
+        .form(&[("name", "Mallory"), ("email_format", "html")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_preferences() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let release_notes = create_list(&app, "Release notes").await;
+    let events = create_list(&app, "Events").await;
+    create_list(&app, "Offers").await;
+
+    let response = post_preferences(
+        &app,
+        subscriber_id,
+        &[
+            ("name", "Ursula Le Guin".into()),
+            ("email_format", "plain_text".into()),
+            ("list_id", release_notes.to_string()),
+            ("list_id", events.to_string()),
+        ],
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 303);
+    let saved = sqlx::query!("SELECT name, email_format FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula Le Guin");
+    assert_eq!(saved.email_format, "plain_text");
+    let mut list_ids: Vec<_> = sqlx::query!("SELECT list_id FROM list_subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.list_id)
+        .collect();
+    list_ids.sort();
+    let mut expected = vec![release_notes, events];
+    expected.sort();
+    assert_eq!(list_ids, expected);
+
+    let html_page = app
+        .api_client
+        .get(app.preferences_link(subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p style=\"color: green;\">Your preferences have been saved.</p>"));
+}
+
+#[tokio::test]
+async fn unticked_lists_are_left() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let list_id = create_list(&app, "Release notes").await;
+    sqlx::query!(
+        "INSERT INTO list_subscriptions (list_id, subscriber_id) VALUES ($1, $2)",
+        list_id,
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    post_preferences(
+        &app,
+        subscriber_id,
+        &[
+            ("name", "Ursula Le Guin".into()),
+            ("email_format", "html".into()),
+        ],
+    )
+    .await;
+
+    let n_memberships = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM list_subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_memberships, 0);
+}
+
+#[tokio::test]
+async fn invalid_names_are_not_saved() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    let name_before = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .name;
+
+    let response = post_preferences(
+        &app,
+        subscriber_id,
+        &[
+            ("name", "<script>".into()),
+            ("email_format", "plain_text".into()),
+        ],
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 303);
+    let saved = sqlx::query!("SELECT name, email_format FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, name_before);
+    assert_eq!(saved.email_format, "html");
+    let html_page = app
+        .api_client
+        .get(app.preferences_link(subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("&lt;script&gt; is not a valid subscriber name."));
+}
+
+#[tokio::test]
+async fn unknown_email_formats_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    let response = post_preferences(
+        &app,
+        subscriber_id,
+        &[
+            ("name", "Ursula Le Guin".into()),
+            ("email_format", "markdown".into()),
+        ],
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn plain_text_subscribers_are_not_sent_html() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let subscriber_id = subscriber_id(&app).await;
+    post_preferences(
+        &app,
+        subscriber_id,
+        &[
+            ("name", "Ursula Le Guin".into()),
+            ("email_format", "plain_text".into()),
+        ],
+    )
+    .await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert!(body[0].get("HtmlBody").is_none());
+    assert!(body[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Newsletter body as plain text"));
+}
@@ -0,0 +1,236 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber(app: &TestApp) -> (Uuid, String) {
+    let record = sqlx::query!("SELECT id, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a subscriber.");
+    (record.id, record.status)
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content" : "Newsletter body as plain text",
+        "html_content" : "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&newsletter_request_body).await;
+}
+
+#[tokio::test]
+async fn newsletters_carry_an_unsubscribe_link_and_headers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let link = app.unsubscribe_links.link(subscriber_id);
+    let message = &body[0];
+    assert!(message["TextBody"]
+        .as_str()
+        .unwrap()
+        .ends_with(&format!("Unsubscribe from this newsletter: {link}")));
+    assert!(message["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&link.replace('&', "&amp;")));
+    assert_eq!(
+        message["Headers"],
+        serde_json::json!([
+            { "Name": "List-Unsubscribe", "Value": format!("<{link}>") },
+            { "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_asks_for_confirmation() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+
+    let response = reqwest::get(app.unsubscribe_link(subscriber_id))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(r#"method="post""#));
+    // Link scanners must not be able to unsubscribe anybody
+    let (_, status) = subscriber(&app).await;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirming_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let record = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(record.status, "unsubscribed");
+    assert!(record.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_requests_are_honoured() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+
+    // What mailbox providers send, as per RFC 8058
+    let response = reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let (_, status) = subscriber(&app).await;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_fine() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+    let client = reqwest::Client::new();
+
+    for _ in 0..2 {
+        let response = client
+            .post(app.unsubscribe_link(subscriber_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn tampered_unsubscribe_links_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+    // A valid token, for somebody else
+    let mut link = app.unsubscribe_link(Uuid::new_v4());
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    link.set_query(Some(&format!(
+        "subscriber_id={subscriber_id}&token={token}"
+    )));
+
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let (_, status) = subscriber(&app).await;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_without_a_token_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}",
+        app.address,
+        Uuid::new_v4()
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+    reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .send()
+        .await
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn subscribers_who_leave_while_an_issue_is_queued_do_not_receive_it() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let (subscriber_id, _) = subscriber(&app).await;
+    publish_newsletter(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .send()
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "sent");
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
@@ -0,0 +1,122 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Expected a subscriber.")
+        .email
+}
+
+async fn saved_tags(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT tag FROM subscriber_tags ORDER BY tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.tag)
+        .collect()
+}
+
+#[tokio::test]
+async fn admins_can_replace_the_tags_of_a_subscriber() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    app.post_tags(&serde_json::json!({ "email": email, "tags": "vip, beta" }))
+        .await;
+
+    let response = app
+        .post_tags(&serde_json::json!({ "email": email, "tags": "VIP, podcast" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/tags");
+    assert_eq!(saved_tags(&app).await, vec!["podcast", "vip"]);
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains(&format!("The tags of {email} have been updated.")));
+    assert!(html_page.contains("vip (1 confirmed subscribers)"));
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_is_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_tags(&serde_json::json!({ "email": "nobody@example.com", "tags": "vip" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/tags");
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("There is no subscriber with the email nobody@example.com."));
+}
+
+#[tokio::test]
+async fn invalid_tags_are_not_saved() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    app.post_tags(&serde_json::json!({ "email": email, "tags": "vip, early adopter" }))
+        .await;
+
+    assert!(saved_tags(&app).await.is_empty());
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("early adopter is not a valid tag."));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_tag_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_tags(&serde_json::json!({ "email": "nobody@example.com", "tags": "vip" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_tagged_from_the_signup_query() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/subscriptions?tags=podcast,RustConf",
+            &app.address
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_tags(&app).await, vec!["podcast", "rustconf"]);
+}
+
+#[tokio::test]
+async fn signing_up_with_an_invalid_tag_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions?tags=a%20b", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}